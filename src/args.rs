@@ -5,25 +5,132 @@ use clap::Parser;
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 pub struct Arguments {
+    /// When to color the output
+    #[clap(long, value_enum, default_value_t = ColorWhen::Auto, global = true)]
+    pub color: ColorWhen,
+
+    /// Disable colored output, same as `--color never`
+    #[clap(long, action, global = true)]
+    pub no_color: bool,
+
+    /// How to print errors
+    #[clap(long, value_enum, default_value_t = OutputFormat::Human, global = true)]
+    pub output_format: OutputFormat,
+
+    /// Index arrays from 0 instead of 1, so `A[0]` is the first element. This
+    /// changes the meaning of every indexing expression in the program.
+    #[clap(long, action, global = true)]
+    pub zero_indexed: bool,
+
     /// Subcommand
     #[clap(subcommand)]
     pub sub_command: SubCommand
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorWhen {
+    Auto,
+    Always,
+    Never
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Human,
+    /// A JSON array of error objects, only available with the `json-errors` feature
+    #[cfg(feature = "json-errors")]
+    Json
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenFormat {
+    Text,
+    Json
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchCommand {
+    Execute,
+    VmRun,
+    Check
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VisFormat {
+    Ansi,
+    Plain,
+    Html
+}
+
 #[derive(clap::Subcommand, Debug)]
 #[non_exhaustive]
 pub enum SubCommand {
     Tokenize {
-        file: PathBuf
+        file: PathBuf,
+
+        /// Print the tokens as a JSON array
+        #[clap(long, action)]
+        json: bool
+    },
+    Tokens {
+        file: PathBuf,
+
+        /// Output format for the tokens
+        #[clap(short, long, value_enum, default_value_t = TokenFormat::Text)]
+        format: TokenFormat
     },
     Parse {
         file: PathBuf
     },
+    /// Print the program with its indentation normalized
+    Reformat {
+        file: PathBuf,
+
+        /// Number of spaces to indent each block by
+        #[clap(long, default_value_t = 4)]
+        indent_size: usize,
+
+        /// Overwrite the file instead of printing the result
+        #[clap(short, long, action)]
+        in_place: bool
+    },
     Execute {
-        file: PathBuf
+        file: PathBuf,
+
+        /// Stop with an error after executing this many statements
+        #[clap(long)]
+        max_steps: Option<u64>
+    },
+    /// Run the program again whenever the file changes
+    Watch {
+        file: PathBuf,
+
+        /// How to run the program, `check` only parses, compiles and verifies it
+        #[clap(short, long, value_enum, default_value_t = WatchCommand::Execute)]
+        command: WatchCommand
     },
     Compile {
-        file: PathBuf
+        file: PathBuf,
+
+        /// Write the compiled program to a `.pbc` file next to the source instead of printing it
+        #[clap(long, action)]
+        emit_bytecode: bool
+    },
+    /// Run a program compiled with `compile --emit-bytecode`
+    VMLoad {
+        file: PathBuf,
+
+        /// Function to run
+        #[clap(short, long, default_value = "Test")]
+        entry: String,
+
+        /// Print summary statistics after execution
+        #[clap(long, action)]
+        stats: bool,
+
+        /// Stop with an error after executing this many instructions
+        #[clap(long)]
+        max_steps: Option<u64>
     },
     VMRun {
         file: PathBuf,
@@ -37,6 +144,68 @@ pub enum SubCommand {
 
         /// Shows instructions as they are executed
         #[clap(short, long, action)]
-        instructions: bool
+        instructions: bool,
+
+        /// Print summary statistics after execution
+        #[clap(long, action)]
+        stats: bool,
+
+        /// Number of recent function calls to remember for error reports
+        #[clap(long, default_value_t = 20)]
+        call_history: usize,
+
+        /// Width of the visualization, detected from $COLUMNS by default
+        #[clap(long)]
+        width: Option<usize>,
+
+        /// Print the compiled instructions of a function and exit
+        #[clap(long, value_name = "FUNCTION")]
+        dump_ir: Option<String>,
+
+        /// Print the compiled instructions of every function and exit
+        #[clap(long, action)]
+        dump_ir_all: bool,
+
+        /// Stop with an error after executing this many instructions
+        #[clap(long)]
+        max_steps: Option<u64>,
+
+        /// Run without stopping until instruction N of FUNCTION, given as `FUNCTION:N`, may be repeated
+        #[clap(long, value_name = "FUNCTION:N")]
+        break_at: Vec<String>,
+
+        /// Stop whenever a variable called NAME changes value, may be repeated
+        #[clap(long, value_name = "NAME")]
+        watch_var: Vec<String>
+    },
+    Profile {
+        file: PathBuf,
+
+        /// Function to run
+        #[clap(short, long, default_value = "Test")]
+        entry: String,
+
+        /// Number of times to run the entry function
+        #[clap(short = 'n', long, default_value_t = 1)]
+        iterations: usize,
+
+        /// Write the report as JSON to this file instead of printing a table
+        #[clap(short, long)]
+        output: Option<PathBuf>
+    },
+    Visualize {
+        file: PathBuf,
+
+        /// Function to run
+        #[clap(short, long, default_value = "Test")]
+        entry: String,
+
+        /// Output format, html writes one page per step into the output directory
+        #[clap(short, long, value_enum, default_value_t = VisFormat::Ansi)]
+        format: VisFormat,
+
+        /// Directory to write html output into
+        #[clap(short, long, default_value = "visualization")]
+        output: PathBuf
     },
 }
\ No newline at end of file
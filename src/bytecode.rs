@@ -0,0 +1,610 @@
+//! A compact binary format for compiled functions, so a program can be compiled
+//! once and run later without its source.
+//!
+//! A file starts with the magic bytes `PSBC` and a version byte, followed by
+//! the number of functions and then each function: the name of the source file,
+//! its name, its arguments and their type hints, and its instructions. Integers
+//! are written as unsigned LEB128, strings as a length followed by UTF-8 bytes.
+
+use std::{borrow::Cow, cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
+
+use crate::{compiler::*, error::GenericError, interpreter::Value, tokenizer::{Location, Token, TokenData}};
+
+const MAGIC: &[u8; 4] = b"PSBC";
+const VERSION: u8 = 2;
+
+const BINARY_OPERATIONS: [VMBinaryOperation; 17] = [
+    VMBinaryOperation::Add, VMBinaryOperation::Subtract, VMBinaryOperation::Multiply, VMBinaryOperation::Divide,
+    VMBinaryOperation::FloorDivide, VMBinaryOperation::Modulo, VMBinaryOperation::Power, VMBinaryOperation::LessThan,
+    VMBinaryOperation::GreaterThan, VMBinaryOperation::LessThanEqual, VMBinaryOperation::GreaterThanEqual,
+    VMBinaryOperation::Equality, VMBinaryOperation::Inequality, VMBinaryOperation::LogicalXor,
+    VMBinaryOperation::Concatenate, VMBinaryOperation::LogicalAnd, VMBinaryOperation::LogicalOr
+];
+
+const UNARY_OPERATIONS: [VMUnaryOperation; 2] = [VMUnaryOperation::Negate, VMUnaryOperation::Not];
+
+/// Encode `functions` as a bytecode file
+pub fn write_program(functions: &[VMFunction<'_>]) -> Vec<u8> {
+    let mut writer = Writer { bytes: MAGIC.to_vec() };
+    writer.byte(VERSION);
+    writer.unsigned(functions.len());
+
+    for function in functions {
+        writer.function(function);
+    }
+
+    writer.bytes
+}
+
+/// Decode every function in a bytecode file
+pub fn read_program(data: &[u8]) -> Result<Vec<VMFunction<'static>>, GenericError<'static>> {
+    if !data.starts_with(MAGIC) {
+        return Err(GenericError::tokenless_error("not a bytecode file".to_string()));
+    }
+
+    let mut reader = Reader { data, position: MAGIC.len(), filename: "".into() };
+
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(GenericError::tokenless_error(format!("unsupported bytecode version {}, expected {}", version, VERSION)));
+    }
+
+    let count = reader.unsigned()?;
+    let functions = (0..count).map(|_| reader.function()).collect::<Result<Vec<_>, _>>()?;
+
+    if reader.position != data.len() {
+        return Err(GenericError::tokenless_error(format!("unexpected data after the last function at byte {}", reader.position)));
+    }
+
+    Ok(functions)
+}
+
+impl<'file> VMFunction<'file> {
+    /// Encode this function on its own as a bytecode file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        write_program(std::slice::from_ref(self))
+    }
+}
+
+impl VMFunction<'static> {
+    /// Decode a bytecode file holding exactly one function
+    pub fn from_bytes(data: &[u8]) -> Result<VMFunction<'static>, GenericError<'static>> {
+        let mut functions = read_program(data)?;
+
+        if functions.len() != 1 {
+            return Err(GenericError::tokenless_error(format!("expected one function in bytecode, found {}", functions.len())));
+        }
+
+        Ok(functions.remove(0))
+    }
+}
+
+struct Writer {
+    bytes: Vec<u8>
+}
+
+impl Writer {
+    fn byte(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    fn unsigned(&mut self, value: usize) {
+        let mut value = value as u64;
+
+        loop {
+            let low = (value & 0x7f) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                self.byte(low);
+                break;
+            }
+
+            self.byte(low | 0x80);
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.unsigned(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn function(&mut self, function: &VMFunction<'_>) {
+        self.string(&function.name.location.filename);
+        self.token(&function.name);
+
+        self.unsigned(function.arguments.len());
+        for (i, argument) in function.arguments.iter().enumerate() {
+            self.token(argument);
+
+            match function.arguments_type_hints.get(i).cloned().flatten() {
+                Some(hint) => {
+                    self.byte(1);
+                    self.string(&hint);
+                }
+                None => self.byte(0)
+            }
+        }
+
+        self.unsigned(function.instructions.len());
+        for instruction in &function.instructions {
+            self.unsigned(instruction.associated_line);
+            self.instruction(&instruction.instruction_type);
+        }
+    }
+
+    fn instruction(&mut self, instruction: &VMInstructionType<'_>) {
+        match instruction {
+            VMInstructionType::Return(value) => {
+                self.byte(0);
+                self.value(value);
+            }
+            VMInstructionType::Assign(dest, src) => {
+                self.byte(1);
+                self.value(dest);
+                self.value(src);
+            }
+            VMInstructionType::UnaryOperation(op, dest, value) => {
+                self.byte(2);
+                self.byte(UNARY_OPERATIONS.iter().position(|o| o == op).unwrap() as u8);
+                self.variable(dest);
+                self.value(value);
+            }
+            VMInstructionType::BinaryOperation(op, dest, a, b) => {
+                self.byte(3);
+                self.byte(BINARY_OPERATIONS.iter().position(|o| o == op).unwrap() as u8);
+                self.variable(dest);
+                self.value(a);
+                self.value(b);
+            }
+            VMInstructionType::FunctionCall(function, dest, arguments) => {
+                self.byte(4);
+                self.variable(function);
+                self.variable(dest);
+                self.unsigned(arguments.len());
+                for argument in arguments {
+                    self.value(argument);
+                }
+            }
+            VMInstructionType::Branch(condition, true_branch, false_branch) => {
+                self.byte(5);
+                self.value(condition);
+                self.unsigned(*true_branch);
+                self.unsigned(*false_branch);
+            }
+            VMInstructionType::Goto(target) => {
+                self.byte(6);
+                self.unsigned(*target);
+            }
+            VMInstructionType::LoopStep(dest, step) => {
+                self.byte(7);
+                self.variable(dest);
+                self.value(step);
+            }
+            VMInstructionType::IterationLength(dest, array) => {
+                self.byte(8);
+                self.variable(dest);
+                self.value(array);
+            }
+            VMInstructionType::IterationItem(dest, array, index) => {
+                self.byte(9);
+                self.variable(dest);
+                self.value(array);
+                self.value(index);
+            }
+        }
+    }
+
+    fn value(&mut self, value: &VMValue<'_>) {
+        match value {
+            VMValue::MemberAccess(m, member) => {
+                self.byte(0);
+                self.value(m);
+                self.value(member);
+            }
+            VMValue::Indexing(m, index) => {
+                self.byte(1);
+                self.value(m);
+                self.value(index);
+            }
+            VMValue::Value(v, token) => {
+                self.byte(2);
+                self.constant(v);
+                self.optional_token(token.as_ref());
+            }
+            VMValue::Variable(v) => {
+                self.byte(3);
+                self.variable(v);
+            }
+        }
+    }
+
+    fn variable(&mut self, variable: &VMVariable<'_>) {
+        match variable {
+            VMVariable::Token(token) => {
+                self.byte(0);
+                self.token(token);
+            }
+            VMVariable::Custom(name, token) => {
+                self.byte(1);
+                self.string(name);
+                self.optional_token(token.as_ref());
+            }
+        }
+    }
+
+    fn constant(&mut self, value: &Value) {
+        match value {
+            Value::None => self.byte(0),
+            Value::Boolean(b) => {
+                self.byte(1);
+                self.byte(*b as u8);
+            }
+            Value::Integer(i) => {
+                self.byte(2);
+                self.bytes.extend_from_slice(&i.to_le_bytes());
+            }
+            Value::Number(n) => {
+                self.byte(3);
+                self.bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::String(s) => {
+                self.byte(4);
+                self.string(s);
+            }
+            Value::Array(a) => {
+                self.byte(5);
+                let (elements, members) = &*a.borrow();
+                self.unsigned(elements.len());
+                for element in elements {
+                    self.constant(element);
+                }
+                self.members(members);
+            }
+            Value::Map(m) => {
+                self.byte(6);
+                self.members(&m.borrow());
+            }
+        }
+    }
+
+    fn members(&mut self, members: &HashMap<String, Value>) {
+        self.unsigned(members.len());
+        for (key, value) in members {
+            self.string(key);
+            self.constant(value);
+        }
+    }
+
+    fn optional_token(&mut self, token: Option<&Token<'_>>) {
+        match token {
+            Some(token) => {
+                self.byte(1);
+                self.token(token);
+            }
+            None => self.byte(0)
+        }
+    }
+
+    fn token(&mut self, token: &Token<'_>) {
+        self.byte(match token.data {
+            TokenData::Identifier(_) => 0,
+            TokenData::NumericLiteral(_) => 1,
+            TokenData::StringLiteral(_) => 2,
+            TokenData::Symbol(_) => 3,
+            TokenData::Indentation(_) => 4,
+            TokenData::EndOfFile => 5,
+        });
+        self.string(if token.data == TokenData::EndOfFile { "" } else { token.extract_text() });
+        self.unsigned(token.location.line);
+        self.unsigned(token.location.column);
+        self.unsigned(token.location.index);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+    /// Source file of the function being read, given to the location of each token
+    filename: Arc<str>
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> GenericError<'static> {
+        GenericError::tokenless_error(format!("invalid bytecode at byte {}: {}", self.position, message))
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], GenericError<'static>> {
+        let end = self.position.checked_add(count).filter(|end| *end <= self.data.len())
+            .ok_or_else(|| self.error("unexpected end of data"))?;
+
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, GenericError<'static>> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn unsigned(&mut self) -> Result<usize, GenericError<'static>> {
+        let mut value = 0u64;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.byte()?;
+
+            if shift >= 64 {
+                return Err(self.error("integer is too large"));
+            }
+
+            value |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        usize::try_from(value).map_err(|_| self.error("integer is too large"))
+    }
+
+    fn string(&mut self) -> Result<String, GenericError<'static>> {
+        let length = self.unsigned()?;
+        let bytes = self.bytes(length)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.error("string is not valid UTF-8"))
+    }
+
+    fn function(&mut self) -> Result<VMFunction<'static>, GenericError<'static>> {
+        self.filename = self.string()?.into();
+        let name = self.token()?;
+
+        let argument_count = self.unsigned()?;
+        let mut arguments = Vec::new();
+        let mut type_hints = Vec::new();
+
+        for _ in 0..argument_count {
+            arguments.push(self.token()?);
+            type_hints.push(match self.byte()? {
+                0 => None,
+                1 => Some(self.string()?),
+                tag => return Err(self.error(&format!("unknown type hint tag {}", tag)))
+            });
+        }
+
+        let mut function = VMFunction::new(name, arguments);
+        function.arguments_type_hints = type_hints;
+
+        let instruction_count = self.unsigned()?;
+        for _ in 0..instruction_count {
+            let line = self.unsigned()?;
+            let instruction = self.instruction()?;
+            function.add_instruction_type(line, instruction);
+        }
+
+        Ok(function)
+    }
+
+    fn instruction(&mut self) -> Result<VMInstructionType<'static>, GenericError<'static>> {
+        Ok(match self.byte()? {
+            0 => VMInstructionType::Return(self.value()?),
+            1 => VMInstructionType::Assign(self.value()?, self.value()?),
+            2 => {
+                let index = self.byte()? as usize;
+                let op = *UNARY_OPERATIONS.get(index).ok_or_else(|| self.error(&format!("unknown unary operation {}", index)))?;
+                VMInstructionType::UnaryOperation(op, self.variable()?, self.value()?)
+            }
+            3 => {
+                let index = self.byte()? as usize;
+                let op = *BINARY_OPERATIONS.get(index).ok_or_else(|| self.error(&format!("unknown binary operation {}", index)))?;
+                VMInstructionType::BinaryOperation(op, self.variable()?, self.value()?, self.value()?)
+            }
+            4 => {
+                let function = self.variable()?;
+                let dest = self.variable()?;
+                let count = self.unsigned()?;
+                let arguments = (0..count).map(|_| self.value()).collect::<Result<Vec<_>, _>>()?;
+                VMInstructionType::FunctionCall(function, dest, arguments)
+            }
+            5 => VMInstructionType::Branch(self.value()?, self.unsigned()?, self.unsigned()?),
+            6 => VMInstructionType::Goto(self.unsigned()?),
+            7 => VMInstructionType::LoopStep(self.variable()?, self.value()?),
+            8 => VMInstructionType::IterationLength(self.variable()?, self.value()?),
+            9 => VMInstructionType::IterationItem(self.variable()?, self.value()?, self.value()?),
+            tag => return Err(self.error(&format!("unknown instruction tag {}", tag)))
+        })
+    }
+
+    fn value(&mut self) -> Result<VMValue<'static>, GenericError<'static>> {
+        Ok(match self.byte()? {
+            0 => VMValue::MemberAccess(Box::new(self.value()?), Box::new(self.value()?)),
+            1 => VMValue::Indexing(Box::new(self.value()?), Box::new(self.value()?)),
+            2 => VMValue::Value(self.constant()?, self.optional_token()?),
+            3 => VMValue::Variable(self.variable()?),
+            tag => return Err(self.error(&format!("unknown value tag {}", tag)))
+        })
+    }
+
+    fn variable(&mut self) -> Result<VMVariable<'static>, GenericError<'static>> {
+        Ok(match self.byte()? {
+            0 => VMVariable::Token(self.token()?),
+            1 => VMVariable::Custom(self.string()?, self.optional_token()?),
+            tag => return Err(self.error(&format!("unknown variable tag {}", tag)))
+        })
+    }
+
+    fn constant(&mut self) -> Result<Value, GenericError<'static>> {
+        Ok(match self.byte()? {
+            0 => Value::None,
+            1 => Value::Boolean(self.byte()? != 0),
+            2 => Value::Integer(i64::from_le_bytes(self.bytes(8)?.try_into().unwrap())),
+            3 => Value::Number(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap())),
+            4 => Value::String(self.string()?),
+            5 => {
+                let count = self.unsigned()?;
+                let elements = (0..count).map(|_| self.constant()).collect::<Result<Vec<_>, _>>()?;
+                let members = self.members()?;
+                Value::Array(Rc::new(RefCell::new((elements, members))))
+            }
+            6 => Value::Map(Rc::new(RefCell::new(self.members()?))),
+            tag => return Err(self.error(&format!("unknown constant tag {}", tag)))
+        })
+    }
+
+    fn members(&mut self) -> Result<HashMap<String, Value>, GenericError<'static>> {
+        let count = self.unsigned()?;
+        (0..count).map(|_| Ok((self.string()?, self.constant()?))).collect()
+    }
+
+    fn optional_token(&mut self) -> Result<Option<Token<'static>>, GenericError<'static>> {
+        match self.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.token()?)),
+            tag => Err(self.error(&format!("unknown token tag {}", tag)))
+        }
+    }
+
+    fn token(&mut self) -> Result<Token<'static>, GenericError<'static>> {
+        let kind = self.byte()?;
+        let text = Cow::Owned(self.string()?);
+        let location = Location {
+            filename: self.filename.clone(),
+            line: self.unsigned()?,
+            column: self.unsigned()?,
+            index: self.unsigned()?,
+            file_text: None
+        };
+
+        let data = match kind {
+            0 => TokenData::Identifier(text),
+            1 => TokenData::NumericLiteral(text),
+            2 => TokenData::StringLiteral(text),
+            3 => TokenData::Symbol(text),
+            4 => TokenData::Indentation(text),
+            5 => TokenData::EndOfFile,
+            kind => return Err(self.error(&format!("unknown token kind {}", kind)))
+        };
+
+        Ok(Token::new(location, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile_document, parser::ParserContext, tokenizer::TokenStream};
+
+    use super::*;
+
+    fn variable(name: &str) -> VMVariable<'static> {
+        name.to_string().into()
+    }
+
+    fn constant(value: Value) -> VMValue<'static> {
+        value.into()
+    }
+
+    fn read_error(data: &[u8]) -> String {
+        read_program(data).map(|_| ()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn every_instruction_roundtrips() {
+        let mut function = VMFunction::new(Token::synthetic("F"), vec![Token::synthetic("A"), Token::synthetic("n")]);
+        function.arguments_type_hints = vec![Some("Array".to_string()), None];
+
+        let instructions = vec![
+            VMInstructionType::Assign(variable("x").into(), constant(Value::Integer(-42))),
+            VMInstructionType::Assign(VMValue::Indexing(Box::new(variable("A").into()), Box::new(constant(Value::Integer(1)))), constant(Value::Number(2.5))),
+            VMInstructionType::UnaryOperation(VMUnaryOperation::Negate, variable("y"), variable("x").into()),
+            VMInstructionType::UnaryOperation(VMUnaryOperation::Not, variable("b"), constant(Value::Boolean(true))),
+            VMInstructionType::BinaryOperation(VMBinaryOperation::Concatenate, variable("s"), constant(Value::String("a \"b\"\n".to_string())), variable("x").into()),
+            VMInstructionType::FunctionCall(variable("Print"), variable("t$0"), vec![VMValue::MemberAccess(Box::new(variable("A").into()), Box::new(variable("length").into())), constant(Value::None)]),
+            VMInstructionType::Branch(variable("b").into(), 7, 8),
+            VMInstructionType::Goto(0),
+            VMInstructionType::LoopStep(variable("step$1"), variable("n").into()),
+            VMInstructionType::IterationLength(variable("length$2"), variable("A").into()),
+            VMInstructionType::IterationItem(variable("x"), variable("A").into(), constant(Value::Integer(0))),
+            VMInstructionType::Return(variable("s").into())
+        ];
+
+        for (line, instruction) in instructions.into_iter().enumerate() {
+            function.add_instruction_type(line + 1, instruction);
+        }
+
+        let bytes = function.to_bytes();
+        let decoded = VMFunction::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded.name.extract_text(), "F");
+        assert_eq!(decoded.argument_names(), ["A", "n"]);
+        assert_eq!(decoded.arguments_type_hints, [Some("Array".to_string()), None]);
+        assert_eq!(decoded.instructions.len(), function.instructions.len());
+
+        for (original, decoded) in function.instructions.iter().zip(&decoded.instructions) {
+            assert_eq!(original.associated_line, decoded.associated_line);
+            assert_eq!(original.instruction_type.to_string(), decoded.instruction_type.to_string());
+        }
+
+        assert!(matches!(decoded.instructions[0].instruction_type, VMInstructionType::Assign(_, VMValue::Value(Value::Integer(-42), _))));
+        assert!(matches!(decoded.instructions[1].instruction_type, VMInstructionType::Assign(VMValue::Indexing(..), VMValue::Value(Value::Number(n), _)) if n == 2.5));
+        assert!(matches!(&decoded.instructions[4].instruction_type, VMInstructionType::BinaryOperation(_, _, VMValue::Value(Value::String(s), _), _) if s == "a \"b\"\n"));
+        assert!(matches!(decoded.instructions[6].instruction_type, VMInstructionType::Branch(_, 7, 8)));
+        assert!(matches!(decoded.instructions[7].instruction_type, VMInstructionType::Goto(0)));
+
+        assert_eq!(decoded.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn compiled_program_roundtrips() {
+        let source = "Add(a, b)\n    return a + b\n\nTest()\n    x = Add(1, 2.5)\n    Print(\"x is \" + x)\n";
+        let mut tokens = TokenStream::from_source(source, "add.ps");
+        let (parse_tree, _) = ParserContext::new(&mut tokens).parse_document().unwrap();
+        let functions = compile_document(parse_tree).unwrap();
+
+        let bytes = write_program(&functions);
+        let decoded = read_program(&bytes).unwrap();
+
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(&*decoded[1].name.location.filename, "add.ps");
+        assert_eq!(decoded[1].name.location.line, functions[1].name.location.line);
+        assert_eq!(write_program(&decoded), bytes);
+    }
+
+    #[test]
+    fn truncated_data_is_an_error() {
+        let mut function = VMFunction::new(Token::synthetic("F"), vec![Token::synthetic("a")]);
+        function.add_instruction_type(1, VMInstructionType::Return(constant(Value::String("text".to_string()))));
+        let bytes = function.to_bytes();
+
+        assert!(read_error(&bytes[..4]).contains("unexpected end of data"));
+
+        for length in 5..bytes.len() {
+            assert!(read_program(&bytes[..length]).is_err(), "{} of {} bytes were accepted", length, bytes.len());
+        }
+    }
+
+    #[test]
+    fn garbage_is_an_error() {
+        assert!(read_error(b"").contains("not a bytecode file"));
+        assert!(read_error(b"\x7fELF\x02\x01\x01").contains("not a bytecode file"));
+        assert!(read_error(b"PSBC\x09\x00").contains("unsupported bytecode version 9"));
+
+        // One function named F with no arguments, whose only instruction has tag 10
+        let mut bytes = b"PSBC\x02\x01\x00\x00\x01F\x00\x00\x00\x00\x01\x01".to_vec();
+        bytes.push(10);
+        assert!(read_error(&bytes).contains("unknown instruction tag 10"));
+
+        // An unknown binary operation
+        bytes.pop();
+        bytes.extend([3, 200]);
+        assert!(read_error(&bytes).contains("unknown binary operation 200"));
+
+        // A varint which never ends
+        assert!(read_error(b"PSBC\x02\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\x01").contains("integer is too large"));
+
+        let mut valid = VMFunction::new(Token::synthetic("F"), vec![]).to_bytes();
+        valid.push(0);
+        assert!(read_error(&valid).contains("unexpected data after the last function"));
+    }
+}
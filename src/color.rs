@@ -0,0 +1,90 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// When ANSI color codes should be emitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never
+}
+
+impl ColorMode {
+    fn to_u8(self) -> u8 {
+        match self {
+            ColorMode::Auto => 0,
+            ColorMode::Always => 1,
+            ColorMode::Never => 2,
+        }
+    }
+
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => ColorMode::Always,
+            2 => ColorMode::Never,
+            _ => ColorMode::Auto,
+        }
+    }
+}
+
+/// Stream which colored output is written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr
+}
+
+impl Stream {
+    fn is_terminal(self) -> bool {
+        match self {
+            Stream::Stdout => std::io::stdout().is_terminal(),
+            Stream::Stderr => std::io::stderr().is_terminal(),
+        }
+    }
+}
+
+static COLOR_MODE: AtomicU8 = AtomicU8::new(0);
+static COLORS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Set when colors are emitted, `Auto` is resolved here by honouring `NO_COLOR`
+/// and only coloring output when `stream` is a terminal
+pub fn set_color_mode(mode: ColorMode, stream: Stream) {
+    let enabled = match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => std::env::var_os("NO_COLOR").map(|v| v.is_empty()).unwrap_or(true) && stream.is_terminal()
+    };
+
+    COLOR_MODE.store(mode.to_u8(), Ordering::Relaxed);
+    COLORS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn color_mode() -> ColorMode {
+    ColorMode::from_u8(COLOR_MODE.load(Ordering::Relaxed))
+}
+
+/// Whether color codes are currently emitted, as resolved by the last `set_color_mode`
+pub fn colors_enabled() -> bool {
+    COLORS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// ANSI escape sequence which is only written while colors are enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ansi(pub &'static str);
+
+impl std::fmt::Display for Ansi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if colors_enabled() {
+            write!(f, "{}", self.0)
+        }
+        else {
+            Ok(())
+        }
+    }
+}
+
+pub const CLEAR: Ansi = Ansi("\x1b[0m");
+pub const RED: Ansi = Ansi("\x1b[31m");
+pub const YELLOW: Ansi = Ansi("\x1b[33m");
+pub const CYAN: Ansi = Ansi("\x1b[36m");
+pub const WHITE: Ansi = Ansi("\x1b[37m");
@@ -1,4 +1,6 @@
-use crate::{tokenizer::Token, interpreter::Value, parser::ExpressionType, error::GenericError};
+use std::collections::HashMap;
+
+use crate::{tokenizer::{Token, FileText}, interpreter::{Value, RuntimeError, builtin::*}, parser::ExpressionType, error::GenericError};
 
 #[derive(Debug, Clone)]
 pub struct VMInstruction<'file> {
@@ -17,7 +19,7 @@ pub enum VMValue<'file> {
 #[derive(Debug, Clone)]
 pub enum VMVariable<'file> {
     Token(Token<'file>),
-    Custom(String)
+    Custom(String, Option<Token<'file>>)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -26,22 +28,44 @@ pub enum VMBinaryOperation {
     Subtract,
     Multiply,
     Divide,
+    FloorDivide,
+    Modulo,
+    Power,
     LessThan,
     GreaterThan,
     LessThanEqual,
     GreaterThanEqual,
     Equality,
     Inequality,
+    LogicalXor,
+    Concatenate,
+    /// Logical and of two operands which have both already been evaluated
+    LogicalAnd,
+    /// Logical or of two operands which have both already been evaluated
+    LogicalOr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VMUnaryOperation {
+    Negate,
+    Not,
 }
 
 #[derive(Debug, Clone)]
 pub enum VMInstructionType<'file> {
     Return(VMValue<'file>),
     Assign(VMValue<'file>, VMValue<'file>),
+    UnaryOperation(VMUnaryOperation, VMVariable<'file>, VMValue<'file>),
     BinaryOperation(VMBinaryOperation, VMVariable<'file>, VMValue<'file>, VMValue<'file>),
     FunctionCall(VMVariable<'file>, VMVariable<'file>, Vec<VMValue<'file>>),
     Branch(VMValue<'file>, usize, usize),
     Goto(usize),
+    /// Store a `for ... by` step computed at run time, after checking it is positive
+    LoopStep(VMVariable<'file>, VMValue<'file>),
+    /// Store the number of elements a `for ... in` loop visits
+    IterationLength(VMVariable<'file>, VMValue<'file>),
+    /// Store the element a `for ... in` loop visits at a zero based position
+    IterationItem(VMVariable<'file>, VMValue<'file>, VMValue<'file>),
 }
 
 #[derive(Debug, Clone)]
@@ -49,8 +73,21 @@ pub struct VMFunction<'file> {
     pub instructions: Vec<VMInstruction<'file>>,
     pub name: Token<'file>,
     pub arguments: Vec<Token<'file>>,
+    /// Types given to the arguments by a `// @type` comment, for documentation only
+    pub arguments_type_hints: Vec<Option<String>>,
     next_name: usize,
-    pub raw_file: Option<&'file str>
+    pub raw_file: Option<FileText<'file>>,
+    /// Functions defined inside this one, collected while compiling
+    pub(crate) side_functions: Vec<VMFunction<'file>>,
+    /// Jumps out of each loop being compiled, waiting for their targets
+    pub(crate) loop_fixups: Vec<LoopFixups>
+}
+
+/// Indices of the `Goto` instructions emitted by `break` and `continue` inside a loop
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LoopFixups {
+    pub breaks: Vec<usize>,
+    pub continues: Vec<usize>
 }
 
 impl<'file> std::fmt::Display for VMInstruction<'file> {
@@ -114,6 +151,133 @@ impl<'file> VMValue<'file> {
     }
 }
 
+impl<'file> VMValue<'file> {
+    /// All variables whose values are read when this value is loaded. The
+    /// member name of a member access is not counted as a read.
+    pub fn get_variables_read(&self) -> Vec<&VMVariable<'file>> {
+        match self {
+            VMValue::MemberAccess(m, _) => m.get_variables_read(),
+            VMValue::Indexing(m, i) => {
+                let mut result = m.get_variables_read();
+                result.extend(i.get_variables_read());
+                result
+            },
+            VMValue::Value(_, _) => vec![],
+            VMValue::Variable(v) => vec![v],
+        }
+    }
+
+    /// Variables read when this value is the destination of a store, i.e. the
+    /// array and index of an indexing expression
+    fn get_variables_read_as_destination(&self) -> Vec<&VMVariable<'file>> {
+        match self {
+            VMValue::Variable(_) => vec![],
+            _ => self.get_variables_read()
+        }
+    }
+
+    pub fn for_each_variable_mut(&mut self, mut f: impl FnMut(&mut VMVariable<'file>)) {
+        self.inner_for_each_variable_mut(&mut f);
+    }
+
+    fn inner_for_each_variable_mut(&mut self, f: &mut impl FnMut(&mut VMVariable<'file>)) {
+        match self {
+            VMValue::MemberAccess(m, a) => {
+                m.inner_for_each_variable_mut(f);
+                a.inner_for_each_variable_mut(f);
+            },
+            VMValue::Indexing(m, i) => {
+                m.inner_for_each_variable_mut(f);
+                i.inner_for_each_variable_mut(f);
+            },
+            VMValue::Value(_, _) => {},
+            VMValue::Variable(v) => f(v),
+        }
+    }
+}
+
+impl<'file> VMInstructionType<'file> {
+    pub fn for_each_jump_target_mut(&mut self, mut f: impl FnMut(&mut usize)) {
+        match self {
+            VMInstructionType::Branch(_, true_branch, false_branch) => {
+                f(true_branch);
+                f(false_branch);
+            },
+            VMInstructionType::Goto(target) => f(target),
+            _ => {}
+        }
+    }
+
+    pub fn get_reads(&self) -> Vec<&VMVariable<'file>> {
+        match self {
+            VMInstructionType::Return(v) => v.get_variables_read(),
+            VMInstructionType::Assign(dest, src) => {
+                let mut result = src.get_variables_read();
+                result.extend(dest.get_variables_read_as_destination());
+                result
+            },
+            VMInstructionType::UnaryOperation(_, _, a) |
+            VMInstructionType::LoopStep(_, a) |
+            VMInstructionType::IterationLength(_, a) => a.get_variables_read(),
+            VMInstructionType::BinaryOperation(_, _, a, b) |
+            VMInstructionType::IterationItem(_, a, b) => {
+                let mut result = a.get_variables_read();
+                result.extend(b.get_variables_read());
+                result
+            },
+            VMInstructionType::FunctionCall(_, _, args) => args.iter().flat_map(|a| a.get_variables_read()).collect(),
+            VMInstructionType::Branch(cond, _, _) => cond.get_variables_read(),
+            VMInstructionType::Goto(_) => vec![],
+        }
+    }
+
+    /// Variables which are rebound by this instruction. Stores into an array
+    /// element or member do not rebind the variable holding the array.
+    pub fn get_writes(&self) -> Vec<&VMVariable<'file>> {
+        match self {
+            VMInstructionType::Assign(VMValue::Variable(dest), _) => vec![dest],
+            VMInstructionType::UnaryOperation(_, dest, _) => vec![dest],
+            VMInstructionType::BinaryOperation(_, dest, _, _) => vec![dest],
+            VMInstructionType::FunctionCall(_, dest, _) => vec![dest],
+            VMInstructionType::LoopStep(dest, _) => vec![dest],
+            VMInstructionType::IterationLength(dest, _) => vec![dest],
+            VMInstructionType::IterationItem(dest, _, _) => vec![dest],
+            _ => vec![]
+        }
+    }
+
+    pub fn for_each_variable_mut(&mut self, mut f: impl FnMut(&mut VMVariable<'file>)) {
+        match self {
+            VMInstructionType::Return(v) => v.inner_for_each_variable_mut(&mut f),
+            VMInstructionType::Assign(dest, src) => {
+                dest.inner_for_each_variable_mut(&mut f);
+                src.inner_for_each_variable_mut(&mut f);
+            },
+            VMInstructionType::UnaryOperation(_, dest, a) |
+            VMInstructionType::LoopStep(dest, a) |
+            VMInstructionType::IterationLength(dest, a) => {
+                f(dest);
+                a.inner_for_each_variable_mut(&mut f);
+            },
+            VMInstructionType::BinaryOperation(_, dest, a, b) |
+            VMInstructionType::IterationItem(dest, a, b) => {
+                f(dest);
+                a.inner_for_each_variable_mut(&mut f);
+                b.inner_for_each_variable_mut(&mut f);
+            },
+            VMInstructionType::FunctionCall(name, dest, args) => {
+                f(name);
+                f(dest);
+                for arg in args {
+                    arg.inner_for_each_variable_mut(&mut f);
+                }
+            },
+            VMInstructionType::Branch(cond, _, _) => cond.inner_for_each_variable_mut(&mut f),
+            VMInstructionType::Goto(_) => {},
+        }
+    }
+}
+
 impl<'file> std::fmt::Display for VMVariable<'file> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.extract_text())
@@ -122,7 +286,7 @@ impl<'file> std::fmt::Display for VMVariable<'file> {
 
 impl<'file> std::convert::From<String> for VMVariable<'file> {
     fn from(s: String) -> Self {
-        VMVariable::Custom(s)
+        VMVariable::Custom(s, None)
     }
 }
 
@@ -136,14 +300,52 @@ impl<'file> VMVariable<'file> {
     pub fn extract_text(&self) -> &str {
         match self {
             VMVariable::Token(t) => t.extract_text(),
-            VMVariable::Custom(s) => s
+            VMVariable::Custom(s, _) => s
         }
     }
 
     pub fn get_token(&self) -> Option<Token<'file>> {
         match self {
             VMVariable::Token(t) => Some(t.clone()),
-            VMVariable::Custom(_) => None,
+            VMVariable::Custom(_, t) => t.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for VMUnaryOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VMUnaryOperation::Negate => write!(f, "neg"),
+            VMUnaryOperation::Not => write!(f, "not"),
+        }
+    }
+}
+
+impl VMUnaryOperation {
+    pub fn temp_purpose(&self) -> &'static str {
+        match self {
+            VMUnaryOperation::Negate => "arith",
+            VMUnaryOperation::Not => "not",
+        }
+    }
+
+    /// Compute the result of the operation on `a`
+    pub fn apply<'file>(&self, a: Value) -> Result<Value, RuntimeError<'file>> {
+        match self {
+            VMUnaryOperation::Negate => builtin_negate(vec![a]),
+            VMUnaryOperation::Not => builtin_logical_not(vec![a]),
+        }
+    }
+}
+
+impl std::convert::TryFrom<ExpressionType> for VMUnaryOperation {
+    type Error = ();
+
+    fn try_from(value: ExpressionType) -> Result<Self, Self::Error> {
+        match value {
+            ExpressionType::Negate => Ok(VMUnaryOperation::Negate),
+            ExpressionType::LogicalNot => Ok(VMUnaryOperation::Not),
+            _ => Err(())
         }
     }
 }
@@ -155,12 +357,66 @@ impl std::fmt::Display for VMBinaryOperation {
             VMBinaryOperation::Subtract => write!(f, "sub"),
             VMBinaryOperation::Multiply => write!(f, "mul"),
             VMBinaryOperation::Divide => write!(f, "div"),
+            VMBinaryOperation::FloorDivide => write!(f, "fdiv"),
+            VMBinaryOperation::Modulo => write!(f, "mod"),
+            VMBinaryOperation::Power => write!(f, "pow"),
             VMBinaryOperation::LessThan => write!(f, "lt"),
             VMBinaryOperation::GreaterThan => write!(f, "gt"),
             VMBinaryOperation::LessThanEqual => write!(f, "lte"),
             VMBinaryOperation::GreaterThanEqual => write!(f, "gte"),
             VMBinaryOperation::Equality => write!(f, "equal"),
             VMBinaryOperation::Inequality => write!(f, "nequal"),
+            VMBinaryOperation::LogicalXor => write!(f, "xor"),
+            VMBinaryOperation::Concatenate => write!(f, "concat"),
+            VMBinaryOperation::LogicalAnd => write!(f, "land"),
+            VMBinaryOperation::LogicalOr => write!(f, "lor"),
+        }
+    }
+}
+
+impl VMBinaryOperation {
+    pub fn temp_purpose(&self) -> &'static str {
+        match self {
+            VMBinaryOperation::Add |
+            VMBinaryOperation::Subtract |
+            VMBinaryOperation::Multiply |
+            VMBinaryOperation::Divide |
+            VMBinaryOperation::FloorDivide |
+            VMBinaryOperation::Modulo |
+            VMBinaryOperation::Power => "arith",
+            VMBinaryOperation::LessThan |
+            VMBinaryOperation::GreaterThan |
+            VMBinaryOperation::LessThanEqual |
+            VMBinaryOperation::GreaterThanEqual |
+            VMBinaryOperation::Equality |
+            VMBinaryOperation::Inequality => "cmp",
+            VMBinaryOperation::LogicalXor => "xor",
+            VMBinaryOperation::Concatenate => "concat",
+            VMBinaryOperation::LogicalAnd => "and",
+            VMBinaryOperation::LogicalOr => "or",
+        }
+    }
+
+    /// Compute the result of the operation on `a` and `b`
+    pub fn apply<'file>(&self, a: Value, b: Value) -> Result<Value, RuntimeError<'file>> {
+        match self {
+            VMBinaryOperation::Add => builtin_add(vec![a, b]),
+            VMBinaryOperation::Subtract => builtin_sub(vec![a, b]),
+            VMBinaryOperation::Multiply => builtin_mul(vec![a, b]),
+            VMBinaryOperation::Divide => builtin_div(vec![a, b]),
+            VMBinaryOperation::FloorDivide => builtin_floor_divide(vec![a, b]),
+            VMBinaryOperation::Modulo => builtin_mod(vec![a, b]),
+            VMBinaryOperation::Power => builtin_pow(vec![a, b]),
+            VMBinaryOperation::LessThan => builtin_less_than(vec![a, b]),
+            VMBinaryOperation::GreaterThan => builtin_greater_than(vec![a, b]),
+            VMBinaryOperation::LessThanEqual => builtin_less_than_equal(vec![a, b]),
+            VMBinaryOperation::GreaterThanEqual => builtin_greater_than_equal(vec![a, b]),
+            VMBinaryOperation::Equality => builtin_equality(vec![a, b]),
+            VMBinaryOperation::Inequality => builtin_inequality(vec![a, b]),
+            VMBinaryOperation::LogicalXor => builtin_logical_xor(vec![a, b]),
+            VMBinaryOperation::Concatenate => builtin_concatenate(vec![a, b]),
+            VMBinaryOperation::LogicalAnd => builtin_evaluated_logical_and(vec![a, b]),
+            VMBinaryOperation::LogicalOr => builtin_evaluated_logical_or(vec![a, b]),
         }
     }
 }
@@ -174,12 +430,19 @@ impl std::convert::TryFrom<ExpressionType> for VMBinaryOperation {
             ExpressionType::Subtract => Ok(VMBinaryOperation::Subtract),
             ExpressionType::Multiply => Ok(VMBinaryOperation::Multiply),
             ExpressionType::Divide => Ok(VMBinaryOperation::Divide),
+            ExpressionType::FloorDivide => Ok(VMBinaryOperation::FloorDivide),
+            ExpressionType::Modulo => Ok(VMBinaryOperation::Modulo),
+            ExpressionType::Power => Ok(VMBinaryOperation::Power),
             ExpressionType::LessThan => Ok(VMBinaryOperation::LessThan),
             ExpressionType::GreaterThan => Ok(VMBinaryOperation::GreaterThan),
             ExpressionType::LessThanEqual => Ok(VMBinaryOperation::LessThanEqual),
             ExpressionType::GreaterThanEqual => Ok(VMBinaryOperation::GreaterThanEqual),
             ExpressionType::Equality => Ok(VMBinaryOperation::Equality),
             ExpressionType::Inequality => Ok(VMBinaryOperation::Inequality),
+            ExpressionType::LogicalXor => Ok(VMBinaryOperation::LogicalXor),
+            ExpressionType::Concatenation => Ok(VMBinaryOperation::Concatenate),
+            ExpressionType::LogicalAnd => Ok(VMBinaryOperation::LogicalAnd),
+            ExpressionType::LogicalOr => Ok(VMBinaryOperation::LogicalOr),
             _ => Err(())
         }
     }
@@ -215,6 +478,7 @@ impl<'file> std::fmt::Display for VMInstructionType<'file> {
         match self {
             VMInstructionType::Return(arg) => render_instruction(f, "return", &[arg.to_string()]),
             VMInstructionType::Assign(dest, src) => render_instruction(f, "assign", &[dest.to_string(), src.to_string()]),
+            VMInstructionType::UnaryOperation(op, dest, a) => render_instruction(f, &op.to_string(), &[dest.to_string(), a.to_string()]),
             VMInstructionType::BinaryOperation(op, dest, a, b) => render_instruction(f, &op.to_string(), &[dest.to_string(), a.to_string(), b.to_string()]),
             VMInstructionType::FunctionCall(name, result, args) => {
                 let mut arg_values = vec![name.to_string(), result.to_string()];
@@ -225,7 +489,10 @@ impl<'file> std::fmt::Display for VMInstructionType<'file> {
                 render_instruction(f, "call", &arg_values)
             },
             VMInstructionType::Branch(condition, true_branch, false_branch) => render_instruction(f, "branch", &[condition.to_string(), true_branch.to_string(), false_branch.to_string()]),
-            VMInstructionType::Goto(inst) => render_instruction(f, "goto", &[inst.to_string()])
+            VMInstructionType::Goto(inst) => render_instruction(f, "goto", &[inst.to_string()]),
+            VMInstructionType::LoopStep(dest, step) => render_instruction(f, "step", &[dest.to_string(), step.to_string()]),
+            VMInstructionType::IterationLength(dest, array) => render_instruction(f, "len", &[dest.to_string(), array.to_string()]),
+            VMInstructionType::IterationItem(dest, array, index) => render_instruction(f, "item", &[dest.to_string(), array.to_string(), index.to_string()])
         }
     }
 }
@@ -236,6 +503,9 @@ impl<'file> std::fmt::Display for VMFunction<'file> {
 
         for (i, arg) in self.arguments.iter().enumerate() {
             write!(f, "{}{}", if i != 0 { ", " } else { "" }, arg.extract_text())?;
+            if let Some(Some(hint)) = self.arguments_type_hints.get(i) {
+                write!(f, ": {}", hint)?;
+            }
         }
 
         writeln!(f, ")")?;
@@ -249,16 +519,23 @@ impl<'file> std::fmt::Display for VMFunction<'file> {
 
 impl<'file> VMFunction<'file> {
     pub fn new(name: Token<'file>, arguments: Vec<Token<'file>>) -> Self {
-        let file_data = name.location.file_text;
+        let file_data = name.location.file_text.clone();
         Self {
             instructions: Vec::new(),
+            arguments_type_hints: vec![None; arguments.len()],
             arguments,
             name,
             next_name: 0,
-            raw_file: file_data
+            raw_file: file_data,
+            side_functions: Vec::new(),
+            loop_fixups: Vec::new()
         }
     }
 
+    pub fn argument_names(&self) -> Vec<&str> {
+        self.arguments.iter().map(|a| a.extract_text().as_ref()).collect()
+    }
+
     pub fn add_instruction(&mut self, instruction: VMInstruction<'file>) {
         self.instructions.push(instruction);
     }
@@ -267,12 +544,252 @@ impl<'file> VMFunction<'file> {
         self.add_instruction(VMInstruction::new(associated_line, instruction_type));
     }
 
-    pub fn next_temp_variable(&mut self) -> VMVariable<'file> {
+    pub fn next_temp_variable(&mut self, token: &Token<'file>) -> VMVariable<'file> {
+        self.next_temp_variable_named("temp", token)
+    }
+
+    /// A new temporary for the expression starting at `token`, so errors
+    /// reading or writing it can still point at the source
+    pub fn next_temp_variable_named(&mut self, purpose: &str, token: &Token<'file>) -> VMVariable<'file> {
         self.next_name += 1;
-        format!("temp${}", self.next_name - 1).into()
+        VMVariable::Custom(format!("{}${}", purpose, self.next_name - 1), Some(token.clone()))
+    }
+
+    pub fn rename_temps(&mut self, prefix: &str) {
+        let rename = |variable: &mut VMVariable<'file>| {
+            if let VMVariable::Custom(name, _) = variable {
+                if let Some(index) = name.strip_prefix("temp$") {
+                    *name = format!("{}${}", prefix, index);
+                }
+            }
+        };
+
+        for instruction in &mut self.instructions {
+            instruction.instruction_type.for_each_variable_mut(rename);
+        }
     }
 
     pub fn next_instruction_index(&self) -> usize {
         self.instructions.len()
     }
-}
\ No newline at end of file
+
+    /// Index of the first instruction compiled from the (zero indexed) source line
+    pub fn instruction_at_line(&self, line: usize) -> Option<usize> {
+        self.instructions.iter().position(|i| i.associated_line == line)
+    }
+
+    pub fn instructions_for_line(&self, line: usize) -> Vec<usize> {
+        self.instructions.iter().enumerate().filter(|(_, i)| i.associated_line == line).map(|(index, _)| index).collect()
+    }
+
+    /// Indices of the instructions compiled from each source line, the inverse of `instruction_at_line`
+    pub fn sources_map(&self) -> HashMap<usize, Vec<usize>> {
+        let mut map: HashMap<usize, Vec<usize>> = HashMap::new();
+
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            map.entry(instruction.associated_line).or_default().push(index);
+        }
+
+        map
+    }
+
+    /// Every distinct source line covered by the function's instructions, in order
+    pub fn instruction_lines(&self) -> Vec<usize> {
+        let mut lines: Vec<usize> = self.sources_map().into_keys().collect();
+        lines.sort_unstable();
+        lines
+    }
+
+    /// Check the function for malformed bytecode, returning every problem found
+    pub fn verify(&self) -> Result<(), Vec<GenericError<'file>>> {
+        let name = self.name.extract_text();
+        self.verify_with(|f| if f == name { Some(self.arguments.len()) } else { None })
+    }
+
+    /// Same as `verify`, with `argument_count` giving the number of arguments
+    /// expected by any known function so calls to it can be checked
+    pub fn verify_with(&self, argument_count: impl Fn(&str) -> Option<usize>) -> Result<(), Vec<GenericError<'file>>> {
+        let mut errors = Vec::new();
+        let length = self.instructions.len();
+
+        let check_target = |errors: &mut Vec<GenericError<'file>>, index: usize, target: usize| {
+            if target >= length {
+                errors.push(GenericError::error(self.name.clone(), format!("instruction {} jumps to {}, past the end of the function", index, target)));
+            }
+        };
+
+        for (i, instruction) in self.instructions.iter().enumerate() {
+            match &instruction.instruction_type {
+                VMInstructionType::Branch(_, t, f) => {
+                    check_target(&mut errors, i, *t);
+                    check_target(&mut errors, i, *f);
+                }
+                VMInstructionType::Goto(t) => {
+                    check_target(&mut errors, i, *t);
+                }
+                VMInstructionType::FunctionCall(function, _, arguments) => {
+                    if let Some(expected) = argument_count(function.extract_text()) {
+                        if expected != arguments.len() {
+                            let token = function.get_token().unwrap_or_else(|| self.name.clone());
+                            errors.push(GenericError::error(token, format!("instruction {} calls '{}' with {} arguments, expected {}", i, function.extract_text(), arguments.len(), expected)));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !matches!(self.instructions.last().map(|i| &i.instruction_type), Some(VMInstructionType::Return(_))) {
+            errors.push(GenericError::error(self.name.clone(), "function does not end with a return".to_string()));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(errors)
+        }
+    }
+
+    /// Remove every instruction `i` where `keep[i]` is false and fix up all jump
+    /// targets. Returns the new index of each old instruction, a removed
+    /// instruction maps to the instruction which followed it.
+    pub fn remap_instructions(&mut self, keep: &[bool]) -> Vec<usize> {
+        let mut mapping = Vec::with_capacity(self.instructions.len() + 1);
+        let mut next = 0;
+
+        for i in 0..self.instructions.len() {
+            mapping.push(next);
+            if keep.get(i).copied().unwrap_or(true) {
+                next += 1;
+            }
+        }
+
+        // Jumps to the end of the function stay at the end
+        mapping.push(next);
+
+        let instructions = std::mem::take(&mut self.instructions);
+        for (i, mut instruction) in instructions.into_iter().enumerate() {
+            if keep.get(i).copied().unwrap_or(true) {
+                instruction.instruction_type.for_each_jump_target_mut(|target| *target = mapping[(*target).min(mapping.len() - 1)]);
+                self.instructions.push(instruction);
+            }
+        }
+
+        mapping.pop();
+        mapping
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> VMVariable<'static> {
+        name.to_string().into()
+    }
+
+    fn value(name: &str) -> VMValue<'static> {
+        var(name).into()
+    }
+
+    fn names<'a>(variables: Vec<&'a VMVariable<'_>>) -> Vec<&'a str> {
+        variables.into_iter().map(|v| v.extract_text()).collect()
+    }
+
+    #[test]
+    fn variables_read_by_values() {
+        assert!(VMValue::from(Value::Integer(1)).get_variables_read().is_empty());
+        assert_eq!(names(value("a").get_variables_read()), ["a"]);
+
+        let indexing = VMValue::Indexing(Box::new(value("A")), Box::new(value("i")));
+        assert_eq!(names(indexing.get_variables_read()), ["A", "i"]);
+
+        let member = VMValue::MemberAccess(Box::new(indexing), Box::new(value("length")));
+        assert_eq!(names(member.get_variables_read()), ["A", "i"]);
+    }
+
+    #[test]
+    fn reads_and_writes_of_each_instruction() {
+        let cases: Vec<(VMInstructionType, Vec<&str>, Vec<&str>)> = vec![
+            (VMInstructionType::Return(value("a")), vec!["a"], vec![]),
+            (VMInstructionType::Assign(value("x"), value("a")), vec!["a"], vec!["x"]),
+            (VMInstructionType::Assign(VMValue::Indexing(Box::new(value("A")), Box::new(value("i"))), value("a")), vec!["a", "A", "i"], vec![]),
+            (VMInstructionType::UnaryOperation(VMUnaryOperation::Negate, var("x"), value("a")), vec!["a"], vec!["x"]),
+            (VMInstructionType::BinaryOperation(VMBinaryOperation::Add, var("x"), value("a"), value("b")), vec!["a", "b"], vec!["x"]),
+            (VMInstructionType::FunctionCall(var("F"), var("x"), vec![value("a"), Value::Integer(1).into(), value("b")]), vec!["a", "b"], vec!["x"]),
+            (VMInstructionType::Branch(value("c"), 1, 2), vec!["c"], vec![]),
+            (VMInstructionType::Goto(3), vec![], vec![]),
+            (VMInstructionType::LoopStep(var("s"), value("a")), vec!["a"], vec!["s"]),
+            (VMInstructionType::IterationLength(var("n"), value("A")), vec!["A"], vec!["n"]),
+            (VMInstructionType::IterationItem(var("x"), value("A"), value("i")), vec!["A", "i"], vec!["x"]),
+        ];
+
+        for (instruction, reads, writes) in cases {
+            assert_eq!(names(instruction.get_reads()), reads, "reads of {}", instruction);
+            assert_eq!(names(instruction.get_writes()), writes, "writes of {}", instruction);
+        }
+    }
+
+    #[test]
+    fn removing_an_instruction_moves_jumps() {
+        let mut function = VMFunction::new(Token::synthetic("F"), vec![]);
+        function.instructions = vec![
+            VMInstructionType::Goto(2),
+            VMInstructionType::Goto(4),
+            VMInstructionType::Assign(value("x"), value("a")),
+            VMInstructionType::Branch(value("c"), 3, 5),
+            VMInstructionType::Return(value("x")),
+        ].into_iter().map(|instruction| VMInstruction::new(1, instruction)).collect();
+
+        let mapping = function.remap_instructions(&[true, true, false, true, true]);
+
+        assert_eq!(mapping, [0, 1, 2, 2, 3]);
+        assert_eq!(function.instructions.len(), 4);
+
+        // The jump to the removed instruction now lands on the one which followed it
+        assert!(matches!(function.instructions[0].instruction_type, VMInstructionType::Goto(2)));
+        assert!(matches!(function.instructions[1].instruction_type, VMInstructionType::Goto(3)));
+        assert!(matches!(function.instructions[2].instruction_type, VMInstructionType::Branch(_, 2, 4)));
+        assert!(matches!(function.instructions[3].instruction_type, VMInstructionType::Return(_)));
+    }
+
+    /// A function whose instructions came from the given source lines
+    fn function_on_lines(lines: &[usize]) -> VMFunction<'static> {
+        let mut function = VMFunction::new(Token::synthetic("F"), vec![]);
+        for line in lines {
+            function.add_instruction_type(*line, VMInstructionType::Goto(0));
+        }
+
+        function
+    }
+
+    #[test]
+    fn instructions_of_a_line() {
+        let function = function_on_lines(&[1, 1, 2, 4, 2, 4]);
+
+        assert_eq!(function.instruction_at_line(1), Some(0));
+        assert_eq!(function.instruction_at_line(2), Some(2));
+        assert_eq!(function.instruction_at_line(3), None);
+        assert_eq!(function.instruction_at_line(4), Some(3));
+
+        assert_eq!(function.instructions_for_line(1), [0, 1]);
+        assert_eq!(function.instructions_for_line(2), [2, 4]);
+        assert!(function.instructions_for_line(3).is_empty());
+        assert_eq!(function.instructions_for_line(4), [3, 5]);
+    }
+
+    #[test]
+    fn instructions_grouped_by_line() {
+        let function = function_on_lines(&[3, 3, 1, 3, 7, 1]);
+
+        let map = function.sources_map();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map[&1], [2, 5]);
+        assert_eq!(map[&3], [0, 1, 3]);
+        assert_eq!(map[&7], [4]);
+
+        assert_eq!(function.instruction_lines(), [1, 3, 7]);
+        assert!(function_on_lines(&[]).instruction_lines().is_empty());
+    }
+}
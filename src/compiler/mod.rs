@@ -2,4 +2,6 @@ pub mod instructions;
 pub use instructions::*;
 
 pub mod parsetree;
-pub use parsetree::*;
\ No newline at end of file
+pub use parsetree::*;
+
+pub mod passes;
\ No newline at end of file
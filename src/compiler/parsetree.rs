@@ -1,27 +1,59 @@
 use std::convert::TryInto;
 
-use crate::{parser::{ParseTreeNode, ExpressionType}, VMFunction, error::GenericError, VMInstructionType, VMValue, interpreter::Value, VMVariable, VMBinaryOperation};
+use crate::{parser::{ParseTreeNode, ExpressionType}, VMFunction, error::GenericError, VMInstructionType, VMValue, interpreter::{Value, builtin::loop_step}, VMVariable, VMBinaryOperation, VMUnaryOperation, LoopFixups};
 
-pub fn compile_function(parsetree: ParseTreeNode<'_>) -> Result<VMFunction<'_>, GenericError<'_>> {
-    if let ParseTreeNode::Function { name, arguments, block } = parsetree {
+/// A compiled function along with every function defined inside it
+#[derive(Debug, Clone)]
+pub struct CompileResult<'file> {
+    pub main: VMFunction<'file>,
+    pub nested: Vec<VMFunction<'file>>
+}
+
+impl<'file> CompileResult<'file> {
+    /// Every compiled function, starting with the outermost
+    pub fn into_functions(self) -> Vec<VMFunction<'file>> {
+        std::iter::once(self.main).chain(self.nested).collect()
+    }
+}
+
+pub fn compile_function(parsetree: ParseTreeNode<'_>) -> Result<CompileResult<'_>, GenericError<'_>> {
+    if let ParseTreeNode::Function { name, arguments, type_hints, block } = parsetree {
         let l = name.location.line;
         let mut result = VMFunction::new(name, arguments);
+        result.arguments_type_hints = type_hints;
 
         result.compile(&block)?;
 
         result.add_instruction_type(l, VMInstructionType::Return(Value::None.into()));
 
-        Ok(result)
+        result.fold_constants();
+        result.eliminate_dead_code();
+        result.peephole_optimize();
+
+        let nested = std::mem::take(&mut result.side_functions);
+
+        Ok(CompileResult { main: result, nested })
     }
     else {
         unimplemented!()
     }
 }
 
+/// Compile every function in a parsed document, including nested functions
+pub fn compile_document(parse_tree: Vec<ParseTreeNode<'_>>) -> Result<Vec<VMFunction<'_>>, GenericError<'_>> {
+    let mut functions = Vec::new();
+
+    for node in parse_tree {
+        functions.append(&mut compile_function(node)?.into_functions());
+    }
+
+    Ok(functions)
+}
+
 impl<'file> VMFunction<'file> {
     pub fn compile(&mut self, parsetree: &ParseTreeNode<'file>) -> Result<Option<VMValue<'file>>, GenericError<'file>> {
         match parsetree {
-            ParseTreeNode::Block { statements } => {
+            ParseTreeNode::Block { statements, .. } => {
                 for statement in statements {
                     self.compile(statement)?;
                 }
@@ -31,8 +63,22 @@ impl<'file> VMFunction<'file> {
             ParseTreeNode::NumericValue { token, value } => {
                 Ok(Some((Value::Number(*value), token.clone()).into()))
             }
+            ParseTreeNode::IntegerValue { token, value } => {
+                Ok(Some((Value::Integer(*value), token.clone()).into()))
+            }
+            ParseTreeNode::StringValue { token, value } => {
+                Ok(Some((Value::String(value.clone()), token.clone()).into()))
+            }
             ParseTreeNode::IdentifierValue { token } => {
-                Ok(Some(token.clone().into()))
+                if token.extract_text() == "True" {
+                    Ok(Some((Value::Boolean(true), token.clone()).into()))
+                }
+                else if token.extract_text() == "False" {
+                    Ok(Some((Value::Boolean(false), token.clone()).into()))
+                }
+                else {
+                    Ok(Some(token.clone().into()))
+                }
             }
             ParseTreeNode::ReturnStatement { token, expression } => {
                 if let Some(expr) = expression {
@@ -66,13 +112,24 @@ impl<'file> VMFunction<'file> {
 
                 Ok(child_b)
             }
+            ParseTreeNode::Expression { expression_type, symbols, children } if expression_type.compound_operation().is_some() => {
+                let target = self.compile(&children[0])?.unwrap();
+                let value = self.compile(&children[1])?.unwrap();
+
+                let bin_op = VMBinaryOperation::try_from(expression_type.compound_operation().unwrap()).unwrap();
+                let v = self.next_temp_variable_named(bin_op.temp_purpose(), &symbols[0]);
+                self.add_instruction_type(symbols[0].location.line, VMInstructionType::BinaryOperation(bin_op, v.clone(), target.clone(), value));
+                self.add_instruction_type(symbols[0].location.line, VMInstructionType::Assign(target, v.clone().into()));
+
+                Ok(Some(v.into()))
+            }
             ParseTreeNode::Expression { expression_type: ExpressionType::FunctionCall, symbols, children } => {
                 let values = children.iter().map(|c| self.compile(c)).collect::<Result<Vec<_>, _>>()?;
                 
                 let func_name: VMVariable<'file> = values[0].clone().unwrap().try_into()?;
 
                 let args = values[1..].iter().map(|c| c.clone().unwrap()).collect();
-                let v = self.next_temp_variable();
+                let v = self.next_temp_variable_named("call", &symbols[0]);
 
                 self.add_instruction_type(symbols[0].location.line, VMInstructionType::FunctionCall(func_name, v.clone(), args));
 
@@ -90,8 +147,22 @@ impl<'file> VMFunction<'file> {
 
                 Ok(Some(VMValue::Indexing(Box::new(value), Box::new(key))))
             }
+            // Constant booleans can always be read and combined, so both sides can be
+            // evaluated up front. A variable may be unset or not a boolean, and must
+            // not be read when the left side already decides the result.
+            ParseTreeNode::Expression { expression_type: ExpressionType::LogicalAnd | ExpressionType::LogicalOr, symbols, children }
+                if children.iter().all(|c| matches!(c, ParseTreeNode::IdentifierValue { token } if matches!(token.extract_text().as_ref(), "True" | "False"))) => {
+                let a = self.compile(&children[0])?.unwrap();
+                let b = self.compile(&children[1])?.unwrap();
+
+                let bin_op = if let ParseTreeNode::Expression { expression_type: ExpressionType::LogicalAnd, .. } = parsetree { VMBinaryOperation::LogicalAnd } else { VMBinaryOperation::LogicalOr };
+                let v = self.next_temp_variable_named(bin_op.temp_purpose(), &symbols[0]);
+                self.add_instruction_type(symbols[0].location.line, VMInstructionType::BinaryOperation(bin_op, v.clone(), a, b));
+
+                Ok(Some(v.into()))
+            }
             ParseTreeNode::Expression { expression_type: ExpressionType::LogicalAnd, symbols, children } => {
-                let v = self.next_temp_variable();
+                let v = self.next_temp_variable_named("and", &symbols[0]);
                 let a = self.compile(&children[0])?.unwrap();
 
                 let first_compare = self.next_instruction_index();
@@ -119,7 +190,7 @@ impl<'file> VMFunction<'file> {
                 Ok(Some(v.into()))
             }
             ParseTreeNode::Expression { expression_type: ExpressionType::LogicalOr, symbols, children } => {
-                let v = self.next_temp_variable();
+                let v = self.next_temp_variable_named("or", &symbols[0]);
                 let a = self.compile(&children[0])?.unwrap();
 
                 let first_compare = self.next_instruction_index();
@@ -146,15 +217,53 @@ impl<'file> VMFunction<'file> {
 
                 Ok(Some(v.into()))
             }
+            ParseTreeNode::Expression { expression_type: ExpressionType::Ternary, symbols, children } => {
+                let v = self.next_temp_variable_named("if", &symbols[0]);
+                let cond = self.compile(&children[0])?.unwrap();
+
+                let first_compare = self.next_instruction_index();
+                self.add_instruction_type(symbols[0].location.line, VMInstructionType::Branch(cond, first_compare + 1, 0));
+
+                let a = self.compile(&children[1])?.unwrap();
+
+                self.add_instruction_type(symbols[1].location.line, VMInstructionType::Assign(v.clone().into(), a));
+
+                let goto_end = self.next_instruction_index();
+                self.add_instruction_type(symbols[1].location.line, VMInstructionType::Goto(0));
+
+                let else_start = self.next_instruction_index();
+                let b = self.compile(&children[2])?.unwrap();
+
+                self.add_instruction_type(symbols[2].location.line, VMInstructionType::Assign(v.clone().into(), b));
+
+                let after = self.next_instruction_index();
+                if let VMInstructionType::Goto(inst) = &mut self.instructions[goto_end].instruction_type {
+                    *inst = after;
+                } else {unimplemented!()}
+
+                if let VMInstructionType::Branch(_, _, inst) = &mut self.instructions[first_compare].instruction_type {
+                    *inst = else_start;
+                } else {unimplemented!()}
+
+                Ok(Some(v.into()))
+            }
             ParseTreeNode::Expression { expression_type, symbols, children } => 
             {
                 let values = children.iter().map(|c| self.compile(c)).collect::<Result<Vec<_>, _>>()?;
 
-                if let Ok(bin_op) = (*expression_type).try_into() {
+                if let Ok(unary_op) = VMUnaryOperation::try_from(*expression_type) {
+                    let a = values[0].as_ref().unwrap().clone();
+
+                    let v = self.next_temp_variable_named(unary_op.temp_purpose(), &symbols[0]);
+                    self.add_instruction_type(symbols[0].location.line, VMInstructionType::UnaryOperation(unary_op, v.clone(), a));
+
+                    Ok(Some(v.into()))
+                }
+                else if let Ok(bin_op) = VMBinaryOperation::try_from(*expression_type) {
                     let a = values[0].as_ref().unwrap().clone();
                     let b = values[1].as_ref().unwrap().clone();
 
-                    let v = self.next_temp_variable();
+                    let v = self.next_temp_variable_named(bin_op.temp_purpose(), &symbols[0]);
                     self.add_instruction_type(symbols[0].location.line, VMInstructionType::BinaryOperation(bin_op, v.clone(), a, b));
                     
                     Ok(Some(v.into()))
@@ -205,9 +314,65 @@ impl<'file> VMFunction<'file> {
 
                 Ok(None)
             }
-            ParseTreeNode::ForLoop { token, loop_variable, bound0, bound1, reverse, block } => {
+            ParseTreeNode::SwitchStatement { token, value, cases, default } => {
+                let mut skip_to_end: Vec<usize> = Vec::new();
+
+                // The value is evaluated once, before any of the cases
+                let v = self.next_temp_variable_named("case", token);
+                let value = self.compile(value)?.unwrap();
+                self.add_instruction_type(token.location.line, VMInstructionType::Assign(v.clone().into(), value));
+
+                for (case, block) in cases {
+                    let case_token = case.get_token().clone();
+                    let line = case_token.location.line;
+                    let case = self.compile(case)?.unwrap();
+
+                    let matches = self.next_temp_variable_named(VMBinaryOperation::Equality.temp_purpose(), &case_token);
+                    self.add_instruction_type(line, VMInstructionType::BinaryOperation(VMBinaryOperation::Equality, matches.clone(), v.clone().into(), case));
+
+                    let prev = self.next_instruction_index();
+                    self.add_instruction_type(line, VMInstructionType::Branch(matches.into(), prev + 1, 0));
+
+                    self.compile(block)?;
+
+                    skip_to_end.push(self.next_instruction_index());
+                    self.add_instruction_type(line, VMInstructionType::Goto(0));
+
+                    let next = self.next_instruction_index();
+                    if let VMInstructionType::Branch(_, _, branch) = &mut self.instructions[prev].instruction_type {
+                        *branch = next;
+                    } else {unimplemented!()}
+                }
+
+                if let Some(default) = default {
+                    self.compile(default)?;
+                }
+
+                let last = self.next_instruction_index();
+                self.fixup_gotos(&skip_to_end, last);
+
+                Ok(None)
+            }
+            ParseTreeNode::ForLoop { token, loop_variable, bound0, bound1, step, reverse, block } => {
                 let b0 = self.compile(bound0)?.unwrap();
                 let b1 = self.compile(bound1)?.unwrap();
+                let step_value = if let Some(step) = step {
+                    let s = self.compile(step)?.unwrap();
+
+                    // Constant steps are checked here, anything else is checked once before the loop runs
+                    if let VMValue::Value(v, _) = &s {
+                        loop_step(v).map_err(|e| e.finish_span(step))?;
+                        s
+                    }
+                    else {
+                        let checked = self.next_temp_variable_named("step", step.first_token());
+                        self.add_instruction_type(token.location.line, VMInstructionType::LoopStep(checked.clone(), s));
+                        checked.into()
+                    }
+                }
+                else {
+                    Value::Integer(1).into()
+                };
 
                 let line = token.location.line;
                 let loop_variable: VMVariable<'file> = loop_variable.clone().into();
@@ -218,14 +383,84 @@ impl<'file> VMFunction<'file> {
                 self.add_instruction_type(line, VMInstructionType::Assign(loop_variable.clone().into(), b0));
 
                 let start = self.next_instruction_index();
-                let v = self.next_temp_variable();
+                let v = self.next_temp_variable_named("loop", token);
                 self.add_instruction_type(line, VMInstructionType::BinaryOperation(comparison, v.clone(), loop_variable.clone().into(), b1));
                 let compare_line = self.next_instruction_index();
                 self.next_instruction_index();self.add_instruction_type(line, VMInstructionType::Branch(v.into(), compare_line + 1, 0));
 
+                self.loop_fixups.push(LoopFixups::default());
+                self.compile(block)?;
+                let fixups = self.loop_fixups.pop().unwrap();
+
+                let step = self.next_instruction_index();
+                self.add_instruction_type(line, VMInstructionType::BinaryOperation(direction, loop_variable.clone(), loop_variable.clone().into(), step_value));
+                self.add_instruction_type(line, VMInstructionType::Goto(start));
+
+                let after = self.next_instruction_index();
+                if let VMInstructionType::Branch(_, _, inst) = &mut self.instructions[compare_line].instruction_type {
+                    *inst = after;
+                } else {unimplemented!()}
+
+                self.fixup_gotos(&fixups.continues, step);
+                self.fixup_gotos(&fixups.breaks, after);
+
+                Ok(None)
+            }
+            ParseTreeNode::BreakStatement { token } | ParseTreeNode::ContinueStatement { token } => {
+                let goto = self.next_instruction_index();
+
+                if let Some(fixups) = self.loop_fixups.last_mut() {
+                    if let ParseTreeNode::BreakStatement { .. } = parsetree {
+                        fixups.breaks.push(goto);
+                    }
+                    else {
+                        fixups.continues.push(goto);
+                    }
+                }
+                else {
+                    return Err(GenericError::error(token.clone(), format!("`{}` outside of a loop", token.extract_text())));
+                }
+
+                self.add_instruction_type(token.location.line, VMInstructionType::Goto(0));
+
+                Ok(None)
+            }
+            // Nested functions are compiled on their own, and are visible everywhere like any other function
+            ParseTreeNode::Function { .. } => {
+                let mut result = compile_function(parsetree.clone())?;
+
+                self.side_functions.push(result.main);
+                self.side_functions.append(&mut result.nested);
+
+                Ok(None)
+            }
+            ParseTreeNode::ForEachLoop { token, item_variable, array_expression, block } => {
+                let a = self.compile(array_expression)?.unwrap();
+
+                let line = token.location.line;
+                let item_variable: VMVariable<'file> = item_variable.clone().into();
+
+                let array = self.next_temp_variable_named("each", array_expression.first_token());
+                let index = self.next_temp_variable_named("index", token);
+                self.add_instruction_type(line, VMInstructionType::Assign(array.clone().into(), a));
+                self.add_instruction_type(line, VMInstructionType::Assign(index.clone().into(), Value::Integer(0).into()));
+
+                // The length is read again each time around, so the block may add to the array
+                let start = self.next_instruction_index();
+                let length = self.next_temp_variable_named("length", array_expression.first_token());
+                let v = self.next_temp_variable_named("loop", token);
+                self.add_instruction_type(line, VMInstructionType::IterationLength(length.clone(), array.clone().into()));
+                self.add_instruction_type(line, VMInstructionType::BinaryOperation(VMBinaryOperation::LessThan, v.clone(), index.clone().into(), length.into()));
+                let compare_line = self.next_instruction_index();
+                self.add_instruction_type(line, VMInstructionType::Branch(v.into(), compare_line + 1, 0));
+                self.add_instruction_type(line, VMInstructionType::IterationItem(item_variable, array.into(), index.clone().into()));
+
+                self.loop_fixups.push(LoopFixups::default());
                 self.compile(block)?;
+                let fixups = self.loop_fixups.pop().unwrap();
 
-                self.add_instruction_type(line, VMInstructionType::BinaryOperation(direction, loop_variable.clone(), loop_variable.clone().into(), Value::Number(1.0).into()));
+                let step = self.next_instruction_index();
+                self.add_instruction_type(line, VMInstructionType::BinaryOperation(VMBinaryOperation::Add, index.clone(), index.into(), Value::Integer(1).into()));
                 self.add_instruction_type(line, VMInstructionType::Goto(start));
 
                 let after = self.next_instruction_index();
@@ -233,6 +468,9 @@ impl<'file> VMFunction<'file> {
                     *inst = after;
                 } else {unimplemented!()}
 
+                self.fixup_gotos(&fixups.continues, step);
+                self.fixup_gotos(&fixups.breaks, after);
+
                 Ok(None)
             }
             ParseTreeNode::WhileLoop { token, condition, block } => {
@@ -243,7 +481,9 @@ impl<'file> VMFunction<'file> {
                 let compare_line = self.next_instruction_index();
                 self.add_instruction_type(line, VMInstructionType::Branch(c, compare_line + 1, 0));
 
+                self.loop_fixups.push(LoopFixups::default());
                 self.compile(block)?;
+                let fixups = self.loop_fixups.pop().unwrap();
 
                 self.add_instruction_type(line, VMInstructionType::Goto(start));
                 let after = self.next_instruction_index();
@@ -251,12 +491,93 @@ impl<'file> VMFunction<'file> {
                     *inst = after;
                 } else {unimplemented!()}
 
+                self.fixup_gotos(&fixups.continues, start);
+                self.fixup_gotos(&fixups.breaks, after);
+
+                Ok(None)
+            }
+            ParseTreeNode::RepeatLoop { block, condition, .. } => {
+                let start = self.next_instruction_index();
+
+                self.loop_fixups.push(LoopFixups::default());
+                self.compile(block)?;
+                let fixups = self.loop_fixups.pop().unwrap();
+
+                let check = self.next_instruction_index();
+                let c = self.compile(condition)?.unwrap();
+                let compare_line = self.next_instruction_index();
+                self.add_instruction_type(condition.get_token().location.line, VMInstructionType::Branch(c, compare_line + 1, start));
+
+                let after = self.next_instruction_index();
+                self.fixup_gotos(&fixups.continues, check);
+                self.fixup_gotos(&fixups.breaks, after);
+
                 Ok(None)
             }
-            _ => {
-                dbg!(parsetree);
-                todo!()
+        }
+    }
+
+    /// Point each of the `Goto` instructions at the given indices to `target`
+    fn fixup_gotos(&mut self, gotos: &[usize], target: usize) {
+        for &i in gotos {
+            if let VMInstructionType::Goto(v) = &mut self.instructions[i].instruction_type {
+                *v = target;
+            }
+            else {
+                unimplemented!()
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::{compile_document, parser::ParserContext, tokenizer::TokenStream};
+
+    use super::*;
+
+    #[test]
+    fn temporaries_keep_their_tokens() {
+        let source = "F(a, b, A)\n    x = a * b + 1\n    for y in A\n        x = x - y\n    return x > 0 and b\n";
+        let mut tokens = TokenStream::from_source(source, "test.ps");
+        let (parse_tree, _) = ParserContext::new(&mut tokens).parse_document().unwrap();
+
+        let functions = compile_document(parse_tree).unwrap();
+        let mut temporaries = 0;
+
+        for instruction in &functions[0].instructions {
+            for variable in instruction.instruction_type.get_writes() {
+                if let VMVariable::Custom(name, token) = variable {
+                    let token = token.as_ref().unwrap_or_else(|| panic!("{} has no token", name));
+                    assert_eq!(token.location.line, instruction.associated_line, "{} is on the wrong line", name);
+                    temporaries += 1;
+                }
             }
         }
+
+        assert!(temporaries > 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn argument_names_of_compiled_functions() {
+        let mut tokens = TokenStream::from_source("Partition(A, p, r)\n    return p\n\nTest()\n    return 1\n", "test.ps");
+        let (parse_tree, _) = ParserContext::new(&mut tokens).parse_document().unwrap();
+
+        let functions = compile_document(parse_tree).unwrap();
+        assert_eq!(functions[0].argument_names(), ["A", "p", "r"]);
+        assert!(functions[1].argument_names().is_empty());
+    }
+
+    #[test]
+    fn loops_compile_without_calls() {
+        let mut tokens = TokenStream::from_source("F(A, k)\n    for i = 1 to 9 by k\n        x = i\n    for y in A\n        x = y\n    return x\n", "test.ps");
+        let (parse_tree, _) = ParserContext::new(&mut tokens).parse_document().unwrap();
+
+        let functions = compile_document(parse_tree).unwrap();
+        let instructions = functions[0].instructions.iter().map(|i| &i.instruction_type).collect::<Vec<_>>();
+
+        assert!(!instructions.iter().any(|i| matches!(i, VMInstructionType::FunctionCall(..))));
+        assert_eq!(instructions.iter().filter(|i| matches!(i, VMInstructionType::LoopStep(..))).count(), 1);
+        assert_eq!(instructions.iter().filter(|i| matches!(i, VMInstructionType::IterationLength(..))).count(), 1);
+        assert_eq!(instructions.iter().filter(|i| matches!(i, VMInstructionType::IterationItem(..))).count(), 1);
+    }
+}
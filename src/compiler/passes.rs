@@ -0,0 +1,318 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{VMFunction, VMInstructionType, VMValue, VMVariable};
+
+/// Whether `variable` is a temporary introduced by the compiler, rather than a
+/// variable named in the source
+fn is_temporary(variable: &VMVariable) -> bool {
+    matches!(variable, VMVariable::Custom(name, _) if name.contains('$'))
+}
+
+/// Replace every read of a temporary with a known constant value by the value
+fn substitute_constants<'file>(value: &mut VMValue<'file>, constants: &HashMap<String, VMValue<'file>>) {
+    match value {
+        VMValue::MemberAccess(m, _) => substitute_constants(m, constants),
+        VMValue::Indexing(m, i) => {
+            substitute_constants(m, constants);
+            substitute_constants(i, constants);
+        }
+        VMValue::Value(_, _) => {}
+        VMValue::Variable(v) => {
+            if let Some(constant) = constants.get(v.extract_text()) {
+                *value = constant.clone();
+            }
+        }
+    }
+}
+
+impl<'file> VMFunction<'file> {
+    /// Evaluate operations whose operands are all known while compiling. A
+    /// temporary which is only ever assigned a constant is replaced by that
+    /// constant wherever it is read, so whole expressions such as `2 + 3 * 4`
+    /// fold down to a single value. Operations which would fail, such as adding
+    /// a number to a string, are left for the runtime to report.
+    pub fn fold_constants(&mut self) {
+        let mut writes: HashMap<String, usize> = HashMap::new();
+
+        for instruction in &self.instructions {
+            for variable in instruction.instruction_type.get_writes() {
+                if is_temporary(variable) {
+                    *writes.entry(variable.extract_text().to_string()).or_default() += 1;
+                }
+            }
+        }
+
+        let mut constants = HashMap::new();
+
+        for instruction in &mut self.instructions {
+            match &mut instruction.instruction_type {
+                VMInstructionType::Return(v) |
+                VMInstructionType::UnaryOperation(_, _, v) |
+                VMInstructionType::LoopStep(_, v) |
+                VMInstructionType::IterationLength(_, v) |
+                VMInstructionType::Branch(v, _, _) => substitute_constants(v, &constants),
+                VMInstructionType::Assign(dest, src) => {
+                    if !matches!(dest, VMValue::Variable(_)) {
+                        substitute_constants(dest, &constants);
+                    }
+                    substitute_constants(src, &constants);
+                }
+                VMInstructionType::BinaryOperation(_, _, a, b) |
+                VMInstructionType::IterationItem(_, a, b) => {
+                    substitute_constants(a, &constants);
+                    substitute_constants(b, &constants);
+                }
+                VMInstructionType::FunctionCall(_, _, arguments) => {
+                    for argument in arguments {
+                        substitute_constants(argument, &constants);
+                    }
+                }
+                VMInstructionType::Goto(_) => {}
+            }
+
+            let folded = match &instruction.instruction_type {
+                VMInstructionType::UnaryOperation(op, dest, VMValue::Value(a, _)) => {
+                    op.apply(a.clone()).ok().map(|result| (dest.clone(), result))
+                }
+                VMInstructionType::BinaryOperation(op, dest, VMValue::Value(a, _), VMValue::Value(b, _)) => {
+                    op.apply(a.clone(), b.clone()).ok().map(|result| (dest.clone(), result))
+                }
+                _ => None
+            };
+
+            if let Some((dest, result)) = folded {
+                instruction.instruction_type = VMInstructionType::Assign(dest.into(), (result, None).into());
+            }
+
+            if let VMInstructionType::Assign(VMValue::Variable(dest), value @ VMValue::Value(_, _)) = &instruction.instruction_type {
+                if writes.get(dest.extract_text()) == Some(&1) {
+                    constants.insert(dest.extract_text().to_string(), value.clone());
+                }
+            }
+        }
+    }
+
+    /// Remove every instruction which can never be executed, such as the jump
+    /// past an else block after a `return`. The final `return` is always kept
+    /// so the function still ends with one.
+    pub fn eliminate_dead_code(&mut self) {
+        let length = self.instructions.len();
+        let mut reachable = vec![false; length];
+        let mut pending = vec![0];
+
+        while let Some(index) = pending.pop() {
+            if index >= length || reachable[index] {
+                continue;
+            }
+
+            reachable[index] = true;
+
+            match &self.instructions[index].instruction_type {
+                VMInstructionType::Branch(_, true_branch, false_branch) => pending.extend([*true_branch, *false_branch]),
+                VMInstructionType::Goto(target) => pending.push(*target),
+                VMInstructionType::Return(_) => {}
+                _ => pending.push(index + 1)
+            }
+        }
+
+        if let Some(last) = reachable.last_mut() {
+            *last = true;
+        }
+
+        self.remap_instructions(&reachable);
+    }
+
+    /// Number of times each variable is read and written across the whole function
+    fn variable_uses(&self) -> (HashMap<String, usize>, HashMap<String, usize>) {
+        let mut reads: HashMap<String, usize> = HashMap::new();
+        let mut writes: HashMap<String, usize> = HashMap::new();
+
+        for instruction in &self.instructions {
+            for variable in instruction.instruction_type.get_reads() {
+                *reads.entry(variable.extract_text().to_string()).or_default() += 1;
+            }
+
+            for variable in instruction.instruction_type.get_writes() {
+                *writes.entry(variable.extract_text().to_string()).or_default() += 1;
+            }
+        }
+
+        (reads, writes)
+    }
+
+    /// Simplify short runs of instructions, repeating until nothing changes
+    pub fn peephole_optimize(&mut self) {
+        while self.peephole_pass() {}
+    }
+
+    /// Make a single pass of `peephole_optimize`, returning whether anything changed
+    fn peephole_pass(&mut self) -> bool {
+        let (reads, writes) = self.variable_uses();
+        let used_once = |v: &VMVariable| is_temporary(v) && reads.get(v.extract_text()) == Some(&1) && writes.get(v.extract_text()) == Some(&1);
+
+        let jump_targets = self.instructions.iter()
+            .flat_map(|i| match i.instruction_type {
+                VMInstructionType::Branch(_, t, f) => vec![t, f],
+                VMInstructionType::Goto(t) => vec![t],
+                _ => vec![]
+            })
+            .collect::<HashSet<_>>();
+
+        let mut keep = vec![true; self.instructions.len()];
+        let mut changed = false;
+
+        for i in 0..self.instructions.len() {
+            match &self.instructions[i].instruction_type {
+                // `goto N` as instruction N - 1 does nothing
+                VMInstructionType::Goto(target) if *target == i + 1 => {
+                    keep[i] = false;
+                    changed = true;
+                    continue;
+                }
+                // A constant stored into a temporary which is never read, left behind by `fold_constants`
+                VMInstructionType::Assign(VMValue::Variable(dest), VMValue::Value(_, _)) if is_temporary(dest) && !reads.contains_key(dest.extract_text()) => {
+                    keep[i] = false;
+                    changed = true;
+                    continue;
+                }
+                // `branch c, N, N` goes to N whatever the condition is
+                VMInstructionType::Branch(_, t, f) if t == f => {
+                    self.instructions[i].instruction_type = VMInstructionType::Goto(*t);
+                    changed = true;
+                    continue;
+                }
+                _ => {}
+            }
+
+            // A temporary which is only written by one instruction and then copied
+            // straight into a variable by the next can be written to that variable
+            // directly, e.g. `add t, a, b; assign x, t` becomes `add x, a, b`
+            if i + 1 >= self.instructions.len() || !keep[i] || jump_targets.contains(&(i + 1))
+                || self.instructions[i].associated_line != self.instructions[i + 1].associated_line {
+                continue;
+            }
+
+            let VMInstructionType::Assign(VMValue::Variable(dest), VMValue::Variable(source)) = &self.instructions[i + 1].instruction_type
+                else { continue };
+
+            if !self.instructions[i].instruction_type.get_writes().iter().any(|w| w.extract_text() == source.extract_text()) || !used_once(source) {
+                continue;
+            }
+
+            let dest = dest.clone();
+            let merged = match &mut self.instructions[i].instruction_type {
+                VMInstructionType::Assign(VMValue::Variable(v), _) |
+                VMInstructionType::UnaryOperation(_, v, _) |
+                VMInstructionType::BinaryOperation(_, v, _, _) |
+                VMInstructionType::FunctionCall(_, v, _) |
+                VMInstructionType::LoopStep(v, _) |
+                VMInstructionType::IterationLength(v, _) |
+                VMInstructionType::IterationItem(v, _, _) => {
+                    *v = dest;
+                    true
+                }
+                _ => false
+            };
+
+            if merged {
+                keep[i + 1] = false;
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.remap_instructions(&keep);
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{VMInstruction, interpreter::Value, parser::{ParserContext, ParseTreeNode}, tokenizer::{Token, TokenStream}};
+
+    use super::*;
+
+    /// Compile the first function in `source` without running any of the passes
+    fn unoptimized(source: &'static str) -> VMFunction<'static> {
+        let mut tokens = TokenStream::from_source(source, "test.ps");
+        let (mut parse_tree, _) = ParserContext::new(&mut tokens).parse_document().unwrap();
+
+        let ParseTreeNode::Function { name, arguments, block, .. } = parse_tree.remove(0) else { panic!("expected a function") };
+        let line = name.location.line;
+        let mut function = VMFunction::new(name, arguments);
+        function.compile(&block).unwrap();
+        function.add_instruction_type(line, VMInstructionType::Return(Value::None.into()));
+
+        function
+    }
+
+    fn types<'a, 'file>(function: &'a VMFunction<'file>) -> Vec<&'a VMInstructionType<'file>> {
+        function.instructions.iter().map(|i| &i.instruction_type).collect()
+    }
+
+    #[test]
+    fn constant_expressions_fold_to_one_assignment() {
+        let mut function = unoptimized("F()\n    x = 2 + 3 * 4\n");
+        function.fold_constants();
+        function.peephole_optimize();
+
+        let instructions = types(&function);
+        assert!(!instructions.iter().any(|i| matches!(i, VMInstructionType::BinaryOperation(..))));
+
+        let assignments = instructions.iter().filter(|i| matches!(i, VMInstructionType::Assign(..))).collect::<Vec<_>>();
+        assert_eq!(assignments.len(), 1);
+        assert!(matches!(assignments[0], VMInstructionType::Assign(VMValue::Variable(x), VMValue::Value(Value::Integer(14), _)) if x.extract_text() == "x"));
+    }
+
+    #[test]
+    fn failing_operations_are_not_folded() {
+        let mut function = unoptimized("F()\n    x = 1 + \"s\"\n");
+        function.fold_constants();
+
+        assert!(types(&function).iter().any(|i| matches!(i, VMInstructionType::BinaryOperation(..))));
+    }
+
+    #[test]
+    fn jump_after_return_is_removed() {
+        let mut function = unoptimized("F()\n    if True\n        return 1\n    else\n        return 2\n");
+        assert!(types(&function).iter().any(|i| matches!(i, VMInstructionType::Goto(_))));
+
+        function.eliminate_dead_code();
+
+        let instructions = types(&function);
+        assert!(!instructions.iter().any(|i| matches!(i, VMInstructionType::Goto(_))));
+        assert!(matches!(instructions.as_slice(), [
+            VMInstructionType::Branch(_, 1, 2),
+            VMInstructionType::Return(VMValue::Value(Value::Integer(1), _)),
+            VMInstructionType::Return(VMValue::Value(Value::Integer(2), _)),
+            VMInstructionType::Return(VMValue::Value(Value::None, _))
+        ]));
+    }
+
+    #[test]
+    fn jumps_over_removed_code_are_moved() {
+        let mut function = VMFunction::new(Token::synthetic("F"), vec![]);
+        let x = || VMValue::from("x".to_string());
+        function.instructions = vec![
+            VMInstructionType::Branch(x(), 1, 5),
+            VMInstructionType::Return(x()),
+            // 2 to 4 are never reached, and the jump at 3 lands on code removed with it
+            VMInstructionType::Assign(x(), Value::Integer(1).into()),
+            VMInstructionType::Goto(2),
+            VMInstructionType::Assign(x(), Value::Integer(2).into()),
+            VMInstructionType::Goto(1),
+            VMInstructionType::Return(Value::None.into())
+        ].into_iter().map(|instruction| VMInstruction::new(1, instruction)).collect();
+
+        function.eliminate_dead_code();
+
+        assert!(matches!(types(&function).as_slice(), [
+            VMInstructionType::Branch(_, 1, 2),
+            VMInstructionType::Return(_),
+            VMInstructionType::Goto(1),
+            VMInstructionType::Return(_)
+        ]));
+    }
+}
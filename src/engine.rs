@@ -0,0 +1,91 @@
+use std::{rc::Rc, cell::RefCell, io::Write};
+
+use crate::{tokenizer::TokenStream, parser::{ParserContext, ParseTreeNode}, error::GenericError,
+            interpreter::{RunTime, RuntimeError, Value, NativeFunctions, OutputSink}};
+
+/// Runs pseudocode programs from other Rust applications, without depending on
+/// the tokenizer, parser and interpreter directly. The program is parsed again
+/// on every call to `execute`, so each call starts from a fresh runtime.
+#[derive(Debug, Clone, Default)]
+pub struct PseudocodeEngine {
+    /// Text and file name of the program
+    source: Option<(String, String)>,
+    max_steps: Option<u64>,
+    max_recursion_depth: Option<usize>,
+    native_functions: NativeFunctions,
+    output: OutputSink
+}
+
+impl PseudocodeEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `source` as the program, with `name` used as the file name in errors
+    pub fn with_source(mut self, source: &str, name: &str) -> Self {
+        self.source = Some((source.to_string(), name.to_string()));
+        self
+    }
+
+    /// Stop execution with an error after `n` statements
+    pub fn with_max_steps(mut self, n: u64) -> Self {
+        self.max_steps = Some(n);
+        self
+    }
+
+    /// Stop execution with an error once more than `n` function calls are nested,
+    /// rather than the default of `DEFAULT_MAX_RECURSION_DEPTH`
+    pub fn with_max_recursion_depth(mut self, n: usize) -> Self {
+        self.max_recursion_depth = Some(n);
+        self
+    }
+
+    /// Make `function` callable from the program as `name`. Engines cloned
+    /// before this call do not see the new function.
+    pub fn with_native_function(mut self, name: &str, function: impl Fn(Vec<Value>) -> Result<Value, RuntimeError<'static>> + 'static) -> Self {
+        self.native_functions = self.native_functions.copied();
+        self.native_functions.register(name, function);
+        self
+    }
+
+    /// Write the output of `Print` to `writer` rather than standard output
+    pub fn set_stdout(mut self, writer: impl Write + 'static) -> Self {
+        self.output = OutputSink::new(writer);
+        self
+    }
+
+    /// Parse the program without running it, giving the warnings found while
+    /// parsing or every error which stopped it from parsing
+    pub fn check(&self) -> Result<Vec<GenericError<'static>>, Vec<GenericError<'static>>> {
+        self.parse().map(|(_, warnings)| warnings)
+    }
+
+    /// Parse the program and call the function `function_name` with `arguments`,
+    /// giving the value it returns or every error which stopped it from running.
+    /// Warnings are not reported here, use `check` to see them.
+    pub fn execute(&self, function_name: &str, arguments: Vec<Value>) -> Result<Value, Vec<GenericError<'static>>> {
+        let (parse_tree, _) = self.parse()?;
+
+        let mut runtime = RunTime::new(parse_tree);
+        runtime.max_steps = self.max_steps;
+        if let Some(depth) = self.max_recursion_depth {
+            runtime.set_max_recursion_depth(depth);
+        }
+        runtime.native_functions = self.native_functions.clone();
+        runtime.output = self.output.clone();
+
+        match RunTime::inner_execute_function(Rc::new(RefCell::new(runtime)), function_name.to_string(), arguments) {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => Err(vec![GenericError::tokenless_error(format!("function '{}' not defined", function_name))]),
+            Err(e) => Err(vec![e.finish_no_token()])
+        }
+    }
+
+    fn parse(&self) -> Result<(Vec<ParseTreeNode<'static>>, Vec<GenericError<'static>>), Vec<GenericError<'static>>> {
+        let (text, name) = self.source.clone()
+            .ok_or_else(|| vec![GenericError::tokenless_error("no source was given to the engine".to_string())])?;
+
+        let mut tokens = TokenStream::from_source_owned(text, name);
+        ParserContext::new(&mut tokens).parse_document()
+    }
+}
@@ -1,11 +1,6 @@
-use crate::tokenizer::Token;
-
-const CLEAR: &str = "\x1b[0m";
-const RED: &str = "\x1b[31m";
-const YELLOW: &str = "\x1b[33m";
-const CYAN: &str = "\x1b[36m";
-const WHITE: &str = "\x1b[37m";
+use std::sync::Arc;
 
+use crate::{tokenizer::{Token, FileText}, color::{Ansi, CLEAR, RED, YELLOW, CYAN, WHITE}};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorType {
@@ -23,7 +18,7 @@ impl ErrorType {
         }
     }
 
-    pub fn color(&self) -> &'static str {
+    pub fn color(&self) -> Ansi {
         match self {
             ErrorType::Error => RED,
             ErrorType::Warning => YELLOW,
@@ -34,86 +29,188 @@ impl ErrorType {
 
 #[derive(Debug)]
 pub struct GenericError<'file> {
-    token: Option<Token<'file>>,
+    token: Option<Box<Token<'file>>>,
+    /// Last token of the range underlined, only `token` is underlined if this is not given
+    end_token: Option<Box<Token<'file>>>,
     pub error_type: ErrorType,
     message: String,
-    help: Option<String>,
-    arrow_note: Option<String>
+    arrow_note: Option<String>,
+    notes: Vec<String>
 }
 
 impl<'file> GenericError<'file> {
     pub fn error(token: Token<'file>, message: String) -> Self {
         Self {
             error_type: ErrorType::Error,
-            token: Some(token), message, help: None, arrow_note: None
+            token: Some(Box::new(token)), end_token: None, message, arrow_note: None, notes: Vec::new()
         }
     }
 
     pub fn warning(token: Token<'file>, message: String) -> Self {
         Self {
             error_type: ErrorType::Warning,
-            token: Some(token), message, help: None, arrow_note: None
+            token: Some(Box::new(token)), end_token: None, message, arrow_note: None, notes: Vec::new()
         }
     }
 
     pub fn info(token: Token<'file>, message: String) -> Self {
         Self {
             error_type: ErrorType::Info,
-            token: Some(token), message, help: None, arrow_note: None
+            token: Some(Box::new(token)), end_token: None, message, arrow_note: None, notes: Vec::new()
         }
     }
 
     pub fn tokenless_error(message: String) -> Self {
         Self {
             error_type: ErrorType::Error,
-            token: None, message, help: None, arrow_note: None
+            token: None, end_token: None, message, arrow_note: None, notes: Vec::new()
         }
     }
 
     pub fn tokenless_warning(message: String) -> Self {
         Self {
             error_type: ErrorType::Warning,
-            token: None, message, help: None, arrow_note: None
+            token: None, end_token: None, message, arrow_note: None, notes: Vec::new()
         }
     }
 
     pub fn tokenless_info(message: String) -> Self {
         Self {
             error_type: ErrorType::Info,
-            token: None, message, help: None, arrow_note: None
+            token: None, end_token: None, message, arrow_note: None, notes: Vec::new()
         }
     }
 
     pub fn tokenable_error(token: Option<Token<'file>>, message: String) -> Self {
         Self {
             error_type: ErrorType::Error,
-            token, message, help: None, arrow_note: None
+            token: token.map(Box::new), end_token: None, message, arrow_note: None, notes: Vec::new()
         }
     }
 
     pub fn tokenable_warning(token: Option<Token<'file>>, message: String) -> Self {
         Self {
             error_type: ErrorType::Warning,
-            token, message, help: None, arrow_note: None
+            token: token.map(Box::new), end_token: None, message, arrow_note: None, notes: Vec::new()
         }
     }
 
     pub fn tokenable_info(token: Option<Token<'file>>, message: String) -> Self {
         Self {
             error_type: ErrorType::Info,
-            token, message, help: None, arrow_note: None
+            token: token.map(Box::new), end_token: None, message, arrow_note: None, notes: Vec::new()
+        }
+    }
+
+    /// Give the text returned by `text_of` for each file name to the error's
+    /// tokens which do not already carry their file's text, such as tokens
+    /// loaded from bytecode, so the offending line can be shown
+    pub fn with_file_text(mut self, text_of: impl Fn(&str) -> Option<Arc<str>>) -> Self {
+        for token in self.token.iter_mut().chain(self.end_token.iter_mut()) {
+            if token.location.file_text.is_none() {
+                token.location.file_text = text_of(&token.location.filename).map(FileText::Shared);
+            }
         }
+
+        self
     }
 
-    pub fn help(mut self, help: String) -> Self {
-        self.help = Some(help);
+    /// Underline everything from the error's token to the end of `end_token`
+    pub fn end_token(mut self, end_token: Token<'file>) -> Self {
+        self.end_token = Some(Box::new(end_token));
         self
     }
 
+    /// Suggest `suggestion` as the name which was meant, if there is one
+    pub fn suggest(self, suggestion: Option<&str>) -> Self {
+        if let Some(suggestion) = suggestion {
+            self.note(format!("did you mean '{}'?", suggestion))
+        }
+        else {
+            self
+        }
+    }
+
     pub fn arrow(mut self, arrow_note: String) -> Self {
         self.arrow_note = Some(arrow_note);
         self
     }
+
+    /// Add a note shown below the offending line, after any added before it
+    pub fn note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+}
+
+impl<'file> GenericError<'file> {
+    /// Number of characters to underline on `line`, starting at `start`. A
+    /// range ending on a later line is underlined to the end of this one.
+    /// Parentheses are not kept as tokens, so the range is extended over any
+    /// closing parentheses needed to balance the ones it contains.
+    fn underline_length(&self, line: &str, start: usize) -> usize {
+        let token = self.token.as_ref().unwrap();
+
+        match &self.end_token {
+            Some(end) if end.location.index >= token.location.index => {
+                let mut end = (end.location.index - token.location.index + start + end.extract_text().len()).min(line.len());
+                let Some(text) = line.get(start..end) else { return 0 };
+
+                let mut open = text.matches('(').count().saturating_sub(text.matches(')').count());
+                while open > 0 {
+                    let rest = &line[end..];
+                    let trimmed = rest.trim_start();
+                    if !trimmed.starts_with(')') {
+                        break;
+                    }
+
+                    end += rest.len() - trimmed.len() + 1;
+                    open -= 1;
+                }
+
+                line[start..end].chars().count()
+            }
+            _ => token.extract_text().chars().count()
+        }
+    }
+}
+
+#[cfg(feature = "json-errors")]
+impl<'file> GenericError<'file> {
+    /// Render the error as a JSON object, with one based line and column numbers
+    /// and `null` for anything the error does not have. `help` holds every note
+    /// as one string, for tools which only show a single hint.
+    pub fn to_json(&self) -> String {
+        use crate::util::json_string;
+
+        let optional = |s: &Option<String>| s.as_deref().map(json_string).unwrap_or_else(|| "null".to_string());
+
+        let (filename, line, column, length) = match &self.token {
+            Some(token) => {
+                let length = match &self.end_token {
+                    Some(end) if end.location.index >= token.location.index => end.location.index - token.location.index + end.extract_text().len(),
+                    _ => token.extract_text().len()
+                };
+
+                (json_string(&token.location.filename), (token.location.line + 1).to_string(), (token.location.column + 1).to_string(), length.to_string())
+            }
+            None => ("null".to_string(), "null".to_string(), "null".to_string(), "null".to_string())
+        };
+
+        let help = if self.notes.is_empty() { None } else { Some(self.notes.join("\n")) };
+
+        format!("{{\"level\": {}, \"message\": {}, \"filename\": {}, \"line\": {}, \"column\": {}, \"length\": {}, \"help\": {}, \"notes\": [{}], \"arrow_note\": {}}}",
+            json_string(self.error_type.to_str()), json_string(&self.message), filename, line, column, length, optional(&help),
+            self.notes.iter().map(|n| json_string(n)).collect::<Vec<_>>().join(", "), optional(&self.arrow_note))
+    }
+}
+
+fn write_annotation(f: &mut std::fmt::Formatter<'_>, label: &str, text: &str) -> std::fmt::Result {
+    for line in text.lines() {
+        writeln!(f, "    {}= {}{}: {}{}", CYAN, WHITE, label, CLEAR, line)?;
+    }
+
+    Ok(())
 }
 
 impl<'file> std::fmt::Display for GenericError<'file> {
@@ -123,7 +220,7 @@ impl<'file> std::fmt::Display for GenericError<'file> {
             let location = token.location.clone();
             
             writeln!(f, "  {}-->{} {}:{}:{}", CYAN, CLEAR, location.filename, location.line + 1, location.column + 1)?;
-            if let Some(raw) = location.file_text {
+            if let Some(raw) = location.file_text.as_deref() {
                 let error_line = location.line;
                 let mut index_offset = 0;
                 writeln!(f, "    {}|", CYAN)?;
@@ -135,7 +232,7 @@ impl<'file> std::fmt::Display for GenericError<'file> {
                             for _ in 0..(location.index - index_offset) {
                                 write!(f, " ")?;
                             }
-                            for _ in token.extract_text().chars() {
+                            for _ in 0..self.underline_length(line, location.index - index_offset) {
                                 write!(f, "^")?;
                             }
                             if let Some(arrow_note) = &self.arrow_note {
@@ -153,13 +250,62 @@ impl<'file> std::fmt::Display for GenericError<'file> {
         }
         
 
-        if let Some(help) = &self.help {
-            for line in help.lines() {
-                writeln!(f, "    {}= {}help: {}{}", CYAN, WHITE, CLEAR, line)?;
-            }
+        for note in &self.notes {
+            write_annotation(f, "note", note)?;
         }
         write!(f, "{}", CLEAR)?;
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_with_the_same_name_show_their_own_lines() {
+        use crate::tokenizer::TokenStream;
+
+        let first = TokenStream::from_source_owned("First()\n", "test.ps").next().unwrap();
+        let second = TokenStream::from_source_owned("Second()\n", "test.ps").next().unwrap();
+
+        let first = GenericError::error(first, "first".to_string()).to_string();
+        let second = GenericError::error(second, "second".to_string()).to_string();
+        assert!(first.contains("First()") && !first.contains("Second()"), "{}", first);
+        assert!(second.contains("Second()") && !second.contains("First()"), "{}", second);
+    }
+
+    #[test]
+    fn file_text_is_given_to_tokens_without_it() {
+        let mut token = Token::synthetic("Missing");
+        token.location.filename = "loaded.ps".into();
+
+        let error = GenericError::error(token, "function 'Missing' not defined".to_string())
+            .with_file_text(|name| (name == "loaded.ps").then(|| "Missing()\n".into()));
+        assert!(error.to_string().contains("1   |"), "{}", error);
+    }
+
+    #[test]
+    fn every_note_is_shown() {
+        let error = GenericError::tokenless_error("function 'F' called with the wrong arguments".to_string())
+            .note("called here".to_string())
+            .note("defined here".to_string())
+            .note("called again here".to_string());
+
+        let text = error.to_string();
+        let notes = ["called here", "defined here", "called again here"].map(|note| text.find(&format!("note: {}{}\n", CLEAR, note)));
+
+        assert!(notes.iter().all(|n| n.is_some()), "{}", text);
+        assert!(notes.windows(2).all(|pair| pair[0] < pair[1]), "notes out of order in {}", text);
+    }
+
+    #[cfg(feature = "json-errors")]
+    #[test]
+    fn json_gives_notes_and_help() {
+        let error = GenericError::tokenless_error("bad".to_string()).note("first".to_string()).note("second".to_string());
+
+        assert_eq!(error.to_json(), r#"{"level": "error", "message": "bad", "filename": null, "line": null, "column": null, "length": null, "help": "first\nsecond", "notes": ["first", "second"], "arrow_note": null}"#);
+        assert!(GenericError::tokenless_error("bad".to_string()).to_json().contains(r#""help": null, "notes": []"#));
+    }
+}
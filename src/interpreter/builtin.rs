@@ -1,8 +1,20 @@
-use std::{rc::Rc, cell::RefCell};
+use std::{cmp::Ordering, collections::HashMap, rc::Rc, cell::RefCell};
 
 use crate::{tokenizer::Token, error::GenericError, parser::ParseTreeNode};
 
-use super::{Value, RuntimeError, Executor};
+use super::{Value, RuntimeError, Executor, OutputSink};
+
+/// Names of every builtin function, used to suggest a name when an unknown function is called
+pub const BUILTIN_NAMES: &[&str] = &[
+    "AssertEqual", "Array", "ArrayCreate", "Print", "ceil", "floor", "FloorDiv", "DivMod",
+    "Substring", "Split", "Join", "ModExp", "Gcd", "ExtendedGcd", "BitAnd", "BitOr",
+    "BitXor", "BitNot", "SHL", "SHR", "Map", "Keys", "Values", "Sort",
+    "Append", "Pop", "min", "max", "abs", "sqrt", "round", "truncate",
+    "str", "num", "type", "Input", "Insert", "Remove", "Swap",
+    "Contains", "HasKey",
+    "Copy", "Range",
+    "int"
+];
 
 pub fn get_args1<'a, T: Clone>(args: Vec<T>) -> Result<T, RuntimeError<'a>> {
     if args.len() != 1 {
@@ -22,6 +34,15 @@ pub fn get_args2<'a, T: Clone>(args: Vec<T>) -> Result<(T, T), RuntimeError<'a>>
     }
 }
 
+pub fn get_args3<'a, T: Clone>(args: Vec<T>) -> Result<(T, T, T), RuntimeError<'a>> {
+    if args.len() != 3 {
+        Err(RuntimeError::ArgumentCountError { expected: 3, got: args.len() })
+    }
+    else {
+        Ok((args[0].clone(), args[1].clone(), args[2].clone()))
+    }
+}
+
 pub fn builtin_assert_eq(name: Option<Token<'_>>, args: Vec<Value>) -> Result<Value, RuntimeError<'_>> {
     let (a, b) = get_args2(args)?;
 
@@ -41,15 +62,159 @@ pub fn builtin_array<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'fil
         vector.push(v);
     }
 
-    Ok(Value::Array(Rc::new(RefCell::new((vector, Value::Number(0.0))))))
+    Ok(Value::new_array(vector))
+}
+
+/// The integers from `a` to `b` inclusive, counting by `step`. With a single
+/// argument `n`, these are the indices of an array of length `n`.
+pub fn builtin_range<'file>(args: Vec<Value>, zero_indexed: bool) -> Result<Value, RuntimeError<'file>> {
+    let bounds = args.iter().map(|v| get_integer(v, "make a range of")).collect::<Result<Vec<_>, _>>()?;
+
+    let (start, end, step) = match bounds[..] {
+        [n] if zero_indexed => (0, n - 1, 1),
+        [n] => (1, n, 1),
+        [a, b] => (a, b, 1),
+        [a, b, step] => (a, b, step),
+        _ => return Err(RuntimeError::MessageError(format!("expected 1 to 3 arguments to Range, got {}", args.len())))
+    };
+
+    if step == 0 {
+        return Err(RuntimeError::MessageError("range step cannot be zero".to_string()));
+    }
+
+    let mut values = Vec::new();
+    let mut i = start;
+    while (step > 0 && i <= end) || (step < 0 && i >= end) {
+        values.push(Value::Integer(i));
+        i += step;
+    }
+
+    Ok(Value::new_array(values))
+}
+
+/// Build a map from alternating keys and values, as in `Map("a", 1, "b", 2)`
+pub fn builtin_map<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    if !args.len().is_multiple_of(2) {
+        return Err(RuntimeError::MessageError(format!("expected an even number of arguments to Map, got {}", args.len())));
+    }
+
+    let mut entries = HashMap::new();
+    let mut args = args.into_iter();
+
+    while let (Some(key), Some(value)) = (args.next(), args.next()) {
+        if let Value::String(key) = key {
+            entries.insert(key, value);
+        }
+        else {
+            return Err(RuntimeError::MessageError(format!("map keys must be strings, got {}", key.type_name_full())));
+        }
+    }
+
+    Ok(Value::new_map(entries))
+}
+
+/// The keys of a map, in sorted order
+pub fn builtin_keys<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let map = get_args1(args)?;
+
+    if let Value::Map(map) = &map {
+        let mut keys = map.borrow().keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+
+        Ok(Value::new_array(keys.into_iter().map(Value::String).collect()))
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot get the keys of type {}", map.type_name_full())))
+    }
+}
+
+/// The values of a map, in the order of their sorted keys
+pub fn builtin_values<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let map = get_args1(args)?;
+
+    if let Value::Map(map) = &map {
+        let map = map.borrow();
+        let mut entries = map.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+
+        Ok(Value::new_array(entries.into_iter().map(|(_, v)| v.clone()).collect()))
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot get the values of type {}", map.type_name_full())))
+    }
+}
+
+/// A new array or map holding the same elements and attributes. Nested arrays
+/// and maps are still shared, other values are returned unchanged.
+pub fn builtin_copy<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let value = get_args1(args)?;
+
+    Ok(match &value {
+        Value::Array(array) => Value::Array(Rc::new(RefCell::new(array.borrow().clone()))),
+        Value::Map(map) => Value::new_map(map.borrow().clone()),
+        _ => value
+    })
+}
+
+/// Whether an array holds a value, a string holds a substring or a map holds a key
+pub fn builtin_contains<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (container, element) = get_args2(args)?;
+
+    match (&container, &element) {
+        (Value::Array(array), _) => Ok(Value::Boolean(array.borrow().0.contains(&element))),
+        (Value::String(s), Value::String(sub)) => Ok(Value::Boolean(s.contains(sub.as_str()))),
+        (Value::Map(map), Value::String(key)) => Ok(Value::Boolean(map.borrow().contains_key(key))),
+        _ => Err(RuntimeError::MessageError(format!("cannot check whether type {} contains type {}", container.type_name_full(), element.type_name_full())))
+    }
+}
+
+/// Whether a map holds a key
+pub fn builtin_has_key<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (map, key) = get_args2(args)?;
+
+    match (&map, &key) {
+        (Value::Map(map), Value::String(key)) => Ok(Value::Boolean(map.borrow().contains_key(key))),
+        (Value::Map(_), _) => Err(RuntimeError::MessageError(format!("map keys must be strings, got {}", key.type_name_full()))),
+        _ => Err(RuntimeError::MessageError(format!("cannot look up a key in type {}, HasKey expects a map", map.type_name_full())))
+    }
+}
+
+/// Sort an array in place, from smallest to largest
+// TODO: Add a `SortBy(array, comparator)` variant which takes the name of a
+// two argument function returning `True` when its first argument is smaller,
+// this needs a reference to the runtime to be threaded through to the builtin
+pub fn builtin_sort<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let array = get_args1(args)?;
+
+    if let Value::Array(array) = &array {
+        if array.borrow().0.iter().any(|v| v.as_number().is_some_and(f64::is_nan)) {
+            return Err(RuntimeError::MessageError("cannot sort an array containing NaN".to_string()));
+        }
+
+        let mut unordered = None;
+
+        array.borrow_mut().0.sort_by(|a, b| a.partial_cmp(b).unwrap_or_else(|| {
+            unordered.get_or_insert_with(|| (a.type_name_full(), b.type_name_full()));
+            Ordering::Equal
+        }));
+
+        if let Some((a, b)) = unordered {
+            return Err(RuntimeError::MessageError(format!("cannot sort an array containing values of type {} and {}", a, b)));
+        }
+
+        Ok(Value::None)
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot sort value of type {}", array.type_name_full())))
+    }
 }
 
 pub fn builtin_array_create<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
     let size = get_args1(args)?;
 
-    if let Value::Number(n) = size {
+    if let Some(n) = size.as_number() {
         if n.fract() == 0.0 && n > 0.0 {
-            Ok(Value::Array(Rc::new(RefCell::new((vec![Value::None; n as usize], Value::Number(0.0))))))
+            Ok(Value::new_array(vec![Value::None; n as usize]))
         }
         else {
             Err(RuntimeError::MessageError(format!("array length '{}' is not a positive integer", n)))
@@ -61,15 +226,11 @@ pub fn builtin_array_create<'file>(args: Vec<Value>) -> Result<Value, RuntimeErr
 }
 
 
-pub fn builtin_print<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
-    for (i, arg) in args.iter().enumerate() {
-        if i != 0 {
-            print!(", ");
-        }
-        print!("{}", arg);
-    }
+pub fn builtin_print<'file>(args: Vec<Value>, output: &OutputSink) -> Result<Value, RuntimeError<'file>> {
+    let mut text = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().join(", ");
+    text.push('\n');
 
-    println!();
+    output.write(&text).map_err(|e| RuntimeError::MessageError(format!("unable to write output: {}", e)))?;
 
     Ok(Value::None)
 }
@@ -90,12 +251,12 @@ pub fn builtin_logical_and<'file>(args: Vec<ParseTreeNode<'file>>, executor: &mu
                 Ok(Value::Boolean(b))
             }
             else {
-                Err(RuntimeError::MessageError(format!("cannot and value of type {}", b.get_type_name())))
+                Err(RuntimeError::MessageError(format!("cannot and value of type {}", b.type_name_full())))
             }
         }
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot and value of type {}", a.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot and value of type {}", a.type_name_full())))
     }
 }
 
@@ -115,103 +276,182 @@ pub fn builtin_logical_or<'file>(args: Vec<ParseTreeNode<'file>>, executor: &mut
                 Ok(Value::Boolean(b))
             }
             else {
-                Err(RuntimeError::MessageError(format!("cannot or value of type {}", b.get_type_name())))
+                Err(RuntimeError::MessageError(format!("cannot or value of type {}", b.type_name_full())))
             }
         }
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot or value of type {}", a.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot or value of type {}", a.type_name_full())))
     }
 }
 
-pub fn builtin_add<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+/// Logical and of two values which have already been evaluated. The second value is
+/// ignored when the first is false, giving the same result as the short circuiting and.
+pub fn builtin_evaluated_logical_and<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (a, b) = get_args2(args)?;
+
+    match (&a, &b) {
+        (Value::Boolean(false), _) => Ok(Value::Boolean(false)),
+        (Value::Boolean(true), Value::Boolean(b)) => Ok(Value::Boolean(*b)),
+        (Value::Boolean(true), _) => Err(RuntimeError::MessageError(format!("cannot and value of type {}", b.type_name_full()))),
+        _ => Err(RuntimeError::MessageError(format!("cannot and value of type {}", a.type_name_full())))
+    }
+}
+
+/// Logical or of two values which have already been evaluated. The second value is
+/// ignored when the first is true, giving the same result as the short circuiting or.
+pub fn builtin_evaluated_logical_or<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
     let (a, b) = get_args2(args)?;
 
-    if let (Value::Number(a), Value::Number(b)) = (&a, &b) {
-        Ok(Value::Number(a + b))
+    match (&a, &b) {
+        (Value::Boolean(true), _) => Ok(Value::Boolean(true)),
+        (Value::Boolean(false), Value::Boolean(b)) => Ok(Value::Boolean(*b)),
+        (Value::Boolean(false), _) => Err(RuntimeError::MessageError(format!("cannot or value of type {}", b.type_name_full()))),
+        _ => Err(RuntimeError::MessageError(format!("cannot or value of type {}", a.type_name_full())))
+    }
+}
+
+pub fn builtin_logical_not<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let a = get_args1(args)?;
+
+    if let Value::Boolean(a) = a {
+        Ok(Value::Boolean(!a))
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot add values of type {} and {}", a.get_type_name(), b.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot not value of type {}", a.type_name_full())))
     }
 }
 
-pub fn builtin_sub<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+pub fn builtin_logical_xor<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
     let (a, b) = get_args2(args)?;
 
-    if let (Value::Number(a), Value::Number(b)) = (&a, &b) {
-        Ok(Value::Number(a - b))
+    if let (Value::Boolean(a), Value::Boolean(b)) = (&a, &b) {
+        Ok(Value::Boolean(a != b))
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot subtract values of type {} and {}", a.get_type_name(), b.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot xor values of type {} and {}", a.type_name_full(), b.type_name_full())))
     }
 }
 
-pub fn builtin_mul<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+pub fn builtin_negate<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let a = get_args1(args)?;
+
+    if let Value::Integer(a) = a {
+        Ok(a.checked_neg().map_or(Value::Number(-(a as f64)), Value::Integer))
+    }
+    else if let Value::Number(a) = a {
+        Ok(Value::Number(-a))
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot negate value of type {}", a.type_name_full())))
+    }
+}
+
+/// Apply an arithmetic operator, keeping the result an integer when both
+/// operands are integers and it does not overflow
+fn arithmetic(a: &Value, b: &Value, integer_op: fn(i64, i64) -> Option<i64>, number_op: fn(f64, f64) -> f64) -> Option<Value> {
+    if let (Value::Integer(x), Value::Integer(y)) = (a, b) {
+        if let Some(v) = integer_op(*x, *y) {
+            return Some(Value::Integer(v));
+        }
+    }
+
+    Some(Value::Number(number_op(a.as_number()?, b.as_number()?)))
+}
+
+pub fn builtin_add<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
     let (a, b) = get_args2(args)?;
 
-    if let (Value::Number(a), Value::Number(b)) = (&a, &b) {
-        Ok(Value::Number(a * b))
+    if let Some(v) = arithmetic(&a, &b, i64::checked_add, |a, b| a + b) {
+        Ok(v)
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot multiply values of type {} and {}", a.get_type_name(), b.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot add values of type {} and {}", a.type_name_full(), b.type_name_full())))
     }
 }
 
-pub fn builtin_div<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+pub fn builtin_concatenate<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
     let (a, b) = get_args2(args)?;
 
-    if let (Value::Number(a), Value::Number(b)) = (&a, &b) {
-        Ok(Value::Number(a / b))
+    // Numbers and booleans are converted to text, anything else has no obvious text form
+    let as_text = |v: &Value| match v {
+        Value::String(_) | Value::Number(_) | Value::Integer(_) | Value::Boolean(_) => Some(v.to_string()),
+        _ => None
+    };
+
+    if let (Some(a), Some(b)) = (as_text(&a), as_text(&b)) {
+        Ok(Value::String(a + &b))
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot divide values of type {} and {}", a.get_type_name(), b.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot concatenate values of type {} and {}", a.type_name_full(), b.type_name_full())))
     }
 }
 
-pub fn builtin_greater_than<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+pub fn builtin_sub<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
     let (a, b) = get_args2(args)?;
 
-    if let (Value::Number(a), Value::Number(b)) = (&a, &b) {
-        Ok(Value::Boolean(a > b))
+    if let Some(v) = arithmetic(&a, &b, i64::checked_sub, |a, b| a - b) {
+        Ok(v)
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot compare values of type {} and {}", a.get_type_name(), b.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot subtract values of type {} and {}", a.type_name_full(), b.type_name_full())))
     }
 }
 
-pub fn builtin_less_than<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+pub fn builtin_mul<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
     let (a, b) = get_args2(args)?;
 
-    if let (Value::Number(a), Value::Number(b)) = (&a, &b) {
-        Ok(Value::Boolean(a < b))
+    if let Some(v) = arithmetic(&a, &b, i64::checked_mul, |a, b| a * b) {
+        Ok(v)
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot compare values of type {} and {}", a.get_type_name(), b.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot multiply values of type {} and {}", a.type_name_full(), b.type_name_full())))
     }
 }
 
-pub fn builtin_greater_than_equal<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+pub fn builtin_div<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
     let (a, b) = get_args2(args)?;
 
-    if let (Value::Number(a), Value::Number(b)) = (&a, &b) {
-        Ok(Value::Boolean(a >= b))
+    // Dividing two integers gives a number, as the result may not be whole
+    if let (Some(a), Some(b)) = (a.as_number(), b.as_number()) {
+        Ok(Value::Number(a / b))
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot compare values of type {} and {}", a.get_type_name(), b.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot divide values of type {} and {}", a.type_name_full(), b.type_name_full())))
     }
 }
 
-pub fn builtin_less_than_equal<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+fn builtin_compare<'file>(args: Vec<Value>, test: fn(Ordering) -> bool) -> Result<Value, RuntimeError<'file>> {
     let (a, b) = get_args2(args)?;
 
-    if let (Value::Number(a), Value::Number(b)) = (&a, &b) {
-        Ok(Value::Boolean(a <= b))
+    // As with floating point comparisons, NaN is neither less than, greater than nor equal to any number
+    if a.as_number().is_some_and(f64::is_nan) && b.as_number().is_some() || b.as_number().is_some_and(f64::is_nan) && a.as_number().is_some() {
+        Ok(Value::Boolean(false))
+    }
+    else if let Some(ordering) = a.partial_cmp(&b) {
+        Ok(Value::Boolean(test(ordering)))
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot compare values of type {} and {}", a.get_type_name(), b.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot compare values of type {} and {}", a.type_name_full(), b.type_name_full())))
     }
 }
 
+pub fn builtin_greater_than<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    builtin_compare(args, Ordering::is_gt)
+}
+
+pub fn builtin_less_than<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    builtin_compare(args, Ordering::is_lt)
+}
+
+pub fn builtin_greater_than_equal<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    builtin_compare(args, Ordering::is_ge)
+}
+
+pub fn builtin_less_than_equal<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    builtin_compare(args, Ordering::is_le)
+}
+
 pub fn builtin_equality<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
     let (a, b) = get_args2(args)?;
 
@@ -224,13 +464,34 @@ pub fn builtin_inequality<'file>(args: Vec<Value>) -> Result<Value, RuntimeError
     Ok(Value::Boolean(a != b))
 }
 
-pub fn builtin_indexing<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+/// Position in the underlying vector of an array index, which starts from 1 unless `zero_indexed` is set
+fn array_position(index: f64, zero_indexed: bool) -> Option<usize> {
+    let first = if zero_indexed { 0.0 } else { 1.0 };
+
+    if index.fract() == 0.0 && index >= first {
+        Some((index - first) as usize)
+    }
+    else {
+        None
+    }
+}
+
+fn bad_index_message(index: &Value, zero_indexed: bool) -> String {
+    if zero_indexed {
+        format!("index {} is not a non-negative integer", index)
+    }
+    else {
+        format!("index {} is not a positive integer", index)
+    }
+}
+
+pub fn builtin_indexing<'file>(args: Vec<Value>, zero_indexed: bool) -> Result<Value, RuntimeError<'file>> {
     let (a, b) = get_args2(args)?;
 
     if let Value::Array(array) = a {
-        if let Value::Number(index) = b {
-            if index.fract() == 0.0 && index > 0.0 {
-                if let Some(value) = array.borrow().0.get(index as usize - 1) {
+        if let Some(index) = b.as_number() {
+            if let Some(position) = array_position(index, zero_indexed) {
+                if let Some(value) = array.borrow().0.get(position) {
                     Ok(value.clone())
                 }
                 else {
@@ -238,25 +499,33 @@ pub fn builtin_indexing<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'
                 }
             }
             else {
-                Err(RuntimeError::MessageError(format!("index {} is not a positive integer", b)))
+                Err(RuntimeError::MessageError(bad_index_message(&b, zero_indexed)))
             }
         }
         else {
-            Err(RuntimeError::MessageError(format!("cannot index using type {}", b.get_type_name())))
+            Err(RuntimeError::MessageError(format!("cannot index using type {}", b.type_name_full())))
+        }
+    }
+    else if let Value::Map(map) = a {
+        if let Value::String(key) = &b {
+            map.borrow().get(key).cloned().ok_or_else(|| RuntimeError::MessageError(format!("key '{}' is not in the map", key)))
+        }
+        else {
+            Err(RuntimeError::MessageError(format!("cannot index a map using type {}", b.type_name_full())))
         }
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot index into type {}", a.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot index into type {}", a.type_name_full())))
     }
 }
 
-pub fn builtin_mutable_indexing<'file>(args: Vec<Value>, value_to_assign: Value) -> Result<(), RuntimeError<'file>> {
+pub fn builtin_mutable_indexing<'file>(args: Vec<Value>, value_to_assign: Value, zero_indexed: bool) -> Result<(), RuntimeError<'file>> {
     let (a, b) = get_args2(args)?;
 
     if let Value::Array(array) = a{
-        if let Value::Number(index) = b {
-            if index.fract() == 0.0 && index > 0.0 {
-                if let Some(value) = array.borrow_mut().0.get_mut(index as usize - 1) {
+        if let Some(index) = b.as_number() {
+            if let Some(position) = array_position(index, zero_indexed) {
+                if let Some(value) = array.borrow_mut().0.get_mut(position) {
                     *value = value_to_assign;
                     Ok(())
                 }
@@ -265,15 +534,112 @@ pub fn builtin_mutable_indexing<'file>(args: Vec<Value>, value_to_assign: Value)
                 }
             }
             else {
-                Err(RuntimeError::MessageError(format!("index {} is not a positive integer", b)))
+                Err(RuntimeError::MessageError(bad_index_message(&b, zero_indexed)))
             }
         }
         else {
-            Err(RuntimeError::MessageError(format!("cannot index using type {}", b.get_type_name())))
+            Err(RuntimeError::MessageError(format!("cannot index using type {}", b.type_name_full())))
+        }
+    }
+    else if let Value::Map(map) = a {
+        if let Value::String(key) = b {
+            map.borrow_mut().insert(key, value_to_assign);
+            Ok(())
+        }
+        else {
+            Err(RuntimeError::MessageError(format!("cannot index a map using type {}", b.type_name_full())))
         }
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot index into type {}", a.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot index into type {}", a.type_name_full())))
+    }
+}
+
+/// Add a value to the end of an array
+pub fn builtin_append<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (array, value) = get_args2(args)?;
+
+    if let Value::Array(array) = &array {
+        array.borrow_mut().0.push(value);
+        Ok(Value::None)
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot append to value of type {}", array.type_name_full())))
+    }
+}
+
+/// Remove and return the last value of an array
+pub fn builtin_pop<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let array = get_args1(args)?;
+
+    if let Value::Array(array) = &array {
+        array.borrow_mut().0.pop().ok_or_else(|| RuntimeError::MessageError("cannot pop from an empty array".to_string()))
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot pop from value of type {}", array.type_name_full())))
+    }
+}
+
+/// Insert a value into an array so that it ends up at the given index, which may be one past the end
+pub fn builtin_insert<'file>(args: Vec<Value>, zero_indexed: bool) -> Result<Value, RuntimeError<'file>> {
+    let (array, index, value) = get_args3(args)?;
+
+    if let (Value::Array(array), Some(position)) = (&array, index.as_number()) {
+        let mut array = array.borrow_mut();
+
+        match array_position(position, zero_indexed) {
+            Some(position) if position <= array.0.len() => {
+                array.0.insert(position, value);
+                Ok(Value::None)
+            }
+            Some(_) => Err(RuntimeError::MessageError(format!("index {} is out of bounds", index))),
+            None => Err(RuntimeError::MessageError(bad_index_message(&index, zero_indexed)))
+        }
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot insert into value of type {} at index of type {}", array.type_name_full(), index.type_name_full())))
+    }
+}
+
+/// Remove and return the value at the given index of an array
+pub fn builtin_remove<'file>(args: Vec<Value>, zero_indexed: bool) -> Result<Value, RuntimeError<'file>> {
+    let (array, index) = get_args2(args)?;
+
+    if let (Value::Array(array), Some(position)) = (&array, index.as_number()) {
+        let mut array = array.borrow_mut();
+
+        match array_position(position, zero_indexed) {
+            Some(position) if position < array.0.len() => Ok(array.0.remove(position)),
+            Some(_) => Err(RuntimeError::MessageError(format!("index {} is out of bounds", index))),
+            None => Err(RuntimeError::MessageError(bad_index_message(&index, zero_indexed)))
+        }
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot remove from value of type {} at index of type {}", array.type_name_full(), index.type_name_full())))
+    }
+}
+
+/// Exchange the values at two indices of an array
+pub fn builtin_swap<'file>(args: Vec<Value>, zero_indexed: bool) -> Result<Value, RuntimeError<'file>> {
+    let (array, i, j) = get_args3(args)?;
+
+    if let (Value::Array(array), Some(first), Some(second)) = (&array, i.as_number(), j.as_number()) {
+        let mut array = array.borrow_mut();
+        let length = array.0.len();
+
+        let position = |index: &Value, n: f64| match array_position(n, zero_indexed) {
+            Some(position) if position < length => Ok(position),
+            Some(_) => Err(RuntimeError::MessageError(format!("index {} is out of bounds", index))),
+            None => Err(RuntimeError::MessageError(bad_index_message(index, zero_indexed)))
+        };
+
+        let (first, second) = (position(&i, first)?, position(&j, second)?);
+        array.0.swap(first, second);
+
+        Ok(Value::None)
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot swap elements of value of type {} at indices of type {} and {}", array.type_name_full(), i.type_name_full(), j.type_name_full())))
     }
 }
 
@@ -281,34 +647,49 @@ pub fn builtin_member_access(arg0: Value, member_token: Token<'_>) -> Result<Val
     let member = member_token.extract_text().to_string();
 
     let error_msg = format!("cannot access member '{}' of ", member);
+    let arg0_type = arg0.type_name_full();
     
     match arg0 {
-        Value::Number(_) => 
+        Value::String(s) if member == "length" => 
+        {
+            Ok(Value::Integer(s.chars().count() as i64))
+        },
+        Value::Number(_) | Value::Integer(_) | Value::String(_) => 
         {
-            let error_text = format!("{} number", error_msg);
+            let error_text = format!("{}{}", error_msg, arg0_type);
             Err(GenericError::error(member_token, error_text.clone()).arrow(error_text).into())
         },
         Value::Array(array) => 
         {
             if member == "length" {
-                Ok(Value::Number(array.borrow().0.len() as f64))
+                Ok(Value::Integer(array.borrow().0.len() as i64))
             }
-            else if member == "heapsize" {
-                Ok(array.borrow().1.clone())
+            else if let Some(value) = array.borrow().1.get(&member) {
+                Ok(value.clone())
             }
             else {
-                let error_text = format!("{} none", error_msg);
+                let error_text = format!("{}{}", error_msg, arg0_type);
+                Err(GenericError::error(member_token, error_text.clone()).arrow(error_text).into())
+            }
+        },
+        Value::Map(map) => 
+        {
+            if let Some(value) = map.borrow().get(&member) {
+                Ok(value.clone())
+            }
+            else {
+                let error_text = format!("key '{}' is not in the map", member);
                 Err(GenericError::error(member_token, error_text.clone()).arrow(error_text).into())
             }
         },
         Value::None => 
         {
-            let error_text = format!("{} none", error_msg);
+            let error_text = format!("{}{}", error_msg, arg0_type);
             Err(GenericError::error(member_token, error_text.clone()).arrow(error_text).into())
         },
         Value::Boolean(_) => 
         {
-            let error_text = format!("{} bool", error_msg);
+            let error_text = format!("{}{}", error_msg, arg0_type);
             Err(GenericError::error(member_token, error_text.clone()).arrow(error_text).into())
         }
     }
@@ -318,11 +699,16 @@ pub fn builtin_mutable_member_access(arg0: Value, member_token: Token<'_>, value
     let member = member_token.extract_text().to_string();
 
     let error_msg = format!("cannot access member '{}' of ", member);
+    let arg0_type = arg0.type_name_full();
     
     match arg0 {
-        Value::Number(_) => 
+        Value::String(_) if member == "length" => 
+        {
+            Err(GenericError::error(member_token, "member length of string is immutable".to_string()).arrow("member is immutable".to_string()).into())
+        },
+        Value::Number(_) | Value::Integer(_) | Value::String(_) => 
         {
-            let error_text = format!("{} number", error_msg);
+            let error_text = format!("{}{}", error_msg, arg0_type);
             Err(GenericError::error(member_token, error_text.clone()).arrow(error_text).into())
         },
         Value::Array(array) => 
@@ -330,46 +716,731 @@ pub fn builtin_mutable_member_access(arg0: Value, member_token: Token<'_>, value
             if member == "length" {
                 Err(GenericError::error(member_token, "member length of array is immutable".to_string()).arrow("member is immutable".to_string()).into())
             }
-            else if member == "heapsize" {
-                array.borrow_mut().1 = value;
-                Ok(())
-            }
             else {
-                let error_text = format!("{} none", error_msg);
-                Err(GenericError::error(member_token, error_text.clone()).arrow(error_text).into())
+                array.borrow_mut().1.insert(member, value);
+                Ok(())
             }
         },
+        Value::Map(map) => 
+        {
+            map.borrow_mut().insert(member, value);
+            Ok(())
+        },
         Value::None => 
         {
-            let error_text = format!("{} none", error_msg);
+            let error_text = format!("{}{}", error_msg, arg0_type);
             Err(GenericError::error(member_token, error_text.clone()).arrow(error_text).into())
         },
         Value::Boolean(_) => 
         {
-            let error_text = format!("{} bool", error_msg);
+            let error_text = format!("{}{}", error_msg, arg0_type);
             Err(GenericError::error(member_token, error_text.clone()).arrow(error_text).into())
         }
     }
 }
 
+/// Pick the number furthest towards `direction` out of one or more numbers,
+/// naming `operation` in errors
+fn fold_numbers<'file>(args: Vec<Value>, operation: &str, direction: Ordering) -> Result<Value, RuntimeError<'file>> {
+    if args.is_empty() {
+        return Err(RuntimeError::MessageError("expected at least 1 argument, got 0 arguments".to_string()));
+    }
+
+    let mut result: Option<Value> = None;
+
+    for arg in args {
+        if !arg.is_numeric() {
+            return Err(RuntimeError::MessageError(format!("cannot take {} of value of type {}", operation, arg.type_name_full())));
+        }
+
+        match &result {
+            Some(r) if arg.partial_cmp(r) != Some(direction) => {}
+            _ => result = Some(arg)
+        }
+    }
+
+    Ok(result.unwrap())
+}
+
+pub fn builtin_min<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    fold_numbers(args, "min", Ordering::Less)
+}
+
+pub fn builtin_max<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    fold_numbers(args, "max", Ordering::Greater)
+}
+
 pub fn builtin_floor<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
     let v = get_args1(args)?;
 
-    if let Value::Number(v) = v {
+    if let Value::Integer(_) = v {
+        Ok(v)
+    }
+    else if let Value::Number(v) = v {
         Ok(Value::Number(v.floor()))
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot take floor of type {}", v.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot take floor of type {}", v.type_name_full())))
     }
 }
 
 pub fn builtin_ceil<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
     let v = get_args1(args)?;
 
-    if let Value::Number(v) = v {
+    if let Value::Integer(_) = v {
+        Ok(v)
+    }
+    else if let Value::Number(v) = v {
         Ok(Value::Number(v.ceil()))
     }
     else {
-        Err(RuntimeError::MessageError(format!("cannot take ceiling of type {}", v.get_type_name())))
+        Err(RuntimeError::MessageError(format!("cannot take ceiling of type {}", v.type_name_full())))
     }
-}
\ No newline at end of file
+}
+
+pub fn builtin_abs<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let v = get_args1(args)?;
+
+    if let Value::Integer(v) = v {
+        Ok(v.checked_abs().map_or(Value::Number((v as f64).abs()), Value::Integer))
+    }
+    else if let Value::Number(v) = v {
+        Ok(Value::Number(v.abs()))
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot take absolute value of type {}", v.type_name_full())))
+    }
+}
+
+pub fn builtin_sqrt<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let v = get_args1(args)?;
+
+    if let Some(v) = v.as_number() {
+        if v < 0.0 {
+            Err(RuntimeError::MessageError(format!("cannot take square root of negative number {}", v)))
+        }
+        else {
+            Ok(Value::Number(v.sqrt()))
+        }
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot take square root of type {}", v.type_name_full())))
+    }
+}
+
+/// Round to the nearest integer, with halves rounded away from zero
+pub fn builtin_round<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let v = get_args1(args)?;
+
+    if let Value::Integer(_) = v {
+        Ok(v)
+    }
+    else if let Value::Number(v) = v {
+        Ok(Value::Number(v.round()))
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot round value of type {}", v.type_name_full())))
+    }
+}
+
+pub fn builtin_truncate<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let v = get_args1(args)?;
+
+    if let Value::Integer(_) = v {
+        Ok(v)
+    }
+    else if let Value::Number(v) = v {
+        Ok(Value::Number(v.trunc()))
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot truncate value of type {}", v.type_name_full())))
+    }
+}
+
+/// The text of any value, as it would be printed
+pub fn builtin_str<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let v = get_args1(args)?;
+
+    Ok(Value::String(v.to_string()))
+}
+
+/// Parse a string as a number, or as an integer if it is written without a
+/// fractional part, numbers are returned unchanged
+pub fn builtin_num<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let v = get_args1(args)?;
+
+    match &v {
+        Value::Number(_) | Value::Integer(_) => Ok(v),
+        Value::String(s) => {
+            if let Ok(n) = s.trim().parse::<i64>() {
+                return Ok(Value::Integer(n));
+            }
+
+            match s.trim().parse::<f64>() {
+                Ok(n) if n.is_finite() => Ok(Value::Number(n)),
+                _ => Err(RuntimeError::MessageError(format!("cannot convert string {:?} to a number", s)))
+            }
+        }
+        _ => Err(RuntimeError::MessageError(format!("cannot convert value of type {} to a number", v.type_name_full())))
+    }
+}
+
+/// Convert a number to an integer, dropping any fractional part
+pub fn builtin_int<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let v = get_args1(args)?;
+
+    match v {
+        Value::Integer(_) => Ok(v),
+        Value::Number(n) if n.is_finite() && n.trunc().abs() < i64::MAX as f64 => Ok(Value::Integer(n.trunc() as i64)),
+        Value::Number(n) => Err(RuntimeError::MessageError(format!("cannot convert {} to an integer", n))),
+        _ => Err(RuntimeError::MessageError(format!("cannot convert value of type {} to an integer", v.type_name_full())))
+    }
+}
+
+pub fn builtin_type<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let v = get_args1(args)?;
+
+    Ok(Value::String(v.get_type_name().to_string()))
+}
+
+fn get_floor_division_args<'file>(a: &Value, b: &Value, operation: &str) -> Result<(f64, f64), RuntimeError<'file>> {
+    if let (Some(a), Some(b)) = (a.as_number(), b.as_number()) {
+        if b == 0.0 {
+            Err(RuntimeError::MessageError("division by zero".to_string()))
+        }
+        else {
+            Ok((a, b))
+        }
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot {} values of type {} and {}", operation, a.type_name_full(), b.type_name_full())))
+    }
+}
+
+/// Raise the first number to the power of the second
+pub fn builtin_pow<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (a, b) = get_args2(args)?;
+
+    if let (Value::Integer(a), Value::Integer(b)) = (&a, &b) {
+        if let Some(v) = u32::try_from(*b).ok().and_then(|b| a.checked_pow(b)) {
+            return Ok(Value::Integer(v));
+        }
+    }
+
+    if let (Some(a), Some(b)) = (a.as_number(), b.as_number()) {
+        Ok(Value::Number(a.powf(b)))
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot exponentiate values of type {} and {}", a.type_name_full(), b.type_name_full())))
+    }
+}
+
+/// Remainder of integer division, taking the sign of the divisor as `DivMod` does
+pub fn builtin_mod<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (a, b) = get_args2(args)?;
+    let (a, b) = (get_integer(&a, "take modulo of")?, get_integer(&b, "take modulo by")?);
+
+    if b == 0 {
+        return Err(RuntimeError::MessageError("division by zero".to_string()));
+    }
+
+    // Only `i64::MIN % -1` overflows, and any integer divided by -1 leaves nothing
+    let remainder = a.checked_rem(b).unwrap_or(0);
+
+    if remainder != 0 && (remainder < 0) != (b < 0) {
+        Ok(Value::Integer(remainder + b))
+    }
+    else {
+        Ok(Value::Integer(remainder))
+    }
+}
+
+/// Quotient and remainder of two integers with the quotient rounded down, or
+/// `None` if either value is not an integer or the quotient does not fit in one
+fn floor_divide_integers(a: &Value, b: &Value) -> Option<(i64, i64)> {
+    if let (Value::Integer(a), Value::Integer(b)) = (a, b) {
+        let (mut quotient, mut remainder) = (a.checked_div(*b)?, a.checked_rem(*b)?);
+
+        if remainder != 0 && (remainder < 0) != (*b < 0) {
+            quotient -= 1;
+            remainder += b;
+        }
+
+        Some((quotient, remainder))
+    }
+    else {
+        None
+    }
+}
+
+pub fn builtin_floor_divide<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (a, b) = get_args2(args)?;
+    let (x, y) = get_floor_division_args(&a, &b, "floor divide")?;
+
+    if let Some((quotient, _)) = floor_divide_integers(&a, &b) {
+        return Ok(Value::Integer(quotient));
+    }
+
+    Ok(Value::Number((x / y).floor()))
+}
+
+pub fn builtin_divmod<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (a, b) = get_args2(args)?;
+    let (x, y) = get_floor_division_args(&a, &b, "divmod")?;
+
+    if let Some((quotient, remainder)) = floor_divide_integers(&a, &b) {
+        return builtin_array(vec![Value::Integer(quotient), Value::Integer(remainder)]);
+    }
+
+    let quotient = (x / y).floor();
+
+    builtin_array(vec![Value::Number(quotient), Value::Number(x - y * quotient)])
+}
+
+pub fn builtin_substring<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (s, start, length) = get_args3(args)?;
+
+    if let (Value::String(s), Some(start), Some(length)) = (&s, start.as_number(), length.as_number()) {
+        if start.fract() != 0.0 || length.fract() != 0.0 {
+            return Err(RuntimeError::MessageError("substring start and length must be integers".to_string()));
+        }
+
+        let count = s.chars().count() as f64;
+        if start < 1.0 || length < 0.0 || start - 1.0 + length > count {
+            return Err(RuntimeError::MessageError(format!("substring of length {} at {} is out of bounds for a string of length {}", length, start, count)));
+        }
+
+        Ok(Value::String(s.chars().skip(start as usize - 1).take(length as usize).collect()))
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot take substring of type {} using {} and {}", s.type_name_full(), start.type_name_full(), length.type_name_full())))
+    }
+}
+
+pub fn builtin_split<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (s, delimiter) = get_args2(args)?;
+
+    if let (Value::String(s), Value::String(delimiter)) = (&s, &delimiter) {
+        if delimiter.is_empty() {
+            return Err(RuntimeError::MessageError("cannot split using an empty delimiter".to_string()));
+        }
+
+        builtin_array(s.split(delimiter.as_str()).map(|part| Value::String(part.to_string())).collect())
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot split value of type {} by {}", s.type_name_full(), delimiter.type_name_full())))
+    }
+}
+
+pub fn builtin_join<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (array, separator) = get_args2(args)?;
+
+    if let (Value::Array(array), Value::String(separator)) = (&array, &separator) {
+        let mut parts = Vec::new();
+
+        for v in &array.borrow().0 {
+            if let Value::String(part) = v {
+                parts.push(part.clone());
+            }
+            else {
+                return Err(RuntimeError::MessageError(format!("cannot join array containing {}", v.type_name_full())));
+            }
+        }
+
+        Ok(Value::String(parts.join(separator)))
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot join value of type {} with {}", array.type_name_full(), separator.type_name_full())))
+    }
+}
+
+/// Convert a value to an integer for the number theory builtins, rejecting anything that is not a whole number
+pub(crate) fn get_integer<'file>(v: &Value, operation: &str) -> Result<i64, RuntimeError<'file>> {
+    match v {
+        Value::Integer(n) => Ok(*n),
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Ok(*n as i64),
+        Value::Number(n) => Err(RuntimeError::MessageError(format!("cannot {} non-integer {}", operation, n))),
+        _ => Err(RuntimeError::MessageError(format!("cannot {} value of type {}", operation, v.type_name_full())))
+    }
+}
+
+pub fn builtin_mod_exp<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (base, exponent, modulus) = get_args3(args)?;
+    let (base, mut exponent, modulus) = (get_integer(&base, "exponentiate")?, get_integer(&exponent, "exponentiate")?, get_integer(&modulus, "take modulus of")?);
+
+    if modulus <= 0 {
+        return Err(RuntimeError::MessageError(format!("modulus {} is not positive", modulus)));
+    }
+    if exponent < 0 {
+        return Err(RuntimeError::MessageError(format!("exponent {} is negative", exponent)));
+    }
+
+    // Repeated squaring, in i128 so the products cannot overflow
+    let modulus = modulus as i128;
+    let mut base = (base as i128).rem_euclid(modulus);
+    let mut result = 1 % modulus;
+
+    while exponent > 0 {
+        if exponent % 2 == 1 {
+            result = result * base % modulus;
+        }
+        base = base * base % modulus;
+        exponent /= 2;
+    }
+
+    Ok(Value::Integer(result as i64))
+}
+
+pub fn builtin_gcd<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (a, b) = get_args2(args)?;
+    let (mut a, mut b) = (get_integer(&a, "take gcd of")?.unsigned_abs(), get_integer(&b, "take gcd of")?.unsigned_abs());
+
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+
+    Ok(Value::Integer(a as i64))
+}
+
+/// EXTENDED-EUCLID from CLRS, giving `(d, x, y)` where `d = gcd(a, b) = ax + by`
+fn extended_euclid(a: i64, b: i64) -> (i64, i64, i64) {
+    if b == 0 {
+        (a, 1, 0)
+    }
+    else {
+        let (d, x, y) = extended_euclid(b, a % b);
+        (d, y, x - (a / b) * y)
+    }
+}
+
+pub fn builtin_extended_gcd<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (a, b) = get_args2(args)?;
+    let (a, b) = (get_integer(&a, "take extended gcd of")?, get_integer(&b, "take extended gcd of")?);
+
+    if a < 0 || b < 0 {
+        return Err(RuntimeError::MessageError(format!("cannot take extended gcd of negative values {} and {}", a, b)));
+    }
+
+    let (d, x, y) = extended_euclid(a, b);
+
+    builtin_array(vec![Value::Integer(d), Value::Integer(x), Value::Integer(y)])
+}
+
+pub fn builtin_bitwise_and<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (a, b) = get_args2(args)?;
+    Ok(Value::Integer(get_integer(&a, "bitwise and")? & get_integer(&b, "bitwise and")?))
+}
+
+pub fn builtin_bitwise_or<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (a, b) = get_args2(args)?;
+    Ok(Value::Integer(get_integer(&a, "bitwise or")? | get_integer(&b, "bitwise or")?))
+}
+
+pub fn builtin_bitwise_xor<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (a, b) = get_args2(args)?;
+    Ok(Value::Integer(get_integer(&a, "bitwise xor")? ^ get_integer(&b, "bitwise xor")?))
+}
+
+pub fn builtin_bitwise_not<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let a = get_args1(args)?;
+    Ok(Value::Integer(!get_integer(&a, "bitwise not")?))
+}
+
+fn get_shift_amount<'file>(v: &Value) -> Result<u32, RuntimeError<'file>> {
+    let n = get_integer(v, "shift by")?;
+
+    if (0..64).contains(&n) {
+        Ok(n as u32)
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot shift by {}, shift amounts must be between 0 and 63", n)))
+    }
+}
+
+pub fn builtin_shift_left<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (a, n) = get_args2(args)?;
+    Ok(Value::Integer(get_integer(&a, "shift")? << get_shift_amount(&n)?))
+}
+
+pub fn builtin_shift_right<'file>(args: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
+    let (a, n) = get_args2(args)?;
+    Ok(Value::Integer(get_integer(&a, "shift")? >> get_shift_amount(&n)?))
+}
+
+/// The step of a `for ... by` loop, which counts in the direction given by
+/// `down` and so must be a positive integer
+pub fn loop_step<'file>(step: &Value) -> Result<i64, RuntimeError<'file>> {
+    let n = get_integer(step, "step by")?;
+
+    if n > 0 {
+        Ok(n)
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot step by {}, loop steps must be positive", n)))
+    }
+}
+
+/// The number of elements a `for ... in` loop will visit, which is only defined for arrays
+pub fn iteration_length<'file>(value: &Value) -> Result<usize, RuntimeError<'file>> {
+    if let Value::Array(array) = value {
+        Ok(array.borrow().0.len())
+    }
+    else {
+        Err(RuntimeError::MessageError(format!("cannot iterate over value of type {}", value.type_name_full())))
+    }
+}
+
+/// The element visited by a `for ... in` loop at the zero based position `i`
+pub fn iteration_item<'file>(value: &Value, i: usize) -> Result<Value, RuntimeError<'file>> {
+    match value {
+        Value::Array(array) => array.borrow().0.get(i).cloned()
+            .ok_or_else(|| RuntimeError::MessageError(format!("array shrank to {} elements while being iterated over", array.borrow().0.len()))),
+        _ => Err(RuntimeError::MessageError(format!("cannot iterate over value of type {}", value.type_name_full())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn integers(values: &[i64]) -> Value {
+        builtin_array(values.iter().map(|v| Value::Integer(*v)).collect()).unwrap()
+    }
+
+    #[test]
+    fn xor_of_booleans() {
+        for (a, b, expected) in [(true, true, false), (true, false, true), (false, true, true), (false, false, false)] {
+            assert_eq!(builtin_logical_xor(vec![Value::Boolean(a), Value::Boolean(b)]).unwrap(), Value::Boolean(expected));
+        }
+    }
+
+    #[test]
+    fn xor_of_other_types() {
+        match builtin_logical_xor(vec![Value::Boolean(true), Value::Integer(1)]) {
+            Err(RuntimeError::MessageError(message)) => assert_eq!(message, "cannot xor values of type Boolean and Integer"),
+            other => panic!("expected a type error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn floor_division_rounds_down() {
+        assert_eq!(builtin_floor_divide(vec![Value::Integer(7), Value::Integer(2)]).unwrap(), Value::Integer(3));
+        assert_eq!(builtin_floor_divide(vec![Value::Integer(-7), Value::Integer(2)]).unwrap(), Value::Integer(-4));
+        assert_eq!(builtin_floor_divide(vec![Value::Number(7.5), Value::Integer(2)]).unwrap(), Value::Number(3.0));
+        assert!(builtin_floor_divide(vec![Value::Integer(7), Value::Integer(0)]).is_err());
+    }
+
+    #[test]
+    fn modulo_takes_the_sign_of_the_divisor() {
+        assert_eq!(call(builtin_mod, &[7, 3]), Value::Integer(1));
+        assert_eq!(call(builtin_mod, &[-7, 3]), Value::Integer(2));
+        assert_eq!(call(builtin_mod, &[7, -3]), Value::Integer(-2));
+        assert_eq!(call(builtin_mod, &[i64::MIN, -1]), Value::Integer(0));
+        assert!(builtin_mod(vec![Value::Integer(7), Value::Integer(0)]).is_err());
+    }
+
+    #[test]
+    fn divmod_returns_quotient_and_remainder() {
+        assert_eq!(builtin_divmod(vec![Value::Integer(7), Value::Integer(3)]).unwrap(), integers(&[2, 1]));
+        assert_eq!(builtin_divmod(vec![Value::Integer(-7), Value::Integer(3)]).unwrap(), integers(&[-3, 2]));
+    }
+
+    fn strings(values: &[&str]) -> Value {
+        builtin_array(values.iter().map(|v| Value::String(v.to_string())).collect()).unwrap()
+    }
+
+    #[test]
+    fn split_and_join() {
+        let parts = builtin_split(vec![Value::String("a,b,c".to_string()), Value::String(",".to_string())]).unwrap();
+        assert_eq!(parts, strings(&["a", "b", "c"]));
+
+        let joined = builtin_join(vec![parts, Value::String(",".to_string())]).unwrap();
+        assert_eq!(joined, Value::String("a,b,c".to_string()));
+
+        assert!(builtin_split(vec![Value::String("a,b,c".to_string()), Value::String(String::new())]).is_err());
+        assert!(builtin_join(vec![integers(&[1, 2]), Value::String(",".to_string())]).is_err());
+    }
+
+    fn call(builtin: fn(Vec<Value>) -> Result<Value, RuntimeError<'static>>, args: &[i64]) -> Value {
+        builtin(args.iter().map(|a| Value::Integer(*a)).collect()).unwrap()
+    }
+
+    #[test]
+    fn modular_exponentiation() {
+        assert_eq!(call(builtin_mod_exp, &[3, 12, 17]), Value::Integer(4));
+        assert_eq!(call(builtin_mod_exp, &[2, 10, 1000]), Value::Integer(24));
+
+        // Would overflow if the power were taken first
+        assert_eq!(call(builtin_mod_exp, &[123456789, 987654321, 1000000007]), Value::Integer(652541198));
+        assert!(builtin_mod_exp(vec![Value::Number(2.5), Value::Integer(2), Value::Integer(7)]).is_err());
+    }
+
+    #[test]
+    fn greatest_common_divisor() {
+        assert_eq!(call(builtin_gcd, &[30, 21]), Value::Integer(3));
+        assert_eq!(call(builtin_gcd, &[7, 0]), Value::Integer(7));
+        assert_eq!(call(builtin_extended_gcd, &[30, 21]), integers(&[3, -2, 3]));
+        assert_eq!(call(builtin_extended_gcd, &[99, 78]), integers(&[3, -11, 14]));
+        assert!(builtin_gcd(vec![Value::Number(1.5), Value::Integer(2)]).is_err());
+    }
+
+    #[test]
+    fn bitwise_operations() {
+        assert_eq!(call(builtin_bitwise_and, &[12, 10]), Value::Integer(8));
+        assert_eq!(call(builtin_bitwise_or, &[12, 10]), Value::Integer(14));
+        assert_eq!(call(builtin_bitwise_xor, &[12, 10]), Value::Integer(6));
+        assert_eq!(call(builtin_bitwise_not, &[12]), Value::Integer(-13));
+        assert_eq!(call(builtin_shift_left, &[1, 3]), Value::Integer(8));
+        assert_eq!(call(builtin_shift_right, &[12, 2]), Value::Integer(3));
+
+        assert!(builtin_bitwise_and(vec![Value::Number(1.5), Value::Integer(2)]).is_err());
+        assert!(builtin_shift_left(vec![Value::Integer(1), Value::Integer(-1)]).is_err());
+    }
+
+    #[test]
+    fn comparisons_with_nan_are_false() {
+        let nan = Value::Number(f64::NAN);
+
+        for compare in [builtin_less_than, builtin_greater_than, builtin_less_than_equal, builtin_greater_than_equal] {
+            assert_eq!(compare(vec![nan.clone(), Value::Integer(1)]).unwrap(), Value::Boolean(false));
+            assert_eq!(compare(vec![Value::Number(1.0), nan.clone()]).unwrap(), Value::Boolean(false));
+            assert_eq!(compare(vec![nan.clone(), nan.clone()]).unwrap(), Value::Boolean(false));
+        }
+
+        assert!(builtin_less_than(vec![nan.clone(), Value::String("a".to_string())]).is_err());
+
+        match builtin_sort(vec![builtin_array(vec![Value::Integer(2), nan, Value::Integer(1)]).unwrap()]) {
+            Err(RuntimeError::MessageError(message)) => assert_eq!(message, "cannot sort an array containing NaN"),
+            other => panic!("expected sorting NaN to fail, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn min_and_max() {
+        assert_eq!(call(builtin_max, &[3, 1, 4, 1, 5, 9]), Value::Integer(9));
+        assert_eq!(call(builtin_min, &[3, 1, 4, 1, 5, 9]), Value::Integer(1));
+        assert!(builtin_max(vec![]).is_err());
+        assert!(builtin_max(vec![Value::Integer(1), Value::String("2".to_string())]).is_err());
+    }
+
+    #[test]
+    fn rounding_and_roots() {
+        assert_eq!(call(builtin_abs, &[-5]), Value::Integer(5));
+        assert_eq!(builtin_abs(vec![Value::Number(-2.5)]).unwrap(), Value::Number(2.5));
+        assert_eq!(call(builtin_sqrt, &[4]), Value::Number(2.0));
+        assert_eq!(builtin_round(vec![Value::Number(2.5)]).unwrap(), Value::Number(3.0));
+        assert_eq!(builtin_round(vec![Value::Number(-2.5)]).unwrap(), Value::Number(-3.0));
+        assert_eq!(builtin_truncate(vec![Value::Number(-2.7)]).unwrap(), Value::Number(-2.0));
+
+        match builtin_sqrt(vec![Value::Integer(-1)]) {
+            Err(RuntimeError::MessageError(message)) => assert_eq!(message, "cannot take square root of negative number -1"),
+            other => panic!("expected the square root of -1 to fail, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn swap_exchanges_elements() {
+        let array = integers(&[1, 2, 3]);
+
+        assert_eq!(builtin_swap(vec![array.clone(), Value::Integer(1), Value::Integer(2)], false).unwrap(), Value::None);
+        assert_eq!(array, integers(&[2, 1, 3]));
+
+        builtin_swap(vec![array.clone(), Value::Integer(0), Value::Integer(2)], true).unwrap();
+        assert_eq!(array, integers(&[3, 1, 2]));
+
+        let error = builtin_swap(vec![array.clone(), Value::Integer(1), Value::Integer(4)], false).unwrap_err();
+        assert!(matches!(error, RuntimeError::MessageError(message) if message == "index 4 is out of bounds"));
+    }
+
+    fn map(entries: &[(&str, i64)]) -> Value {
+        Value::new_map(entries.iter().map(|(k, v)| (k.to_string(), Value::Integer(*v))).collect())
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn contains_of_arrays_strings_and_maps() {
+        let contains = |container: Value, element: Value| builtin_contains(vec![container, element]).unwrap();
+
+        assert_eq!(contains(integers(&[1, 2, 3]), Value::Integer(2)), Value::Boolean(true));
+        assert_eq!(contains(integers(&[1, 2, 3]), Value::Number(2.0)), Value::Boolean(true));
+        assert_eq!(contains(integers(&[1, 2, 3]), Value::Integer(4)), Value::Boolean(false));
+        assert_eq!(contains(strings(&["a", "b"]), string("b")), Value::Boolean(true));
+
+        assert_eq!(contains(string("pseudocode"), string("code")), Value::Boolean(true));
+        assert_eq!(contains(string("pseudocode"), string("Code")), Value::Boolean(false));
+
+        assert_eq!(contains(map(&[("a", 1)]), string("a")), Value::Boolean(true));
+        assert_eq!(contains(map(&[("a", 1)]), string("b")), Value::Boolean(false));
+
+        let error = builtin_contains(vec![Value::Integer(1), Value::Integer(1)]).unwrap_err();
+        assert!(matches!(error, RuntimeError::MessageError(message) if message == "cannot check whether type Integer contains type Integer"));
+    }
+
+    #[test]
+    fn has_key_of_maps() {
+        assert_eq!(builtin_has_key(vec![map(&[("a", 1)]), string("a")]).unwrap(), Value::Boolean(true));
+        assert_eq!(builtin_has_key(vec![map(&[("a", 1)]), string("b")]).unwrap(), Value::Boolean(false));
+
+        let error = builtin_has_key(vec![map(&[]), Value::Integer(1)]).unwrap_err();
+        assert!(matches!(error, RuntimeError::MessageError(message) if message == "map keys must be strings, got Integer"));
+
+        let error = builtin_has_key(vec![integers(&[1]), string("a")]).unwrap_err();
+        assert!(matches!(error, RuntimeError::MessageError(message) if message.ends_with("HasKey expects a map")));
+    }
+
+    /// Set the element at zero based `index` of an array to `value`
+    fn set_element(array: &Value, index: usize, value: Value) {
+        if let Value::Array(array) = array {
+            array.borrow_mut().0[index] = value;
+        }
+    }
+
+    #[test]
+    fn modifying_a_copy_leaves_the_original() {
+        let original = integers(&[1, 2, 3]);
+        let copy = builtin_copy(vec![original.clone()]).unwrap();
+
+        set_element(&copy, 0, Value::Integer(10));
+        builtin_append(vec![copy.clone(), Value::Integer(4)]).unwrap();
+        assert_eq!(original, integers(&[1, 2, 3]));
+        assert_eq!(copy, integers(&[10, 2, 3, 4]));
+
+        let original = map(&[("a", 1)]);
+        let copy = builtin_copy(vec![original.clone()]).unwrap();
+        if let Value::Map(copy) = &copy {
+            copy.borrow_mut().insert("b".to_string(), Value::Integer(2));
+        }
+        assert_eq!(builtin_has_key(vec![original, string("b")]).unwrap(), Value::Boolean(false));
+    }
+
+    #[test]
+    fn copy_is_shallow_and_deep_clone_is_not() {
+        let inner = integers(&[1]);
+        let outer = builtin_array(vec![inner.clone()]).unwrap();
+
+        let shallow = builtin_copy(vec![outer.clone()]).unwrap();
+        let deep = outer.deep_clone();
+        set_element(&inner, 0, Value::Integer(2));
+
+        assert_eq!(shallow, builtin_array(vec![integers(&[2])]).unwrap());
+        assert_eq!(deep, builtin_array(vec![integers(&[1])]).unwrap());
+    }
+
+    #[test]
+    fn ranges_of_consecutive_integers() {
+        let range = |args: &[i64], zero_indexed| builtin_range(args.iter().map(|a| Value::Integer(*a)).collect(), zero_indexed).unwrap();
+
+        assert_eq!(range(&[3], false), builtin_array(vec![Value::Integer(1), Value::Integer(2), Value::Integer(3)]).unwrap());
+        assert_eq!(range(&[3], true), integers(&[0, 1, 2]));
+        assert_eq!(range(&[2, 5], false), integers(&[2, 3, 4, 5]));
+        assert_eq!(range(&[1, 10, 4], false), integers(&[1, 5, 9]));
+        assert_eq!(range(&[5, 1, -2], false), integers(&[5, 3, 1]));
+        assert_eq!(range(&[0], false), integers(&[]));
+
+        assert!(builtin_range(vec![Value::Integer(1), Value::Integer(5), Value::Integer(0)], false).is_err());
+        assert!(builtin_range(vec![], false).is_err());
+    }
+}
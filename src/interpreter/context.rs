@@ -1,28 +1,124 @@
-use std::{collections::HashMap, rc::Rc, cell::RefCell};
+use std::{collections::HashMap, rc::Rc, cell::RefCell, time::{Duration, Instant}};
 
-use crate::{parser::ParseTreeNode, tokenizer::Token, error::GenericError};
+use crate::{parser::ParseTreeNode, tokenizer::Token, error::GenericError, util::closest_match};
 
-use super::{Function, Value, RuntimeError};
+use super::{Function, Value, RuntimeError, InputSource, OutputSink, NativeFunctions};
 
+/// Deepest nesting of function calls allowed before execution is stopped
+pub const DEFAULT_MAX_RECURSION_DEPTH: usize = 1000;
+
+/// Each pseudocode call recurses on the Rust stack, using a few hundred KiB in
+/// debug builds. When less than this is left the call is made on a new stack of
+/// `STACK_SEGMENT_SIZE` bytes, so the depth limit is reached before the stack of
+/// whichever thread is running the program overflows.
+const STACK_RED_ZONE: usize = 1024 * 1024;
+const STACK_SEGMENT_SIZE: usize = 16 * 1024 * 1024;
 
 #[derive(Debug, Clone)]
 pub struct RunTime<'file> {
-    functions: HashMap<String, Function<'file>>
+    functions: HashMap<String, Function<'file>>,
+    /// Number of statements executed, checked against `max_steps` and `deadline`
+    pub steps_executed: u64,
+    pub max_steps: Option<u64>,
+    deadline: Option<Instant>,
+    /// Index arrays from 0 rather than 1
+    pub zero_indexed: bool,
+    /// Lines read by `Input`, shared by every executor using this runtime
+    pub input: InputSource,
+    /// Where `Print` writes to, shared by every executor using this runtime
+    pub output: OutputSink,
+    /// Number of function calls currently being executed, limited to `max_depth`
+    depth: usize,
+    max_depth: usize,
+    /// Functions registered by the embedding code, shared by every executor using this runtime
+    pub native_functions: NativeFunctions
 }
 
 impl<'file> RunTime<'file> {
     pub fn new(parsed_functions: Vec<ParseTreeNode<'file>>) -> Self {
-        let mut functions = HashMap::new();
+        let mut result = Self {
+            functions: HashMap::new(),
+            steps_executed: 0,
+            max_steps: None,
+            deadline: None,
+            zero_indexed: false,
+            input: InputSource::default(),
+            output: OutputSink::default(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_RECURSION_DEPTH,
+            native_functions: NativeFunctions::default()
+        };
 
         for func in parsed_functions {
-            let func = Function::new(func);
+            result.add_function_node(func);
+        }
+
+        result
+    }
 
-            functions.insert(func.name.clone(), func);
+    /// Add a parsed function and any functions defined inside it to the runtime,
+    /// replacing any existing functions with the same names
+    pub fn add_function_node(&mut self, node: ParseTreeNode<'file>) {
+        let mut functions = Vec::new();
+        node.for_each_function(&mut |f| functions.push(Function::new(f.clone())));
+
+        for func in functions {
+            self.functions.insert(func.name.clone(), func);
         }
+    }
+
+    /// Stop execution with an error once more than `n` function calls are nested
+    pub fn set_max_recursion_depth(&mut self, n: usize) {
+        self.max_depth = n;
+    }
+
+    /// Make `function` callable from pseudocode as `name`. Builtins take priority
+    /// over native functions, which take priority over functions in the program.
+    pub fn register_native_function(&mut self, name: &str, function: impl Fn(Vec<Value>) -> Result<Value, RuntimeError<'static>> + 'static) {
+        self.native_functions.register(name, function);
+    }
+
+    /// The defined or builtin function with the name closest to `name`, if any is close enough
+    fn suggest_function(&self, name: &str) -> Option<String> {
+        closest_match(name, self.functions.keys().map(|k| k.as_str()).chain(super::BUILTIN_NAMES.iter().copied())).map(|s| s.to_string())
+    }
+
+    /// Names of the arguments taken by the function `name`, if it is defined
+    pub fn function_argument_names(&self, name: &str) -> Option<Vec<&str>> {
+        self.functions.get(name).map(|f| f.arguments.iter().map(|a| a.extract_text().as_ref()).collect())
+    }
 
-        Self {
-            functions
+    /// Count a single executed statement, failing once the step limit or deadline is exceeded
+    pub fn step(&mut self) -> Result<(), RuntimeError<'file>> {
+        self.steps_executed += 1;
+
+        if let Some(max_steps) = self.max_steps {
+            if self.steps_executed > max_steps {
+                return Err(RuntimeError::MessageError(format!("execution exceeded the limit of {} steps", max_steps)));
+            }
         }
+
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(RuntimeError::MessageError(format!("execution timed out after {} steps", self.steps_executed)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Execute the function `entry`, stopping with an error if it runs for longer than `timeout`
+    pub fn execute_with_timeout(runtime: Rc<RefCell<Self>>, entry: String, arguments: Vec<Value>, timeout: Duration) -> Result<Option<Value>, RuntimeError<'file>> {
+        {
+            let mut runtime = runtime.borrow_mut();
+            runtime.steps_executed = 0;
+            runtime.deadline = Some(Instant::now() + timeout);
+        }
+
+        let result = Self::inner_execute_function(runtime.clone(), entry, arguments);
+        runtime.borrow_mut().deadline = None;
+
+        result
     }
 
     pub fn execute_function(runtime: Rc<RefCell<Self>>, func_name: &Token<'file>, arguments: Vec<Value>) -> Result<Value, RuntimeError<'file>> {
@@ -38,7 +134,8 @@ impl<'file> RunTime<'file> {
             return super::builtin_array_create(arguments);
         }
         else if name == "Print" {
-            return super::builtin_print(arguments);
+            let output = runtime.borrow().output.clone();
+            return super::builtin_print(arguments, &output);
         }
         else if name == "ceil" {
             return super::builtin_ceil(arguments);
@@ -46,26 +143,252 @@ impl<'file> RunTime<'file> {
         else if name == "floor" {
             return super::builtin_floor(arguments);
         }
+        else if name == "FloorDiv" {
+            return super::builtin_floor_divide(arguments);
+        }
+        else if name == "DivMod" {
+            return super::builtin_divmod(arguments);
+        }
+        else if name == "Substring" {
+            return super::builtin_substring(arguments);
+        }
+        else if name == "Split" {
+            return super::builtin_split(arguments);
+        }
+        else if name == "Join" {
+            return super::builtin_join(arguments);
+        }
+        else if name == "ModExp" {
+            return super::builtin_mod_exp(arguments);
+        }
+        else if name == "Gcd" {
+            return super::builtin_gcd(arguments);
+        }
+        else if name == "ExtendedGcd" {
+            return super::builtin_extended_gcd(arguments);
+        }
+        else if name == "BitAnd" {
+            return super::builtin_bitwise_and(arguments);
+        }
+        else if name == "BitOr" {
+            return super::builtin_bitwise_or(arguments);
+        }
+        else if name == "BitXor" {
+            return super::builtin_bitwise_xor(arguments);
+        }
+        else if name == "BitNot" {
+            return super::builtin_bitwise_not(arguments);
+        }
+        else if name == "SHL" {
+            return super::builtin_shift_left(arguments);
+        }
+        else if name == "SHR" {
+            return super::builtin_shift_right(arguments);
+        }
+        else if name == "Map" {
+            return super::builtin_map(arguments);
+        }
+        else if name == "Keys" {
+            return super::builtin_keys(arguments);
+        }
+        else if name == "Values" {
+            return super::builtin_values(arguments);
+        }
+        else if name == "Sort" {
+            return super::builtin_sort(arguments);
+        }
+        else if name == "Append" {
+            return super::builtin_append(arguments);
+        }
+        else if name == "Pop" {
+            return super::builtin_pop(arguments);
+        }
+        else if name == "min" {
+            return super::builtin_min(arguments);
+        }
+        else if name == "max" {
+            return super::builtin_max(arguments);
+        }
+        else if name == "abs" {
+            return super::builtin_abs(arguments);
+        }
+        else if name == "sqrt" {
+            return super::builtin_sqrt(arguments);
+        }
+        else if name == "round" {
+            return super::builtin_round(arguments);
+        }
+        else if name == "truncate" {
+            return super::builtin_truncate(arguments);
+        }
+        else if name == "str" {
+            return super::builtin_str(arguments);
+        }
+        else if name == "num" {
+            return super::builtin_num(arguments);
+        }
+        else if name == "type" {
+            return super::builtin_type(arguments);
+        }
+        else if name == "Contains" {
+            return super::builtin_contains(arguments);
+        }
+        else if name == "HasKey" {
+            return super::builtin_has_key(arguments);
+        }
+        else if name == "Copy" {
+            return super::builtin_copy(arguments);
+        }
+        else if name == "int" {
+            return super::builtin_int(arguments);
+        }
+        else if name == "Input" {
+            let (input, output) = (runtime.borrow().input.clone(), runtime.borrow().output.clone());
+            return super::builtin_input(arguments, &input, &output);
+        }
+        else if name == "Insert" {
+            return super::builtin_insert(arguments, runtime.borrow().zero_indexed);
+        }
+        else if name == "Remove" {
+            return super::builtin_remove(arguments, runtime.borrow().zero_indexed);
+        }
+        else if name == "Swap" {
+            return super::builtin_swap(arguments, runtime.borrow().zero_indexed);
+        }
+        else if name == "Range" {
+            return super::builtin_range(arguments, runtime.borrow().zero_indexed);
+        }
 
-        if let Some(v) = Self::inner_execute_function(runtime, name.to_string(), arguments)? {
+        let native_functions = runtime.borrow().native_functions.clone();
+        if let Some(result) = native_functions.call(name, arguments.clone()) {
+            return result;
+        }
+
+        if let Some(v) = Self::inner_execute_function(runtime.clone(), name.to_string(), arguments)? {
             Ok(v)
         }
         else {
-            Err(GenericError::error(func_name.clone(), format!("function '{}' not defined", name)).into())
+            let suggestion = runtime.borrow().suggest_function(name);
+            Err(GenericError::error(func_name.clone(), format!("function '{}' not defined", name)).suggest(suggestion.as_deref()).into())
         }
     }
 
     pub fn inner_execute_function(runtime: Rc<RefCell<Self>>, func_name: String, arguments: Vec<Value>) -> Result<Option<Value>, RuntimeError<'file>> {
-        #[allow(clippy::manual_map)]
-        if let Some(func) = runtime.borrow().functions.get(&func_name) {
+        // The function is cloned out so the runtime is not borrowed while it executes
+        let func = runtime.borrow().functions.get(&func_name).cloned();
+
+        if let Some(func) = func {
             if arguments.len() != func.arguments.len() {
                 return Err(RuntimeError::ArgumentCountError { expected: func.arguments.len(), got: arguments.len() });
             }
 
-            Ok(Some(func.clone().execute(arguments, runtime.clone())?))
+            {
+                let mut runtime = runtime.borrow_mut();
+
+                if runtime.depth >= runtime.max_depth {
+                    return Err(RuntimeError::MessageError(format!("maximum recursion depth {} exceeded", runtime.max_depth)));
+                }
+                runtime.depth += 1;
+            }
+
+            let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, || func.execute(arguments, runtime.clone()));
+            runtime.borrow_mut().depth -= 1;
+
+            Ok(Some(result?))
         } 
         else {
             Ok(None)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser::ParserContext, tokenizer::TokenStream};
+
+    use super::*;
+
+    fn runtime(source: &str) -> Rc<RefCell<RunTime<'_>>> {
+        let mut tokens = TokenStream::from_source(source, "test.ps");
+        let (parse_tree, _) = ParserContext::new(&mut tokens).parse_document().unwrap();
+
+        Rc::new(RefCell::new(RunTime::new(parse_tree)))
+    }
+
+    #[test]
+    fn step_limit_stops_infinite_recursion() {
+        let runtime = runtime("Test()\n    return Test()\n");
+        runtime.borrow_mut().max_steps = Some(10);
+
+        match RunTime::inner_execute_function(runtime, "Test".to_string(), vec![]) {
+            Err(RuntimeError::FinishedError(e)) => assert!(e.to_string().contains("execution exceeded the limit of 10 steps"), "{}", e),
+            other => panic!("expected the step limit to stop execution, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn recursion_limit_is_reached_before_the_stack_overflows() {
+        // Tests run on threads with small stacks, far less than the limit needs without growing
+        let runtime = runtime("Depth(n)\n    if n == 0\n        return 0\n    return Depth(n - 1) + 1\n\nTest()\n    return Test()\n");
+
+        let deepest = DEFAULT_MAX_RECURSION_DEPTH as i64 - 1;
+        let result = RunTime::inner_execute_function(runtime.clone(), "Depth".to_string(), vec![Value::Integer(deepest)]);
+        assert_eq!(result.unwrap(), Some(Value::Integer(deepest)));
+
+        match RunTime::inner_execute_function(runtime, "Test".to_string(), vec![]) {
+            Err(RuntimeError::FinishedError(e)) => assert!(e.to_string().contains("maximum recursion depth 1000 exceeded"), "{}", e),
+            other => panic!("expected the recursion limit to stop execution, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn timeout_stops_infinite_loop() {
+        let runtime = runtime("Test()\n    while True\n        x = 1\n");
+
+        match RunTime::execute_with_timeout(runtime.clone(), "Test".to_string(), vec![], Duration::from_millis(50)) {
+            Err(RuntimeError::FinishedError(e)) => assert!(e.to_string().contains("execution timed out"), "{}", e),
+            other => panic!("expected the timeout to stop execution, got {:?}", other)
+        }
+
+        // The deadline only applies to that one call
+        assert!(runtime.borrow().deadline.is_none());
+    }
+
+    #[test]
+    fn argument_names_of_functions() {
+        let runtime = runtime("Partition(A, p, r)\n    return p\n\nTest()\n    return 1\n");
+        let runtime = runtime.borrow();
+
+        assert_eq!(runtime.function_argument_names("Partition"), Some(vec!["A", "p", "r"]));
+        assert_eq!(runtime.function_argument_names("Test"), Some(vec![]));
+        assert_eq!(runtime.function_argument_names("Missing"), None);
+    }
+
+    #[test]
+    fn misspelled_names_suggest_the_closest() {
+        let runtime = runtime("Test()\n    total = 1\n    Prnit(total)\n\nSum()\n    total = 1\n    return totl\n");
+
+        for (function, suggestion) in [("Test", "did you mean 'Print'?"), ("Sum", "did you mean 'total'?")] {
+            match RunTime::inner_execute_function(runtime.clone(), function.to_string(), vec![]) {
+                Err(RuntimeError::FinishedError(e)) => assert!(e.to_string().contains(suggestion), "{}", e),
+                other => panic!("expected {} to fail, got {:?}", function, other)
+            }
+        }
+    }
+
+    #[test]
+    fn bare_return_produces_no_value() {
+        let runtime = runtime("Test()\n    x = 1\n    return\n    x = 2\n");
+
+        let result = RunTime::inner_execute_function(runtime, "Test".to_string(), vec![]);
+        assert_eq!(result.unwrap(), Some(Value::None));
+    }
+
+    #[test]
+    fn arrays_hold_custom_attributes() {
+        let runtime = runtime("Test()\n    A = Array(1, 2)\n    A.parent = 5\n    A.heapsize = 2\n    return A.parent + A.heapsize\n");
+
+        let result = RunTime::inner_execute_function(runtime, "Test".to_string(), vec![]);
+        assert_eq!(result.unwrap(), Some(Value::Integer(7)));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{error::GenericError, tokenizer::Token};
+use crate::{error::GenericError, tokenizer::Token, parser::ParseTreeNode};
 
 #[derive(Debug)]
 pub enum RuntimeError<'file> {
@@ -22,6 +22,14 @@ impl<'file> RuntimeError<'file> {
         }
     }
 
+    /// Same as `finish`, underlining all of `node` rather than a single token
+    pub fn finish_span(self, node: &ParseTreeNode<'file>) -> GenericError<'file> {
+        match self {
+            RuntimeError::FinishedError(error) => error,
+            error => error.finish(node.first_token().clone()).end_token(node.last_token().clone())
+        }
+    }
+
     pub fn finish_no_token(self) -> GenericError<'file> {
         match self {
             RuntimeError::FinishedError(error) => error,
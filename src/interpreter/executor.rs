@@ -1,6 +1,6 @@
 use std::{collections::HashMap, cell::RefCell, rc::Rc};
 
-use crate::{error::GenericError, tokenizer::Token};
+use crate::{error::GenericError, tokenizer::Token, util::closest_match};
 
 use super::{Value, RunTime};
 
@@ -24,7 +24,8 @@ impl<'file> Executor<'file> {
         }
         else {
             let t = name.extract_text().to_string();
-            Err(GenericError::error(name.clone(), format!("variable '{}' does not exist", t)))
+            let suggestion = closest_match(&t, self.variables.keys().map(|k| k.as_str()));
+            Err(GenericError::error(name.clone(), format!("variable '{}' does not exist", t)).suggest(suggestion))
         }
     }
 
@@ -45,6 +46,15 @@ impl<'file> Executor<'file> {
         self.variables.insert(name, value);
     }
 
+    /// Whether arrays are indexed from 0 rather than 1
+    pub fn zero_indexed(&self) -> bool {
+        self.context.borrow().zero_indexed
+    }
+
+    pub fn step(&mut self, token: &Token<'file>) -> Result<(), GenericError<'file>> {
+        self.context.borrow_mut().step().map_err(|e| e.finish(token.clone()))
+    }
+
     pub fn execute_function(&mut self, func_name: Token<'file>, arguments: Vec<Value>) -> Result<Value, GenericError<'file>> {
         RunTime::execute_function(self.context.clone(), &func_name, arguments).map_err(|e| e.finish(func_name))
     }
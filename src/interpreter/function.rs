@@ -2,23 +2,25 @@ use std::{rc::Rc, cell::RefCell};
 
 use crate::{tokenizer::Token, parser::ParseTreeNode, error::GenericError};
 
-use super::{Value, Executor, RunTime};
+use super::{Value, Executor, RunTime, ControlFlow};
 
 #[derive(Debug, Clone)]
 pub struct Function<'file> {
     pub name: String,
     _name_token: Token<'file>,
     pub arguments: Vec<Token<'file>>,
+    pub arguments_type_hints: Vec<Option<String>>,
     block: ParseTreeNode<'file>
 }
 
 impl<'file> Function<'file> {
     pub fn new(node: ParseTreeNode<'file>) -> Self {
-        if let ParseTreeNode::Function { name, arguments, block } = node {
+        if let ParseTreeNode::Function { name, arguments, type_hints, block } = node {
             Self {
                 name: name.extract_text().to_string(),
                 _name_token: name,
                 arguments,
+                arguments_type_hints: type_hints,
                 block: *block
             }
         }
@@ -34,6 +36,7 @@ impl<'file> Function<'file> {
             executor.set_variable(name.extract_text().to_string(), arg.clone());
         }
 
-        self.block.execute(&mut executor).map(|v| v.0)
+        // Falling off the end of a function returns None, as it does in the VM
+        self.block.execute(&mut executor).map(|(v, flow)| if flow == ControlFlow::Return { v } else { Value::None })
     }
 }
\ No newline at end of file
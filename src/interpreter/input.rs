@@ -0,0 +1,61 @@
+use std::{rc::Rc, cell::RefCell, io::BufRead};
+
+use super::{Value, RuntimeError, OutputSink, get_args1};
+
+/// Where `Input` reads its lines from, standard input unless a reader is given.
+/// Clones share the same reader.
+#[derive(Clone, Default)]
+pub struct InputSource {
+    reader: Option<Rc<RefCell<dyn BufRead>>>
+}
+
+impl std::fmt::Debug for InputSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.reader.is_some() {
+            write!(f, "InputSource(reader)")
+        }
+        else {
+            write!(f, "InputSource(stdin)")
+        }
+    }
+}
+
+impl InputSource {
+    pub fn new(reader: impl BufRead + 'static) -> Self {
+        Self {
+            reader: Some(Rc::new(RefCell::new(reader)))
+        }
+    }
+
+    /// Read the next line, without its line ending, giving `None` at the end of the input
+    pub fn read_line(&self) -> std::io::Result<Option<String>> {
+        let mut line = String::new();
+
+        let read = if let Some(reader) = &self.reader {
+            reader.borrow_mut().read_line(&mut line)?
+        }
+        else {
+            std::io::stdin().lock().read_line(&mut line)?
+        };
+
+        Ok(if read == 0 { None } else { Some(line) })
+    }
+}
+
+/// Print the optional prompt, then read a line of input with surrounding whitespace removed
+pub fn builtin_input<'file>(args: Vec<Value>, input: &InputSource, output: &OutputSink) -> Result<Value, RuntimeError<'file>> {
+    if args.len() > 1 {
+        return Err(RuntimeError::ArgumentCountError { expected: 1, got: args.len() });
+    }
+
+    if !args.is_empty() {
+        let prompt = get_args1(args)?.to_string();
+        output.write(&prompt).map_err(|e| RuntimeError::MessageError(format!("unable to write output: {}", e)))?;
+    }
+
+    match input.read_line() {
+        Ok(Some(line)) => Ok(Value::String(line.trim().to_string())),
+        Ok(None) => Err(RuntimeError::MessageError("no more input to read".to_string())),
+        Err(e) => Err(RuntimeError::MessageError(format!("unable to read input: {}", e)))
+    }
+}
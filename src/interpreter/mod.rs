@@ -13,6 +13,15 @@ pub use executor::*;
 pub mod function;
 pub use function::*;
 
+pub mod input;
+pub use input::*;
+
+pub mod native;
+pub use native::*;
+
+pub mod output;
+pub use output::*;
+
 pub mod parsetree;
 pub use parsetree::*;
 
@@ -0,0 +1,38 @@
+use std::{rc::Rc, cell::RefCell, collections::HashMap};
+
+use super::{Value, RuntimeError};
+
+/// A function written in Rust which can be called from pseudocode like a builtin
+pub type NativeFunction = dyn Fn(Vec<Value>) -> Result<Value, RuntimeError<'static>>;
+
+/// Native functions registered by the embedding code. Clones share the same
+/// registrations, so functions registered later are seen everywhere, use
+/// `copied` for a registry which does not.
+#[derive(Clone, Default)]
+pub struct NativeFunctions {
+    functions: Rc<RefCell<HashMap<String, Rc<NativeFunction>>>>
+}
+
+impl std::fmt::Debug for NativeFunctions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.functions.borrow().keys()).finish()
+    }
+}
+
+impl NativeFunctions {
+    pub fn register(&self, name: &str, function: impl Fn(Vec<Value>) -> Result<Value, RuntimeError<'static>> + 'static) {
+        self.functions.borrow_mut().insert(name.to_string(), Rc::new(function));
+    }
+
+    /// A registry holding the functions registered so far, which does not share
+    /// registrations made afterwards with `self`
+    pub fn copied(&self) -> Self {
+        Self { functions: Rc::new(RefCell::new(self.functions.borrow().clone())) }
+    }
+
+    /// Call the native function `name`, giving `None` if no function with that name is registered
+    pub fn call(&self, name: &str, arguments: Vec<Value>) -> Option<Result<Value, RuntimeError<'static>>> {
+        let function = self.functions.borrow().get(name).cloned();
+        function.map(|f| f(arguments))
+    }
+}
@@ -0,0 +1,41 @@
+use std::{rc::Rc, cell::RefCell, io::Write};
+
+/// Where `Print` and the prompt of `Input` write to, standard output unless a
+/// writer is given. Clones share the same writer.
+#[derive(Clone, Default)]
+pub struct OutputSink {
+    writer: Option<Rc<RefCell<dyn Write>>>
+}
+
+impl std::fmt::Debug for OutputSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.writer.is_some() {
+            write!(f, "OutputSink(writer)")
+        }
+        else {
+            write!(f, "OutputSink(stdout)")
+        }
+    }
+}
+
+impl OutputSink {
+    pub fn new(writer: impl Write + 'static) -> Self {
+        Self {
+            writer: Some(Rc::new(RefCell::new(writer)))
+        }
+    }
+
+    /// Write `text` and flush it, so prompts appear before input is read
+    pub fn write(&self, text: &str) -> std::io::Result<()> {
+        if let Some(writer) = &self.writer {
+            let mut writer = writer.borrow_mut();
+            writer.write_all(text.as_bytes())?;
+            writer.flush()
+        }
+        else {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(text.as_bytes())?;
+            stdout.flush()
+        }
+    }
+}
@@ -2,15 +2,25 @@ use crate::{parser::{ParseTreeNode, ExpressionType}, error::GenericError};
 
 use super::{Value, Executor, builtin::*};
 
+/// How control leaves a statement once it has been executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlFlow {
+    Normal,
+    Break,
+    Continue,
+    Return
+}
+
 impl<'file> ParseTreeNode<'file> {
-    pub fn execute<'a>(&self, executor: &'a mut Executor<'file>) -> Result<(Value, bool), GenericError<'file>> {
+    pub fn execute<'a>(&self, executor: &'a mut Executor<'file>) -> Result<(Value, ControlFlow), GenericError<'file>> {
         match self {
-            Self::Block { statements } => {
-                let mut last = (Value::None, false);
+            Self::Block { statements, .. } => {
+                let mut last = (Value::None, ControlFlow::Normal);
                 for statement in statements {
+                    executor.step(statement.get_token())?;
                     last = statement.execute(executor)?;
 
-                    if last.1 {
+                    if last.1 != ControlFlow::Normal {
                         break;
                     }
                 }
@@ -25,7 +35,7 @@ impl<'file> ParseTreeNode<'file> {
                         let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
                         if let ParseTreeNode::IdentifierValue { token } = f {
-                            Ok((executor.execute_function(token.clone(), args)?, false))
+                            Ok((executor.execute_function(token.clone(), args)?, ControlFlow::Normal))
                         }
                         else {
                             Err(GenericError::error(f.get_token().clone(), "unable to execute non-function value".to_string()).arrow("unable to execute non-function value".to_string()))
@@ -35,187 +45,382 @@ impl<'file> ParseTreeNode<'file> {
                         let value = children[1].execute(executor)?.0;
                         children[0].execute_mutable(executor, value.clone())?;
 
-                        Ok((value, false))
+                        Ok((value, ControlFlow::Normal))
+                    }
+                    // `a += b` is executed as `a = a + b`, evaluating the parts of `a` only once
+                    ExpressionType::AddAssign | ExpressionType::SubAssign | ExpressionType::MulAssign | ExpressionType::DivAssign => {
+                        let operation: fn(Vec<Value>) -> Result<Value, _> = match expression_type {
+                            ExpressionType::AddAssign => builtin_add,
+                            ExpressionType::SubAssign => builtin_sub,
+                            ExpressionType::MulAssign => builtin_mul,
+                            _ => builtin_div
+                        };
+
+                        let target = &children[0];
+                        match target {
+                            ParseTreeNode::Expression { expression_type: ExpressionType::Indexing, symbols, children: parts } => {
+                                let array = parts[0].execute(executor)?.0;
+                                let index = parts[1].execute(executor)?.0;
+                                let operand = children[1].execute(executor)?.0;
+
+                                let zero_indexed = executor.zero_indexed();
+                                let current = builtin_indexing(vec![array.clone(), index.clone()], zero_indexed).map_err(|e| e.finish(symbols[0].clone()))?;
+                                let value = operation(vec![current, operand]).map_err(|e| e.finish_span(self))?;
+                                builtin_mutable_indexing(vec![array, index], value.clone(), zero_indexed).map_err(|e| e.finish(symbols[0].clone()))?;
+
+                                Ok((value, ControlFlow::Normal))
+                            }
+                            ParseTreeNode::Expression { expression_type: ExpressionType::MemberAccess, symbols, children: parts } => {
+                                let object = parts[0].execute(executor)?.0;
+                                let operand = children[1].execute(executor)?.0;
+
+                                let current = builtin_member_access(object.clone(), parts[1].get_token().clone()).map_err(|e| e.finish(symbols[0].clone()))?;
+                                let value = operation(vec![current, operand]).map_err(|e| e.finish_span(self))?;
+                                builtin_mutable_member_access(object, parts[1].get_token().clone(), value.clone()).map_err(|e| e.finish(symbols[0].clone()))?;
+
+                                Ok((value, ControlFlow::Normal))
+                            }
+                            _ => {
+                                let current = target.execute(executor)?.0;
+                                let operand = children[1].execute(executor)?.0;
+
+                                let value = operation(vec![current, operand]).map_err(|e| e.finish_span(self))?;
+                                target.execute_mutable(executor, value.clone())?;
+
+                                Ok((value, ControlFlow::Normal))
+                            }
+                        }
                     }
                     ExpressionType::Add => {
                         let args_iter = children.iter().map(|c| c.execute(executor));
                         let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
-                        builtin_add(args).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_add(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::Subtract => {
                         let args_iter = children.iter().map(|c| c.execute(executor));
                         let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
-                        builtin_sub(args).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_sub(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::Multiply => {
                         let args_iter = children.iter().map(|c| c.execute(executor));
                         let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
-                        builtin_mul(args).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_mul(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::Divide => {
                         let args_iter = children.iter().map(|c| c.execute(executor));
                         let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
-                        builtin_div(args).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_div(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
+                    }
+                    ExpressionType::FloorDivide => {
+                        let args_iter = children.iter().map(|c| c.execute(executor));
+                        let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
+
+                        builtin_floor_divide(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
+                    }
+                    ExpressionType::Modulo => {
+                        let args_iter = children.iter().map(|c| c.execute(executor));
+                        let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
+
+                        builtin_mod(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
+                    }
+                    ExpressionType::Power => {
+                        let args_iter = children.iter().map(|c| c.execute(executor));
+                        let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
+
+                        builtin_pow(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::GreaterThan => {
                         let args_iter = children.iter().map(|c| c.execute(executor));
                         let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
-                        builtin_greater_than(args).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_greater_than(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::LessThan => {
                         let args_iter = children.iter().map(|c| c.execute(executor));
                         let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
-                        builtin_less_than(args).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_less_than(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::GreaterThanEqual => {
                         let args_iter = children.iter().map(|c| c.execute(executor));
                         let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
-                        builtin_greater_than_equal(args).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_greater_than_equal(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::LessThanEqual => {
                         let args_iter = children.iter().map(|c| c.execute(executor));
                         let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
-                        builtin_less_than_equal(args).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_less_than_equal(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::Equality => {
                         let args_iter = children.iter().map(|c| c.execute(executor));
                         let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
-                        builtin_equality(args).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_equality(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::Inequality => {
                         let args_iter = children.iter().map(|c| c.execute(executor));
                         let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
-                        builtin_inequality(args).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_inequality(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::LogicalAnd => {
-                        builtin_logical_and(children.clone(), executor).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_logical_and(children.clone(), executor).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::LogicalOr => {
-                        builtin_logical_or(children.clone(), executor).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_logical_or(children.clone(), executor).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, ControlFlow::Normal))
+                    }
+                    ExpressionType::Concatenation => {
+                        let args_iter = children.iter().map(|c| c.execute(executor));
+                        let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
+
+                        builtin_concatenate(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
+                    }
+                    ExpressionType::LogicalXor => {
+                        let args_iter = children.iter().map(|c| c.execute(executor));
+                        let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
+
+                        builtin_logical_xor(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
+                    }
+                    ExpressionType::Ternary => {
+                        if let (Value::Boolean(cond), _) = children[0].execute(executor)? {
+                            children[if cond { 1 } else { 2 }].execute(executor)
+                        }
+                        else {
+                            Err(GenericError::error(children[0].first_token().clone(), "condition is not a boolean".to_string()).end_token(children[0].last_token().clone()))
+                        }
+                    }
+                    ExpressionType::Negate => {
+                        let args_iter = children.iter().map(|c| c.execute(executor));
+                        let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
+
+                        builtin_negate(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
+                    }
+                    ExpressionType::LogicalNot => {
+                        let args_iter = children.iter().map(|c| c.execute(executor));
+                        let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
+
+                        builtin_logical_not(args).map_err(|e| e.finish_span(self)).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::Indexing => {
                         let args_iter = children.iter().map(|c| c.execute(executor));
                         let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
-                        builtin_indexing(args).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_indexing(args, executor.zero_indexed()).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, ControlFlow::Normal))
                     }
                     ExpressionType::MemberAccess => {
                         let v = children[0].execute(executor)?.0;
-                        builtin_member_access(v, children[1].get_token().clone()).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, false))
+                        builtin_member_access(v, children[1].get_token().clone()).map_err(|e| e.finish(symbols[0].clone())).map(|v| (v, ControlFlow::Normal))
                     }
                 }
             },
             Self::NumericValue { value, .. } => {
-                Ok((Value::Number(*value), false))
+                Ok((Value::Number(*value), ControlFlow::Normal))
+            },
+            Self::IntegerValue { value, .. } => {
+                Ok((Value::Integer(*value), ControlFlow::Normal))
+            },
+            Self::StringValue { value, .. } => {
+                Ok((Value::String(value.clone()), ControlFlow::Normal))
             },
             Self::IdentifierValue { token } => {
                 if token.extract_text() == "True" {
-                    Ok((Value::Boolean(true), false))
+                    Ok((Value::Boolean(true), ControlFlow::Normal))
                 }
                 else if token.extract_text() == "False" {
-                    Ok((Value::Boolean(false), false))
+                    Ok((Value::Boolean(false), ControlFlow::Normal))
                 }
                 else {
-                    Ok((executor.get_variable(token)?, false))
+                    Ok((executor.get_variable(token)?, ControlFlow::Normal))
                 }
             }
             Self::ReturnStatement { expression, ..} => {
                 if let Some(inner) = expression {
                     let mut v = inner.execute(executor)?;
-                    v.1 = true;
+                    v.1 = ControlFlow::Return;
 
                     Ok(v)
                 }
                 else {
-                    Ok((Value::None, true))
+                    Ok((Value::None, ControlFlow::Return))
                 }
             }
-            Self::ForLoop { loop_variable, bound0, bound1, reverse, block, .. } => {
+            Self::ForLoop { loop_variable, bound0, bound1, step, reverse, block, .. } => {
                 let value0 = bound0.execute(executor)?.0;
                 let value1 = bound1.execute(executor)?.0;
 
-                let value0_number = if let Value::Number(v) = value0 {
+                let step_number = if let Some(step) = step {
+                    let value = step.execute(executor)?.0;
+                    loop_step(&value).map_err(|e| e.finish_span(step))?
+                }
+                else {
+                    1
+                };
+
+                let value0_number = if let Value::Integer(v) = value0 {
+                    v
+                }
+                else if let Value::Number(v) = value0 {
                     if v.fract() != 0.0 {
-                        return Err(GenericError::error(bound0.get_token().clone(), 
-                                        format!("first bound {} is not an integer", v)))
+                        return Err(GenericError::error(bound0.first_token().clone(), 
+                                        format!("first bound {} is not an integer", v)).end_token(bound0.last_token().clone()))
                     }
                     else {
                         v as i64
                     }
                 }
                 else {
-                    return Err(GenericError::error(bound0.get_token().clone(), 
-                                        "first bound is not a number".to_string()))
+                    return Err(GenericError::error(bound0.first_token().clone(), 
+                                        "first bound is not a number".to_string()).end_token(bound0.last_token().clone()))
                 };
 
-                let value1_number = if let Value::Number(v) = value1 {
+                let value1_number = if let Value::Integer(v) = value1 {
+                    v
+                }
+                else if let Value::Number(v) = value1 {
                     if v.fract() != 0.0 {
-                        return Err(GenericError::error(bound1.get_token().clone(), 
-                                        format!("second bound {} is not an integer", v)))
+                        return Err(GenericError::error(bound1.first_token().clone(), 
+                                        format!("second bound {} is not an integer", v)).end_token(bound1.last_token().clone()))
                     }
                     else {
                         v as i64
                     }
                 }
                 else {
-                    return Err(GenericError::error(bound1.get_token().clone(), 
-                                        "second bound is not a number".to_string()))
+                    return Err(GenericError::error(bound1.first_token().clone(), 
+                                        "second bound is not a number".to_string()).end_token(bound1.last_token().clone()))
                 };
 
                 let mut i = value0_number;
                 while !reverse && i <= value1_number || *reverse && i >= value1_number {
-                    executor.set_variable(loop_variable.extract_text().to_string(), Value::Number(i as f64));
-                    block.execute(executor)?;
+                    executor.set_variable(loop_variable.extract_text().to_string(), Value::Integer(i));
+                    let result = block.execute(executor)?;
+
+                    match result.1 {
+                        ControlFlow::Return => return Ok(result),
+                        ControlFlow::Break => break,
+                        ControlFlow::Normal | ControlFlow::Continue => {}
+                    }
 
                     if !reverse {
-                        i += 1;
+                        i += step_number;
                     }
                     else {
-                        i -= 1;
+                        i -= step_number;
                     }
                 }
 
-                Ok((Value::None, false))
+                Ok((Value::None, ControlFlow::Normal))
             },
             Self::IfStatement { ifs, else_block, .. } => {
-                let mut found = false;
                 for (_, condition, block) in ifs {
                     if let (Value::Boolean(cond), _) = condition.execute(executor)? {
                         if cond {
-                            block.execute(executor)?;
-                            found = true;
+                            return block.execute(executor);
+                        }
+                    }
+                    else {
+                        return Err(GenericError::error(condition.first_token().clone(), "condition is not a boolean".to_string()).end_token(condition.last_token().clone()));
+                    }
+                }
+
+                if let Some(else_block) = else_block {
+                    return else_block.execute(executor);
+                }
+
+                Ok((Value::None, ControlFlow::Normal))
+            }
+            Self::ForEachLoop { item_variable, array_expression, block, .. } => {
+                let array = array_expression.execute(executor)?.0;
+
+                // The length is read again each time around, so the block may add to the array
+                let mut i = 0;
+                while i < iteration_length(&array).map_err(|e| e.finish_span(array_expression))? {
+                    let item = iteration_item(&array, i).map_err(|e| e.finish_span(array_expression))?;
+                    executor.set_variable(item_variable.extract_text().to_string(), item);
+                    let result = block.execute(executor)?;
+
+                    match result.1 {
+                        ControlFlow::Return => return Ok(result),
+                        ControlFlow::Break => break,
+                        ControlFlow::Normal | ControlFlow::Continue => {}
+                    }
+
+                    i += 1;
+                }
+
+                Ok((Value::None, ControlFlow::Normal))
+            }
+            Self::WhileLoop { condition, block, .. } => {
+                loop {
+                    if let (Value::Boolean(cond), _) = condition.execute(executor)? {
+                        if !cond {
                             break;
                         }
                     }
                     else {
-                        return Err(GenericError::error(condition.get_token().clone(), "condition is not a boolean".to_string()));
+                        return Err(GenericError::error(condition.first_token().clone(), "condition is not a boolean".to_string()).end_token(condition.last_token().clone()));
+                    }
+
+                    let result = block.execute(executor)?;
+
+                    match result.1 {
+                        ControlFlow::Return => return Ok(result),
+                        ControlFlow::Break => break,
+                        ControlFlow::Normal | ControlFlow::Continue => {}
                     }
                 }
 
-                if !found {
-                    if let Some(else_block) = else_block {
-                        else_block.execute(executor)?;
+                Ok((Value::None, ControlFlow::Normal))
+            }
+            Self::SwitchStatement { token, value, cases, default } => {
+                let value = value.execute(executor)?.0;
+
+                for (case, block) in cases {
+                    let case_value = case.execute(executor)?.0;
+
+                    if let Value::Boolean(true) = builtin_equality(vec![value.clone(), case_value]).map_err(|e| e.finish(token.clone()))? {
+                        return block.execute(executor);
                     }
                 }
 
-                Ok((Value::None, false))
+                if let Some(default) = default {
+                    return default.execute(executor);
+                }
+
+                Ok((Value::None, ControlFlow::Normal))
             }
-            _ => 
-            {
-                dbg!(self);
+            Self::RepeatLoop { block, condition, .. } => {
+                loop {
+                    let result = block.execute(executor)?;
+
+                    match result.1 {
+                        ControlFlow::Return => return Ok(result),
+                        ControlFlow::Break => break,
+                        ControlFlow::Normal | ControlFlow::Continue => {}
+                    }
+
+                    if let (Value::Boolean(cond), _) = condition.execute(executor)? {
+                        if cond {
+                            break;
+                        }
+                    }
+                    else {
+                        return Err(GenericError::error(condition.first_token().clone(), "condition is not a boolean".to_string()).end_token(condition.last_token().clone()));
+                    }
+                }
 
-                todo!()
+                Ok((Value::None, ControlFlow::Normal))
             }
+            Self::BreakStatement { .. } => Ok((Value::None, ControlFlow::Break)),
+            Self::ContinueStatement { .. } => Ok((Value::None, ControlFlow::Continue)),
+            // Nested functions are registered with the runtime when their enclosing function is added
+            Self::Function { .. } => Ok((Value::None, ControlFlow::Normal)),
         }
     }
 
@@ -234,7 +439,8 @@ impl<'file> ParseTreeNode<'file> {
                 let args_iter = children.iter().map(|c| c.execute(executor));
                 let args = args_iter.collect::<Result<Vec<_>, GenericError<'file>>>()?.iter().map(|v| v.0.clone()).collect();
 
-                builtin_mutable_indexing(args, value).map_err(|e| e.finish(symbols[0].clone()))?;
+                let zero_indexed = executor.zero_indexed();
+                builtin_mutable_indexing(args, value, zero_indexed).map_err(|e| e.finish(symbols[0].clone()))?;
                 Ok(())
             }
             ParseTreeNode::Expression { expression_type: ExpressionType::MemberAccess, symbols, children } => {
@@ -1,9 +1,15 @@
-use std::{rc::Rc, cell::RefCell};
+use std::{rc::Rc, cell::RefCell, collections::HashMap};
 
-#[derive(Debug, Clone, PartialEq)]
+/// Array elements along with the array's named attributes, such as `heapsize`
+pub type ArrayData = (Vec<Value>, HashMap<String, Value>);
+
+#[derive(Debug, Clone)]
 pub enum Value {
     Number(f64),
-    Array(Rc<RefCell<(Vec<Value>, Value)>>),
+    Integer(i64),
+    String(String),
+    Array(Rc<RefCell<ArrayData>>),
+    Map(Rc<RefCell<HashMap<String, Value>>>),
     Boolean(bool),
     None
 }
@@ -14,6 +20,18 @@ impl std::convert::From<f64> for Value {
     }
 }
 
+impl std::convert::From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Integer(v)
+    }
+}
+
+impl std::convert::From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::String(v)
+    }
+}
+
 impl std::convert::From<bool> for Value {
     fn from(v: bool) -> Self {
         Value::Boolean(v)
@@ -32,17 +50,105 @@ impl std::convert::From<Option<Value>> for Value {
 }
 
 impl Value {
+    pub fn new_array(values: Vec<Value>) -> Value {
+        let attributes = HashMap::from([("heapsize".to_string(), Value::Integer(0))]);
+        Value::Array(Rc::new(RefCell::new((values, attributes))))
+    }
+
+    pub fn new_map(entries: HashMap<String, Value>) -> Value {
+        Value::Map(Rc::new(RefCell::new(entries)))
+    }
+
+    /// Copy the value, along with every array and map inside it, so nothing in
+    /// the copy is shared with the original. Assignment only copies the
+    /// reference to an array or map.
+    pub fn deep_clone(&self) -> Value {
+        match self {
+            Value::Array(array) => {
+                let array = array.borrow();
+                let values = array.0.iter().map(|v| v.deep_clone()).collect();
+                let attributes = array.1.iter().map(|(k, v)| (k.clone(), v.deep_clone())).collect();
+                Value::Array(Rc::new(RefCell::new((values, attributes))))
+            }
+            Value::Map(map) => Value::new_map(map.borrow().iter().map(|(k, v)| (k.clone(), v.deep_clone())).collect()),
+            v => v.clone()
+        }
+    }
+
     pub fn get_type_name(&self) -> &str {
         match self {
             Value::Number(_) => "number",
+            Value::Integer(_) => "integer",
+            Value::String(_) => "string",
             Value::None => "none",
             Value::Boolean(_) => "bool",
-            Value::Array(_) => "array"
+            Value::Array(_) => "array",
+            Value::Map(_) => "map"
+        }
+    }
+
+    /// Type name including shape information, used in runtime error messages
+    pub fn type_name_full(&self) -> String {
+        match self {
+            Value::Number(_) => "Number".to_string(),
+            Value::Integer(_) => "Integer".to_string(),
+            Value::String(s) => format!("String({})", s.chars().count()),
+            Value::None => "None".to_string(),
+            Value::Boolean(_) => "Boolean".to_string(),
+            Value::Array(array) => {
+                let array = array.borrow();
+                match array.1.get("heapsize") {
+                    Some(heapsize) if heapsize.as_number() != Some(0.0) => format!("Array({}, heap={})", array.0.len(), heapsize),
+                    _ => format!("Array({})", array.0.len()),
+                }
+            }
+            Value::Map(map) => format!("Dictionary({})", map.borrow().len()),
         }
     }
 
     pub fn is_numeric(&self) -> bool {
-        matches!(self, Value::Number(_))
+        matches!(self, Value::Number(_) | Value::Integer(_))
+    }
+
+    /// The value of a number or integer as a float
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(v) => Some(*v),
+            Value::Integer(v) => Some(*v as f64),
+            _ => None
+        }
+    }
+}
+
+/// Integers are equal to numbers with the same value, otherwise values are
+/// only equal to values of the same type
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Number(_) | Value::Integer(_), Value::Number(_) | Value::Integer(_)) => self.as_number() == other.as_number(),
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) => a == b,
+            (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::None, Value::None) => true,
+            _ => false
+        }
+    }
+}
+
+/// Numbers, strings and booleans are ordered among values of the same type,
+/// any other values are only ordered against values equal to them
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a.partial_cmp(b),
+            (Value::Number(_) | Value::Integer(_), Value::Number(_) | Value::Integer(_)) => self.as_number().partial_cmp(&other.as_number()),
+            (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+            (Value::Boolean(a), Value::Boolean(b)) => (*a as u8).partial_cmp(&(*b as u8)),
+            _ if self == other => Some(std::cmp::Ordering::Equal),
+            _ => None
+        }
     }
 }
 
@@ -50,6 +156,8 @@ impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Number(v) => write!(f, "{}", v),
+            Value::Integer(v) => write!(f, "{}", v),
+            Value::String(s) => write!(f, "{}", s),
             Value::None => write!(f, "None"),
             Value::Boolean(b) => write!(f, "{}", b),
             Value::Array(array) => {
@@ -65,6 +173,23 @@ impl std::fmt::Display for Value {
                 write!(f, "]")?;
                 Ok(())
             }
+            // Keys are sorted so the same map always prints the same way
+            Value::Map(map) => {
+                let map = map.borrow();
+                let mut keys = map.keys().collect::<Vec<_>>();
+                keys.sort();
+
+                write!(f, "{{")?;
+
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, map[key])?;
+                }
+
+                write!(f, "}}")
+            }
         }
     }
 }
\ No newline at end of file
@@ -1,6 +1,13 @@
+pub mod bytecode;
+
+pub mod color;
+
 pub mod compiler;
 pub use compiler::*;
 
+pub mod engine;
+pub use engine::*;
+
 pub mod error;
 
 pub mod parser;
@@ -9,4 +16,6 @@ pub mod interpreter;
 
 pub mod tokenizer;
 
+pub mod util;
+
 pub mod virtualmachine;
\ No newline at end of file
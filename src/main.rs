@@ -1,26 +1,133 @@
 #![allow(dead_code)]
 
-use std::{rc::Rc, cell::RefCell, io::{BufRead, Write}};
+use std::{rc::Rc, cell::RefCell, collections::HashMap, path::Path, sync::Arc, io::{BufRead, Write, IsTerminal}};
 
-use pseudocode::{tokenizer::TokenStream, interpreter::{RunTime, RuntimeError}, compile_function, VMFunction, error::GenericError};
+use pseudocode::{color::{set_color_mode, ColorMode, Stream}, tokenizer::{Token, TokenStream}, interpreter::{RunTime, RuntimeError, Value}, compile_document, error::GenericError, virtualmachine::{DebugCommand, CallHistory}};
 mod args;
 
 fn execute() {
     
 }
 
+/// Run `file` once for the watch subcommand, returning the value returned by `Test`
+/// or every error produced, already rendered
+fn watch_run(file: &Path, command: args::WatchCommand, zero_indexed: bool) -> Result<Option<Value>, String> {
+    let name = file.to_string_lossy().to_string();
+    let text = std::fs::read_to_string(file).map_err(|e| GenericError::tokenless_error(format!("unable to read '{}': {}", name, e)).to_string())?;
+
+    let mut tokens = TokenStream::from_source(text.as_str(), &name);
+    let mut context = pseudocode::parser::ParserContext::new(&mut tokens);
+
+    let parse_tree = match context.parse_document() {
+        Ok((parse_tree, errors)) => {
+            for error in errors {
+                println!("{}", error);
+            }
+
+            parse_tree
+        },
+        Err(errors) => return Err(errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")),
+    };
+
+    match command {
+        args::WatchCommand::Execute => {
+            let mut runtime = RunTime::new(parse_tree);
+            runtime.zero_indexed = zero_indexed;
+
+            match RunTime::inner_execute_function(Rc::new(RefCell::new(runtime)), "Test".to_string(), vec![]) {
+                Ok(Some(v)) => Ok(Some(v)),
+                Ok(None) => Err("Function Test Not Defined".to_string()),
+                Err(RuntimeError::FinishedError(e)) => Err(e.to_string()),
+                Err(e) => Err(format!("{:?}", e)),
+            }
+        }
+        args::WatchCommand::VmRun => {
+            let mut runtime = pseudocode::virtualmachine::Runtime::load(compile_document(parse_tree).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+            runtime.set_zero_indexed(zero_indexed);
+
+            runtime.run_function("Test", vec![]).map_err(|e| format!("{}\n{}", e, CallHistory(runtime.call_history())))
+        }
+        args::WatchCommand::Check => {
+            let runtime = pseudocode::virtualmachine::Runtime::load(compile_document(parse_tree).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+            runtime.verify().map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))?;
+
+            Ok(None)
+        }
+    }
+}
+
+/// Print every error in `errors`. As JSON, the errors are printed as a single
+/// array, and nothing is printed if there are none.
+/// Print every token as one JSON array, for both `tokenize --json` and `tokens --format json`
+fn print_tokens_json<'file>(tokens: impl Iterator<Item = Token<'file>>) {
+    let entries = tokens.map(|token| format!("  {}", token.to_json())).collect::<Vec<_>>();
+    println!("[\n{}\n]", entries.join(",\n"));
+}
+
+fn print_errors(errors: &[GenericError], format: args::OutputFormat) {
+    match format {
+        args::OutputFormat::Human => {
+            for error in errors {
+                println!("{}", error);
+            }
+        }
+        #[cfg(feature = "json-errors")]
+        args::OutputFormat::Json => {
+            if !errors.is_empty() {
+                println!("[{}]", errors.iter().map(|e| e.to_json()).collect::<Vec<_>>().join(", "));
+            }
+        }
+    }
+}
+
 fn main()
 {
     use clap::Parser;
     let args = args::Arguments::parse();
 
-    if let args::SubCommand::Tokenize{ file } = args.sub_command {
+    // Reformatting writes the document to stdout, so only its diagnostics are colored
+    let stream = match args.sub_command {
+        args::SubCommand::Reformat { .. } => Stream::Stderr,
+        _ => Stream::Stdout
+    };
+
+    set_color_mode(match (args.no_color, args.color) {
+        (true, _) | (_, args::ColorWhen::Never) => ColorMode::Never,
+        (_, args::ColorWhen::Always) => ColorMode::Always,
+        (_, args::ColorWhen::Auto) => ColorMode::Auto,
+    }, stream);
+
+    let zero_indexed = args.zero_indexed;
+    let output_format = args.output_format;
+
+    if let args::SubCommand::Tokenize{ file, json } = args.sub_command {
+        let name = file.to_string_lossy().to_string();
+        let text = std::fs::read_to_string(file).expect("Unable to read file");
+
+        let tokens = TokenStream::from_source(text.as_str(), &name);
+        if json {
+            print_tokens_json(tokens);
+        }
+        else {
+            for token in tokens {
+                println!("{}", token);
+            }
+        }
+    }
+    else if let args::SubCommand::Tokens{ file, format } = args.sub_command {
         let name = file.to_string_lossy().to_string();
         let text = std::fs::read_to_string(file).expect("Unable to read file");
 
         let tokens = TokenStream::from_source(text.as_str(), &name);
-        for token in tokens {
-            println!("{}", token);
+        match format {
+            args::TokenFormat::Text => {
+                for token in tokens {
+                    println!("{}", token);
+                }
+            }
+            args::TokenFormat::Json => {
+                print_tokens_json(tokens);
+            }
         }
     }
     else if let args::SubCommand::Parse{ file } = args.sub_command {
@@ -31,29 +138,72 @@ fn main()
         let mut context = pseudocode::parser::ParserContext::new(&mut tokens);
         
         
+        let parse_tree = match context.parse_document() {
+            Ok((parse_tree, errors)) => 
+            {
+                print_errors(&errors, output_format);
+
+                parse_tree
+            },
+            Err(errors) => {
+                if output_format == args::OutputFormat::Human {
+                    println!("Parsing Failed");
+                }
+
+                print_errors(&errors, output_format);
+
+                return;
+            },
+        };
+
+        dbg!(parse_tree);
+    }
+    else if let args::SubCommand::Reformat{ file, indent_size, in_place } = args.sub_command {
+        let name = file.to_string_lossy().to_string();
+        let text = std::fs::read_to_string(&file).expect("Unable to read file");
+
+        let mut tokens = TokenStream::from_source(text.as_str(), &name);
+        let mut context = pseudocode::parser::ParserContext::new(&mut tokens);
+
         let parse_tree = match context.parse_document() {
             Ok((parse_tree, errors)) => 
             {
                 for error in errors {
-                    println!("{}", error);
+                    eprintln!("{}", error);
                 }
 
                 parse_tree
             },
             Err(errors) => {
-                println!("Parsing Failed");
+                eprintln!("Parsing Failed");
 
                 for error in errors {
-                    println!("{}", error);
+                    eprintln!("{}", error);
                 }
 
-                return;
+                std::process::exit(1);
             },
         };
 
-        dbg!(parse_tree);
+        let formatted = pseudocode::parser::pretty::pretty_print_document(&parse_tree, indent_size, tokens.comments());
+
+        if in_place {
+            // Write beside the original and rename over it, so a failure never leaves a half written file
+            let mut temp_name = file.file_name().unwrap_or_default().to_os_string();
+            temp_name.push(".tmp");
+            let temp = file.with_file_name(temp_name);
+
+            if let Err(e) = std::fs::write(&temp, formatted).and_then(|_| std::fs::rename(&temp, &file)) {
+                eprintln!("{}", GenericError::tokenless_error(format!("unable to write '{}': {}", name, e)));
+                let _ = std::fs::remove_file(&temp);
+                std::process::exit(1);
+            }
+        }
+        else {
+            print!("{}", formatted);
+        }
     }
-    else if let args::SubCommand::Execute{ file } = args.sub_command {
+    else if let args::SubCommand::Execute{ file, max_steps } = args.sub_command {
         let name = file.to_string_lossy().to_string();
         let text = std::fs::read_to_string(file).expect("Unable to read file");
 
@@ -64,29 +214,31 @@ fn main()
         let parse_tree = match context.parse_document() {
             Ok((parse_tree, errors)) => 
             {
-                for error in errors {
-                    println!("{}", error);
-                }
+                print_errors(&errors, output_format);
 
                 parse_tree
             },
             Err(errors) => {
-                println!("Parsing Failed");
-
-                for error in errors {
-                    println!("{}", error);
+                if output_format == args::OutputFormat::Human {
+                    println!("Parsing Failed");
                 }
 
+                print_errors(&errors, output_format);
+
                 return;
             },
         };
 
-        let executor = Rc::new(RefCell::new(RunTime::new(parse_tree)));
+        let mut runtime = RunTime::new(parse_tree);
+        runtime.zero_indexed = zero_indexed;
+        runtime.max_steps = max_steps;
+
+        let executor = Rc::new(RefCell::new(runtime));
 
         let result = RunTime::inner_execute_function(executor, "Test".to_string(), vec![]);
         
-        if let Err(RuntimeError::FinishedError(e)) = &result {
-            println!("{}", e);
+        if let Err(RuntimeError::FinishedError(e)) = result {
+            print_errors(&[e], output_format);
         }
         else if let Err(e) = &result {
             println!("{:?}", e);
@@ -98,9 +250,33 @@ fn main()
             println!("Function Test Not Defined");
         }
     }
-    else if let args::SubCommand::Compile{ file } = args.sub_command {
+    else if let args::SubCommand::Watch{ file, command } = args.sub_command {
+        let mut last_modified = None;
+
+        loop {
+            let modified = std::fs::metadata(&file).and_then(|m| m.modified()).ok();
+
+            if modified != last_modified {
+                last_modified = modified;
+
+                if std::io::stdout().is_terminal() {
+                    print!("\x1b[2J\x1b[H");
+                }
+
+                match watch_run(&file, command, zero_indexed) {
+                    Ok(Some(v)) => println!("\u{2713} OK\n{}", v),
+                    Ok(None) => println!("\u{2713} OK"),
+                    Err(e) => println!("{}", e),
+                }
+                let _ = std::io::stdout().flush();
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(500));
+        }
+    }
+    else if let args::SubCommand::Compile{ file, emit_bytecode } = args.sub_command {
         let name = file.to_string_lossy().to_string();
-        let text = std::fs::read_to_string(file).expect("Unable to read file");
+        let text = std::fs::read_to_string(&file).expect("Unable to read file");
 
         let mut tokens = TokenStream::from_source(text.as_str(), &name);
         let mut context = pseudocode::parser::ParserContext::new(&mut tokens);
@@ -109,34 +285,88 @@ fn main()
         let parse_tree = match context.parse_document() {
             Ok((parse_tree, errors)) => 
             {
-                for error in errors {
-                    println!("{}", error);
-                }
+                print_errors(&errors, output_format);
 
                 parse_tree
             },
             Err(errors) => {
-                println!("Parsing Failed");
-
-                for error in errors {
-                    println!("{}", error);
+                if output_format == args::OutputFormat::Human {
+                    println!("Parsing Failed");
                 }
 
+                print_errors(&errors, output_format);
+
                 return;
             },
         };
 
-        let functions = parse_tree.into_iter().map(compile_function).collect::<Result<Vec<VMFunction>, GenericError>>();
+        let functions = compile_document(parse_tree);
         if let Err(e) = functions {
-            println!("{}", e);
+            print_errors(&[e], output_format);
         }
         else if let Ok(functions) = functions {
-            for f in functions {
-                println!("{}\n", f);
+            if emit_bytecode {
+                let output = file.with_extension("pbc");
+                if let Err(e) = std::fs::write(&output, pseudocode::bytecode::write_program(&functions)) {
+                    println!("{}", GenericError::tokenless_error(format!("unable to write '{}': {}", output.display(), e)));
+                }
+            }
+            else {
+                for f in functions {
+                    println!("{}\n", f);
+                }
             }
         }
     }
-    else if let args::SubCommand::VMRun{ file, supress: hide, no_wait, instructions } = args.sub_command {
+    else if let args::SubCommand::VMLoad{ file, entry, stats, max_steps } = args.sub_command {
+        let data = std::fs::read(file).expect("Unable to read file");
+
+        let functions = match pseudocode::bytecode::read_program(&data) {
+            Ok(functions) => functions,
+            Err(e) => {
+                print_errors(&[e], output_format);
+                return;
+            }
+        };
+
+        // Errors can still show the offending lines when the source is at hand
+        let mut sources = HashMap::new();
+        for function in &functions {
+            let filename = function.name.location.filename.to_string();
+            sources.entry(filename.clone()).or_insert_with(|| std::fs::read_to_string(&filename).ok().map(Arc::<str>::from));
+        }
+        let with_source = |e: GenericError<'static>| e.with_file_text(|name| sources.get(name).cloned().flatten());
+
+        let mut runtime = match pseudocode::virtualmachine::Runtime::load(functions) {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                print_errors(&[with_source(e)], output_format);
+                return;
+            }
+        };
+        runtime.set_zero_indexed(zero_indexed);
+
+        if let Some(max_steps) = max_steps {
+            runtime.set_max_steps(max_steps);
+        }
+
+        if let Err(e) = runtime.start_execution(&entry) {
+            print_errors(&[with_source(e)], output_format);
+            return;
+        }
+
+        if let Err(e) = runtime.run_to_completion() {
+            print_errors(&[with_source(e)], output_format);
+            if output_format == args::OutputFormat::Human {
+                print!("{}", CallHistory(runtime.call_history()));
+            }
+        }
+
+        if stats {
+            print!("{}", runtime.output_stats());
+        }
+    }
+    else if let args::SubCommand::VMRun{ file, supress: hide, no_wait, instructions, stats, call_history, width, dump_ir, dump_ir_all, max_steps, break_at, watch_var } = args.sub_command {
         let name = file.to_string_lossy().to_string();
         let text = std::fs::read_to_string(file).expect("Unable to read file");
 
@@ -147,26 +377,24 @@ fn main()
         let parse_tree = match context.parse_document() {
             Ok((parse_tree, errors)) => 
             {
-                for error in errors {
-                    println!("{}", error);
-                }
+                print_errors(&errors, output_format);
 
                 parse_tree
             },
             Err(errors) => {
-                println!("Parsing Failed");
-
-                for error in errors {
-                    println!("{}", error);
+                if output_format == args::OutputFormat::Human {
+                    println!("Parsing Failed");
                 }
 
+                print_errors(&errors, output_format);
+
                 return;
             },
         };
 
-        let functions = parse_tree.into_iter().map(compile_function).collect::<Result<Vec<VMFunction>, GenericError>>();
+        let functions = compile_document(parse_tree);
         let functions = if let Err(e) = functions {
-            println!("{}", e);
+            print_errors(&[e], output_format);
             return;
         }
         else if let Ok(functions) = functions {
@@ -176,34 +404,305 @@ fn main()
             unimplemented!()
         };
 
-        let mut runtime = pseudocode::virtualmachine::Runtime::load(functions);
+        if dump_ir_all {
+            for f in &functions {
+                println!("{}\n", f);
+            }
+            return;
+        }
+
+        if let Some(function_name) = dump_ir {
+            if let Some(f) = functions.iter().find(|f| f.name.extract_text() == function_name.as_str()) {
+                println!("{}", f);
+            }
+            else {
+                println!("{}", GenericError::tokenless_error(format!("function '{}' not defined", function_name)));
+            }
+            return;
+        }
+
+        let mut runtime = match pseudocode::virtualmachine::Runtime::load(functions) {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                print_errors(&[e], output_format);
+                return;
+            }
+        };
+        runtime.set_zero_indexed(zero_indexed);
+        runtime.set_call_history_limit(call_history);
+
+        if let Some(max_steps) = max_steps {
+            runtime.set_max_steps(max_steps);
+        }
+
+        if let Some(width) = width {
+            let (_, height) = runtime.terminal_size();
+            runtime.set_terminal_size(width, height);
+        }
+
+        for breakpoint in &break_at {
+            let parsed = breakpoint.rsplit_once(':').and_then(|(function, index)| Some((function, index.trim().parse::<usize>().ok()?)));
+
+            match parsed {
+                Some((function, _)) if !runtime.loaded_function_names().any(|name| name == function) => {
+                    print_errors(&[GenericError::tokenless_error(format!("function '{}' not defined", function))], output_format);
+                    return;
+                }
+                Some((function, index)) => runtime.add_breakpoint(function, index),
+                None => {
+                    print_errors(&[GenericError::tokenless_error(format!("breakpoint '{}' should be written as FUNCTION:N", breakpoint))], output_format);
+                    return;
+                }
+            }
+        }
+
+        for name in &watch_var {
+            runtime.add_watchpoint(name, move |name, old, new| {
+                if output_format == args::OutputFormat::Human {
+                    println!("{} changed from {} to {}", name, old, new);
+                }
+            });
+        }
 
         if let Err(e) = runtime.start_execution("Test") {
-            println!("{}", e);
+            print_errors(&[e], output_format);
             return;
         }
 
-        'outer: loop {
-            if !hide {
-                print!("{}", runtime);
-                let _ = std::io::stdout().flush();
+        // Whether execution runs from one breakpoint or watched change to the next
+        let stopping = !break_at.is_empty() || !watch_var.is_empty();
+
+        // Without a visualization or prompt to show between steps, the program can simply run
+        if hide && no_wait && !instructions && !stopping {
+            if let Err(e) = runtime.run_to_completion() {
+                print_errors(&[e], output_format);
+                if output_format == args::OutputFormat::Human {
+                    print!("{}", CallHistory(runtime.call_history()));
+                }
             }
+        }
+        else {
+            // Run straight to the first breakpoint before showing anything
+            let mut continuing = stopping && !runtime.at_breakpoint();
 
-            if !no_wait {
-                let mut s = String::new();
+            'outer: loop {
+                if !hide && !continuing {
+                    print!("{}", runtime);
+                    let _ = std::io::stdout().flush();
+                }
+
+                if !no_wait && !continuing {
+                    loop {
+                        let mut s = String::new();
+                
+                        std::io::stdin().lock().read_line(&mut s).unwrap();
+
+                        match DebugCommand::parse(&s) {
+                            Ok(DebugCommand::Step) => break,
+                            Ok(DebugCommand::Continue) => {
+                                continuing = true;
+                                break;
+                            }
+                            Ok(command) => match runtime.run_debug_command(&command) {
+                                Ok(output) => println!("{}", output),
+                                Err(e) => println!("{}", e),
+                            },
+                            Err(e) => println!("{}", e),
+                        }
+                        print!("> ");
+                        let _ = std::io::stdout().flush();
+                    }
+                }
+                else if no_wait && stopping {
+                    continuing = true;
+                }
             
-                std::io::stdin().lock().read_line(&mut s).unwrap();
+                runtime.clear();
+                loop {
+                    let v = if std::mem::take(&mut continuing) {
+                        let hit = runtime.run_until_breakpoint();
+                        if let (Ok(true), Some(frame), args::OutputFormat::Human) = (&hit, runtime.current_frame(), output_format) {
+                            if runtime.at_breakpoint() {
+                                println!("stopped at breakpoint {}:{}", frame.function.name.extract_text(), frame.line);
+                            }
+                        }
+
+                        hit.map(|_| true)
+                    }
+                    else {
+                        runtime.single_step(instructions)
+                    };
+
+                    if let Err(e) = v {
+                        print_errors(&[e], output_format);
+                        if output_format == args::OutputFormat::Human {
+                            print!("{}", CallHistory(runtime.call_history()));
+                        }
+                        break 'outer;
+                    }
+                    else if let Ok(v) = v {
+                        if v { break; }
+                    }
+                }
+
+                if runtime.is_done() {
+                    break;
+                }
             }
-            
+        }
+
+        if stats {
+            print!("{}", runtime.output_stats());
+        }
+    }
+
+    else if let args::SubCommand::Profile{ file, entry, iterations, output } = args.sub_command {
+        let name = file.to_string_lossy().to_string();
+        let text = std::fs::read_to_string(file).expect("Unable to read file");
+
+        let mut tokens = TokenStream::from_source(text.as_str(), &name);
+        let mut context = pseudocode::parser::ParserContext::new(&mut tokens);
+        
+        
+        let parse_tree = match context.parse_document() {
+            Ok((parse_tree, errors)) => 
+            {
+                print_errors(&errors, output_format);
+
+                parse_tree
+            },
+            Err(errors) => {
+                if output_format == args::OutputFormat::Human {
+                    println!("Parsing Failed");
+                }
+
+                print_errors(&errors, output_format);
+
+                return;
+            },
+        };
+
+        let functions = match compile_document(parse_tree) {
+            Ok(functions) => functions,
+            Err(e) => {
+                print_errors(&[e], output_format);
+                return;
+            }
+        };
+
+        let mut runtime = match pseudocode::virtualmachine::Runtime::load(functions) {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                print_errors(&[e], output_format);
+                return;
+            }
+        };
+        runtime.set_zero_indexed(zero_indexed);
+        runtime.enable_profiling();
+
+        for _ in 0..iterations {
+            if let Err(e) = runtime.run_function(&entry, vec![]) {
+                print_errors(&[e], output_format);
+                return;
+            }
+        }
+
+        if let Some(report) = runtime.profile_report() {
+            if let Some(output) = output {
+                std::fs::write(output, report.to_json()).expect("Unable to write profile report");
+            }
+            else {
+                print!("{}", report);
+            }
+        }
+    }
+
+    else if let args::SubCommand::Visualize{ file, entry, format, output } = args.sub_command {
+        let name = file.to_string_lossy().to_string();
+        let text = std::fs::read_to_string(file).expect("Unable to read file");
+
+        let mut tokens = TokenStream::from_source(text.as_str(), &name);
+        let mut context = pseudocode::parser::ParserContext::new(&mut tokens);
+        
+        
+        let parse_tree = match context.parse_document() {
+            Ok((parse_tree, errors)) => 
+            {
+                print_errors(&errors, output_format);
+
+                parse_tree
+            },
+            Err(errors) => {
+                if output_format == args::OutputFormat::Human {
+                    println!("Parsing Failed");
+                }
+
+                print_errors(&errors, output_format);
+
+                return;
+            },
+        };
+
+        let functions = match compile_document(parse_tree) {
+            Ok(functions) => functions,
+            Err(e) => {
+                print_errors(&[e], output_format);
+                return;
+            }
+        };
+
+        let mut runtime = match pseudocode::virtualmachine::Runtime::load(functions) {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                print_errors(&[e], output_format);
+                return;
+            }
+        };
+        runtime.set_zero_indexed(zero_indexed);
+
+        if let Err(e) = runtime.start_execution(&entry) {
+            print_errors(&[e], output_format);
+            return;
+        }
+
+        if format == args::VisFormat::Html {
+            std::fs::create_dir_all(&output).expect("Unable to create output directory");
+        }
+
+        let mut steps = Vec::new();
+
+        'outer: loop {
+            if let Some(frame) = runtime.current_frame() {
+                match format {
+                    args::VisFormat::Ansi => {
+                        print!("{}", runtime);
+                        let _ = std::io::stdout().flush();
+
+                        let mut s = String::new();
+                        std::io::stdin().lock().read_line(&mut s).unwrap();
+                    },
+                    args::VisFormat::Plain => {
+                        println!("--- step {} ---", steps.len() + 1);
+                        println!("{}", frame.to_plain());
+                    },
+                    args::VisFormat::Html => {
+                        let step_name = format!("step_{:04}.html", steps.len() + 1);
+                        std::fs::write(output.join(&step_name), frame.to_html()).expect("Unable to write step");
+                    },
+                }
+
+                steps.push(frame.last_line.map(|l| l + 1).unwrap_or(0));
+            }
+
             runtime.clear();
             loop {
-                let v = runtime.single_step(instructions);
-                if let Err(e) = v {
-                    println!("{}", e);
-                    break 'outer;
-                }
-                else if let Ok(v) = v {
-                    if v { break; }
+                match runtime.single_step(false) {
+                    Err(e) => {
+                        print_errors(&[e], output_format);
+                        break 'outer;
+                    },
+                    Ok(true) => break,
+                    Ok(false) => {}
                 }
             }
 
@@ -211,6 +710,16 @@ fn main()
                 break;
             }
         }
+
+        if format == args::VisFormat::Html {
+            let mut index = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Steps</title>\n</head>\n<body>\n<ol>\n");
+            for (i, line) in steps.iter().enumerate() {
+                index += &format!("<li><a href=\"step_{:04}.html\">line {}</a></li>\n", i + 1, line);
+            }
+            index += "</ol>\n</body>\n</html>\n";
+
+            std::fs::write(output.join("index.html"), index).expect("Unable to write index");
+        }
     }
 
 }
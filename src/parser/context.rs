@@ -1,6 +1,6 @@
 use crate::{error::{GenericError, ErrorType}, tokenizer::{TokenStream, LocationTracker, Token, TokenData}};
 
-use super::{ParseTreeNode};
+use super::{ParseTreeNode, ExpressionType, PrecedenceClimber};
 
 pub struct ParserContext<'file, 'this, I: LocationTracker<'file>> {
     errors: Vec<GenericError<'file>>,
@@ -8,6 +8,9 @@ pub struct ParserContext<'file, 'this, I: LocationTracker<'file>> {
     token_stream: &'this mut TokenStream<'file, I>,
     current_indent: usize,
     indentation_stack: Vec<usize>,
+    /// Number of loops enclosing the statement being parsed, within the current function
+    loop_depth: usize,
+    pub(super) precedence: PrecedenceClimber,
 }
 
 impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
@@ -17,10 +20,17 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
             failed: false,
             token_stream,
             current_indent: 0,
-            indentation_stack: vec![]
+            indentation_stack: vec![],
+            loop_depth: 0,
+            precedence: PrecedenceClimber::default()
         }
     }
 
+    /// Replace the binary operator precedence table used for expressions
+    pub fn set_precedence_climber(&mut self, precedence: PrecedenceClimber) {
+        self.precedence = precedence;
+    }
+
     pub fn add_error(&mut self, error: GenericError<'file>) {
         if error.error_type == ErrorType::Error {
             self.failed = true;
@@ -61,6 +71,22 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
         self.consume_if(|t| matches!(t.data, TokenData::NumericLiteral(_)))
     }
 
+    pub fn optional_consume_string(&mut self) -> Option<Token<'file>> {
+        self.consume_if(|t| matches!(t.data, TokenData::StringLiteral(_)))
+    }
+
+    /// Name the token `n` tokens ahead in a note on `error`, if it is on the
+    /// same line, to give context for an unexpected token
+    pub fn note_following_token(&mut self, error: GenericError<'file>, n: usize) -> GenericError<'file> {
+        match self.token_stream.peek_nth(n) {
+            Some(Token { data: TokenData::Indentation(_) | TokenData::EndOfFile, .. }) | None => error,
+            Some(following) => {
+                let text = following.extract_text().to_string();
+                error.note(format!("followed by '{}'", text))
+            }
+        }
+    }
+
     pub fn optional_consume_identifier(&mut self) -> Option<Token<'file>> {
         self.consume_if(|t| matches!(t.data, TokenData::Identifier(_)))
     }
@@ -69,8 +95,10 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
         self.optional_consume_identifier().or_else(|| {
             let token = self.token_stream.peek()?.clone();
             let text = token.extract_text().to_string();
-            self.add_error(GenericError::error(token, format!("expected identifier, got '{}'", text))
-                                    .arrow("expected identifier".to_string()));
+            let error = GenericError::error(token, format!("expected identifier, got '{}'", text))
+                                    .arrow("expected identifier".to_string());
+            let error = self.note_following_token(error, 1);
+            self.add_error(error);
             None
         })
     }
@@ -80,11 +108,13 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
     }
 
     pub fn enforce_consume_identifier_value(&mut self, identifier: &str) -> Option<Token<'file>> {
-        self.optional_consume_identifier().or_else(|| {
+        self.optional_consume_identifier_value(identifier).or_else(|| {
             let token = self.token_stream.peek()?.clone();
             let text = token.extract_text().to_string();
-            self.add_error(GenericError::error(token, format!("expected keyword '{}', got '{}'", identifier, text))
-                                    .arrow(format!("expected keyword '{}'", identifier)));
+            let error = GenericError::error(token, format!("expected keyword '{}', got '{}'", identifier, text))
+                                    .arrow(format!("expected keyword '{}'", identifier));
+            let error = self.note_following_token(error, 1);
+            self.add_error(error);
             None
         })
     }
@@ -97,8 +127,10 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
         self.optional_consume_symbol(symbol).or_else(|| {
             let token = self.token_stream.peek()?.clone();
             let text = token.extract_text().to_string();
-            self.add_error(GenericError::error(token, format!("expected symbol '{}', got '{}'", symbol, text))
-                                .arrow(format!("expected symbol '{}'", symbol)));
+            let error = GenericError::error(token, format!("expected symbol '{}', got '{}'", symbol, text))
+                                .arrow(format!("expected symbol '{}'", symbol));
+            let error = self.note_following_token(error, 1);
+            self.add_error(error);
             None
         })
     }
@@ -113,12 +145,11 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
         self.consume_if(|t| if let TokenData::Indentation(s) = &t.data { s.len() > indent } else { false }).or_else( || {
             let token = self.expect_token()?;
             self.add_error(GenericError::error(token, "expected indented block".to_string())
-                                .help("make sure blocks are denoted with further levels of indentation".to_string()));
+                                .note("make sure blocks are denoted with further levels of indentation".to_string()));
             None
-        }).map( |v| {
+        }).inspect( |v| {
             self.indentation_stack.push(self.current_indent);
             self.current_indent = v.extract_text().len();
-            v
         })
     }
 
@@ -147,39 +178,181 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
         {
             if token.extract_text() == "return" {
                 let token = self.expect_token()?;
-                let expression = Some(Box::new(self.parse_expression()?));
+                let bare = matches!(self.token_stream.peek().map(|t| &t.data), Some(TokenData::Indentation(_) | TokenData::EndOfFile) | None);
+                let expression = if bare { None } else { Some(Box::new(self.parse_expression()?)) };
 
                 Some(ParseTreeNode::ReturnStatement { token, expression })
             }
             else if token.extract_text() == "while" {
                 let token = self.expect_token()?;
                 let condition = Box::new(self.parse_expression()?);
-                let block = Box::new(self.parse_block()?);
+                let block = Box::new(self.parse_loop_block()?);
 
                 Some(ParseTreeNode::WhileLoop { token, condition, block })
             }
+            else if token.extract_text() == "repeat" {
+                let token = self.expect_token()?;
+                let block = Box::new(self.parse_loop_block()?);
+
+                // The `until` line sits at the same indentation as the `repeat`
+                self.optional_consume_consistent_indentation();
+                self.enforce_consume_identifier_value("until")?;
+                let condition = Box::new(self.parse_expression()?);
+
+                Some(ParseTreeNode::RepeatLoop { token, block, condition })
+            }
             else if token.extract_text() == "for" {
                 let token = self.expect_token()?;
-                
+
+                // `for x in array` and `for i = a to b` differ only after the loop variable
+                if matches!(self.token_stream.peek_nth(1), Some(Token { data: TokenData::Identifier(s), .. }) if s == "in") {
+                    let item_variable = self.enforce_consume_identifier()?;
+                    self.enforce_consume_identifier_value("in")?;
+                    let array_expression = Box::new(self.parse_expression()?);
+                    let block = Box::new(self.parse_loop_block()?);
+
+                    return Some(ParseTreeNode::ForEachLoop { token, item_variable, array_expression, block });
+                }
+
                 let loop_variable = self.enforce_consume_identifier()?;
                 self.enforce_consume_symbol("=");
                 let bound0 = Box::new(self.parse_expression()?);
                 let reverse = self.optional_consume_identifier_value("down").is_some();
                 self.enforce_consume_identifier_value("to")?;
                 let bound1 = Box::new(self.parse_expression()?);
+                let step = if self.optional_consume_identifier_value("by").is_some() {
+                    Some(Box::new(self.parse_expression()?))
+                }
+                else {
+                    None
+                };
+
+                let block = Box::new(self.parse_loop_block()?);
+
+                Some(ParseTreeNode::ForLoop { token, loop_variable, bound0, bound1, step, reverse, block })
+            }
+            else if token.extract_text() == "case" {
+                let token = self.expect_token()?;
+                let value = Box::new(self.parse_expression()?);
+                self.enforce_consume_identifier_value("of")?;
+                self.enforce_consume_more_indentation()?;
+
+                let mut cases = vec![];
+                let mut default = None;
+
+                loop {
+                    if let Some(else_token) = self.optional_consume_identifier_value("else") {
+                        self.enforce_consume_symbol(":")?;
+                        let block = self.parse_case_body(&else_token)?;
+
+                        if default.is_some() {
+                            self.add_error(GenericError::error(else_token, "case has more than one else arm".to_string()));
+                        }
+                        default = Some(Box::new(block));
+                    }
+                    else {
+                        let case = self.parse_expression()?;
+                        let colon = self.enforce_consume_symbol(":")?;
+                        let block = self.parse_case_body(&colon)?;
+
+                        cases.push((case, block));
+                    }
+
+                    if self.enforce_indent_or_less().is_none() {
+                        self.current_indent = self.indentation_stack.pop().unwrap_or(0);
+                        break;
+                    }
+                }
 
-                let block = Box::new(self.parse_block()?);
+                Some(ParseTreeNode::SwitchStatement { token, value, cases, default })
+            }
+            else if token.extract_text() == "break" || token.extract_text() == "continue" {
+                let token = self.expect_token()?;
+
+                if self.loop_depth == 0 {
+                    self.add_error(GenericError::error(token.clone(), format!("`{}` outside of a loop", token.extract_text())));
+                }
 
-                Some(ParseTreeNode::ForLoop { token, loop_variable, bound0, bound1, reverse, block })
+                if token.extract_text() == "break" {
+                    Some(ParseTreeNode::BreakStatement { token })
+                }
+                else {
+                    Some(ParseTreeNode::ContinueStatement { token })
+                }
             }
             else {
-                self.parse_expression()
+                let expression = self.parse_expression()?;
+
+                if self.starts_nested_function(&expression) {
+                    self.parse_nested_function(expression)
+                }
+                else {
+                    Some(expression)
+                }
             }
         }
     }
 
+    /// Parse the body of a case arm, either an indented block or a single
+    /// statement on the same line as the arm's value
+    fn parse_case_body(&mut self, token: &Token<'file>) -> Option<ParseTreeNode<'file>> {
+        if matches!(self.token_stream.peek().map(|t| &t.data), Some(TokenData::Indentation(_))) {
+            self.parse_block()
+        }
+        else {
+            Some(ParseTreeNode::Block { token: token.clone(), statements: vec![self.parse_statement()?] })
+        }
+    }
+
+    fn parse_loop_block(&mut self) -> Option<ParseTreeNode<'file>> {
+        self.loop_depth += 1;
+        let block = self.parse_block();
+        self.loop_depth -= 1;
+
+        block
+    }
+
+    /// A call whose arguments are all plain names, followed by an indented
+    /// block, is a function defined inside the current block
+    fn starts_nested_function(&mut self, expression: &ParseTreeNode<'file>) -> bool {
+        let indent = self.current_indent;
+
+        matches!(expression, ParseTreeNode::Expression { expression_type: ExpressionType::FunctionCall, children, .. }
+                    if children.iter().all(|c| matches!(c, ParseTreeNode::IdentifierValue { .. })))
+            && matches!(self.token_stream.peek().map(|t| &t.data), Some(TokenData::Indentation(s)) if s.len() > indent)
+    }
+
+    fn parse_nested_function(&mut self, signature: ParseTreeNode<'file>) -> Option<ParseTreeNode<'file>> {
+        let mut tokens = signature.children().into_iter().map(|c| c.get_token().clone());
+        let name = tokens.next()?;
+        let arguments = tokens.collect::<Vec<_>>();
+
+        let type_hints = self.parse_type_hints(&name, &arguments);
+
+        // Loops around the definition do not enclose the body of the function
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        let block = self.parse_block();
+        self.loop_depth = loop_depth;
+
+        Some(ParseTreeNode::Function { name, arguments, type_hints, block: Box::new(block?) })
+    }
+
+    /// Parse the body of an if or elseif branch, which may be empty when it is
+    /// immediately followed by another branch at the same indentation
+    pub fn parse_branch_block(&mut self, token: &Token<'file>) -> Option<ParseTreeNode<'file>> {
+        let indent = self.current_indent;
+        let next = self.token_stream.peek()?;
+
+        if matches!(&next.data, TokenData::Indentation(s) if s.len() <= indent) {
+            Some(ParseTreeNode::Block { token: token.clone(), statements: vec![] })
+        }
+        else {
+            self.parse_block()
+        }
+    }
+
     pub fn parse_block(&mut self) -> Option<ParseTreeNode<'file>> {
-        self.enforce_consume_more_indentation()?;
+        let token = self.enforce_consume_more_indentation()?;
 
         let mut statements = vec![];
 
@@ -188,7 +361,7 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
             if s == Some("if".to_string()) {
                 let token = self.expect_token()?;
                 let condition = self.parse_expression()?;
-                let block = self.parse_block()?;
+                let block = self.parse_branch_block(&token)?;
 
                 let mut else_ifs: Vec<(Token<'file>, ParseTreeNode, ParseTreeNode)> = vec![(token, condition, block)];
 
@@ -205,7 +378,7 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
                     if t == "elseif" {
                         let token = self.token_stream.next()?;
                         let condition = self.parse_expression()?;
-                        let block = self.parse_block()?;
+                        let block = self.parse_branch_block(&token)?;
                         else_ifs.push((token, condition, block))
                     }
                     else if t == "else" {
@@ -236,7 +409,7 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
             }
         }
 
-        Some(ParseTreeNode::Block { statements })
+        Some(ParseTreeNode::Block { token, statements })
     }
 
     pub fn parse_function(&mut self) -> Option<ParseTreeNode<'file>> {
@@ -255,31 +428,82 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
             }
         }
 
+        let type_hints = self.parse_type_hints(&name, &arguments);
         let block = Box::new(self.parse_block()?);
 
-        Some(ParseTreeNode::Function { name, arguments, block })
+        Some(ParseTreeNode::Function { name, arguments, type_hints, block })
     }
 
-    pub fn parse_document(&mut self) -> Result<(Vec<ParseTreeNode<'file>>, Vec<GenericError<'file>>), Vec<GenericError<'file>>> {
-        let mut result = Vec::new();
+    /// Read the argument types from a `// @type A: Array, n: Number` comment on the line above a function
+    fn parse_type_hints(&mut self, name: &Token<'file>, arguments: &[Token<'file>]) -> Vec<Option<String>> {
+        let mut hints = vec![None; arguments.len()];
 
-        loop {
-            if let Some(next) = self.token_stream.peek()
-            {
-                if next.data == TokenData::EndOfFile {
-                    break;
+        let comment = name.location.line.checked_sub(1)
+            .and_then(|line| self.token_stream.comment_on_line(line))
+            .and_then(|comment| comment.trim().strip_prefix("@type"))
+            .map(|hint_text| hint_text.to_string());
+
+        if let Some(hint_text) = comment {
+            for hint in hint_text.split(',').filter(|h| !h.trim().is_empty()) {
+                let (argument, hint_type) = hint.split_once(':').unwrap_or((hint, ""));
+                let (argument, hint_type) = (argument.trim(), hint_type.trim());
+
+                if let Some(i) = arguments.iter().position(|a| a.extract_text() == argument) {
+                    hints[i] = Some(hint_type.to_string());
+                }
+                else {
+                    self.add_error(GenericError::warning(name.clone(), format!("type hint given for unknown argument '{}'", argument)));
                 }
             }
-            else {
+        }
+
+        hints
+    }
+
+    /// Recover from a function which failed to parse by discarding tokens up
+    /// to the start of the next function, an identifier at the start of a line
+    /// followed by `(`, or the end of the file
+    fn skip_to_next_function(&mut self) {
+        loop {
+            let at_line_start = match self.token_stream.peek() {
+                None | Some(Token { data: TokenData::EndOfFile, .. }) => break,
+                Some(Token { data: TokenData::Identifier(_), location, .. }) => location.column == 0,
+                Some(_) => false
+            };
+
+            if at_line_start && matches!(self.token_stream.peek_nth(1), Some(Token { data: TokenData::Symbol(s), .. }) if s == "(") {
                 break;
             }
 
-            if let Some(function) = self.parse_function() {
-                result.push(function);
-            }
-            else {
+            self.token_stream.next();
+        }
+
+        self.current_indent = 0;
+        self.indentation_stack.clear();
+        self.loop_depth = 0;
+    }
+
+    pub fn parse_document(&mut self) -> Result<(Vec<ParseTreeNode<'file>>, Vec<GenericError<'file>>), Vec<GenericError<'file>>> {
+        let mut result = Vec::new();
+
+        while let Some(next) = self.token_stream.peek() {
+            if next.data == TokenData::EndOfFile {
                 break;
             }
+
+            let errors_before = self.failed;
+            self.failed = false;
+
+            match self.parse_function() {
+                Some(function) if !self.failed => result.push(function),
+                _ => self.skip_to_next_function()
+            }
+
+            self.failed |= errors_before;
+        }
+
+        for error in self.token_stream.take_errors() {
+            self.add_error(error);
         }
 
         if self.failed {
@@ -289,4 +513,29 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
             Ok((result, std::mem::take(&mut self.errors)))
         }
     }
-}
\ No newline at end of file
+
+    /// Parse the next function from the token stream and append it to `existing`.
+    /// Errors are reset between calls so parsing can carry on after a failure.
+    pub fn parse_document_incremental(&mut self, existing: &mut Vec<ParseTreeNode<'file>>) -> Result<Vec<GenericError<'file>>, Vec<GenericError<'file>>> {
+        let at_end = matches!(self.token_stream.peek().map(|t| &t.data), Some(TokenData::EndOfFile) | None);
+
+        if !at_end {
+            match self.parse_function() {
+                Some(function) if !self.failed => existing.push(function),
+                _ => self.skip_to_next_function()
+            }
+        }
+
+        for error in self.token_stream.take_errors() {
+            self.add_error(error);
+        }
+
+        let errors = std::mem::take(&mut self.errors);
+        if std::mem::replace(&mut self.failed, false) {
+            Err(errors)
+        }
+        else {
+            Ok(errors)
+        }
+    }
+}
@@ -1,22 +1,120 @@
-use crate::{tokenizer::LocationTracker, error::GenericError};
+use crate::{tokenizer::{LocationTracker, Token, TokenData, unescape_string}, error::GenericError};
 
 use super::{ParserContext, ParseTreeNode, ExpressionType};
 
+/// The value of a numeric literal, which is an integer when written without a
+/// fractional part unless it is too large for one
+enum NumericLiteral {
+    Integer(i64),
+    Number(f64)
+}
+
+fn parse_numeric_literal(text: &str) -> Option<NumericLiteral> {
+    let radix = match text.get(..2) {
+        Some("0x" | "0X") => Some(16),
+        Some("0b" | "0B") => Some(2),
+        _ => None
+    };
+
+    if let Some(radix) = radix {
+        let value = u64::from_str_radix(&text[2..], radix).ok()?;
+        Some(i64::try_from(value).map_or(NumericLiteral::Number(value as f64), NumericLiteral::Integer))
+    }
+    else if let Ok(value) = text.parse::<i64>() {
+        Some(NumericLiteral::Integer(value))
+    }
+    else {
+        text.parse::<f64>().ok().map(NumericLiteral::Number)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right
+}
+
+/// Binary operators grouped into precedence levels, from the loosest binding
+/// level to the tightest. Operators made of letters are matched as keywords.
+#[derive(Debug, Clone)]
+pub struct PrecedenceClimber {
+    pub levels: Vec<(Vec<(&'static str, ExpressionType)>, Associativity)>
+}
+
+impl Default for PrecedenceClimber {
+    fn default() -> Self {
+        Self {
+            levels: vec![
+                (vec![("=", ExpressionType::Assignment), ("+=", ExpressionType::AddAssign), ("-=", ExpressionType::SubAssign),
+                      ("*=", ExpressionType::MulAssign), ("/=", ExpressionType::DivAssign)], Associativity::Right),
+                (vec![("xor", ExpressionType::LogicalXor)], Associativity::Left),
+                (vec![("or", ExpressionType::LogicalOr)], Associativity::Left),
+                (vec![("and", ExpressionType::LogicalAnd)], Associativity::Left),
+                (vec![("==", ExpressionType::Equality), ("!=", ExpressionType::Inequality)], Associativity::Left),
+                (vec![("<", ExpressionType::LessThan), (">", ExpressionType::GreaterThan),
+                      ("<=", ExpressionType::LessThanEqual), (">=", ExpressionType::GreaterThanEqual)], Associativity::Left),
+                (vec![("+", ExpressionType::Add), ("-", ExpressionType::Subtract), ("&", ExpressionType::Concatenation)], Associativity::Left),
+                (vec![("*", ExpressionType::Multiply), ("/", ExpressionType::Divide), ("//", ExpressionType::FloorDivide), ("%", ExpressionType::Modulo)], Associativity::Left),
+            ]
+        }
+    }
+}
+
+impl PrecedenceClimber {
+    pub fn level_of(&self, expression_type: ExpressionType) -> Option<usize> {
+        self.levels.iter().position(|(operators, _)| operators.iter().any(|(_, t)| *t == expression_type))
+    }
+}
+
 impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
     
     pub fn parse_value(&mut self) -> Option<ParseTreeNode<'file>> {
-        if let Some(identifier_token) = self.optional_consume_identifier() {
+        if let Some(if_token) = self.optional_consume_identifier_value("if") {
+            self.parse_ternary_expression(if_token)
+        }
+        else if let Some(identifier_token) = self.optional_consume_identifier() {
             Some(ParseTreeNode::IdentifierValue { token: identifier_token })
         }
         else if let Some(numeric_token) = self.optional_consume_number() {
-            if let Ok(value) = numeric_token.extract_text().parse::<f64>() {
-                Some(ParseTreeNode::NumericValue { token: numeric_token, value })
+            match parse_numeric_literal(numeric_token.extract_text()) {
+                Some(NumericLiteral::Integer(value)) => Some(ParseTreeNode::IntegerValue { token: numeric_token, value }),
+                Some(NumericLiteral::Number(value)) => Some(ParseTreeNode::NumericValue { token: numeric_token, value }),
+                None => {
+                    let text = numeric_token.extract_text().to_string();
+                    self.add_error(GenericError::error(numeric_token.clone(), format!("unable to parse number from '{}'", text)));
+
+                    Some(ParseTreeNode::NumericValue { token: numeric_token, value: 0.0 })
+                }
             }
-            else {
-                let text = numeric_token.extract_text().to_string();
-                self.add_error(GenericError::error(numeric_token.clone(), format!("unable to parse number from '{}'", text)));
-
-                Some(ParseTreeNode::NumericValue { token: numeric_token, value: 0.0 })
+        }
+        else if let Some(string_token) = self.optional_consume_string() {
+            let text = string_token.extract_text().to_string();
+
+            // The closing quote must not itself be escaped
+            let contents = text.get(1..).and_then(|t| t.strip_suffix('"'))
+                .filter(|t| (t.len() - t.trim_end_matches('\\').len()) % 2 == 0);
+
+            let Some(contents) = contents else {
+                self.add_error(GenericError::error(string_token.clone(), "unterminated string literal".to_string())
+                                    .arrow("missing closing '\"'".to_string()));
+                return None;
+            };
+
+            match unescape_string(contents) {
+                Ok(value) => Some(ParseTreeNode::StringValue { token: string_token, value: value.into_owned() }),
+                Err((offset, message)) => {
+                    // Point at the escape itself, which is on the same line as the opening quote
+                    let escape = contents[offset..].chars().take(2).collect::<String>();
+                    let mut location = string_token.location.clone();
+                    location.column += 1 + contents[..offset].chars().count();
+                    location.index += 1 + offset;
+
+                    let token = Token::new(location, TokenData::Symbol(escape.into()));
+                    self.add_error(GenericError::error(token, message).arrow("unknown escape".to_string())
+                                        .note("the escapes are \\n, \\t, \\r, \\\\ and \\\"".to_string()));
+
+                    Some(ParseTreeNode::StringValue { token: string_token, value: String::new() })
+                }
             }
         }
         else if self.optional_consume_symbol("(").is_some() {
@@ -28,7 +126,9 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
         }
         else if let Some(token) = self.expect_token() {
             let text = token.extract_text().to_string();
-            self.add_error(GenericError::error(token, format!("expected value, got '{}'", text)).arrow("expected value".to_string()).help("a value can be any of the following:\n  a numeric literal\n  an identifier".to_string()));
+            let error = GenericError::error(token, format!("expected value, got '{}'", text)).arrow("expected value".to_string()).note("a value can be any of the following:\n  a numeric literal\n  a string literal\n  an identifier".to_string());
+            let error = self.note_following_token(error, 0);
+            self.add_error(error);
 
             None
         }
@@ -37,6 +137,18 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
         }
     }
 
+    /// Parse the rest of an `if <cond> then <expr> else <expr>` expression, each
+    /// part of which may use any operator binding tighter than assignment
+    fn parse_ternary_expression(&mut self, if_token: Token<'file>) -> Option<ParseTreeNode<'file>> {
+        let condition = self.parse_precedence_level(1)?;
+        let then_token = self.enforce_consume_identifier_value("then")?;
+        let true_expression = self.parse_precedence_level(1)?;
+        let else_token = self.enforce_consume_identifier_value("else")?;
+        let false_expression = self.parse_precedence_level(1)?;
+
+        Some(ParseTreeNode::Expression { expression_type: ExpressionType::Ternary, symbols: vec![if_token, then_token, else_token], children: vec![condition, true_expression, false_expression] })
+    }
+
     pub fn parse_postfix_expression(&mut self) -> Option<ParseTreeNode<'file>> {
         let mut inner = self.parse_value()?;
 
@@ -54,15 +166,20 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
             else if let Some(symbol) = self.optional_consume_symbol("(") {
                 let mut children = vec![inner];
 
-                loop {
-                    children.push(self.parse_expression()?);
+                let symbol1 = if let Some(symbol1) = self.optional_consume_symbol(")") {
+                    symbol1
+                }
+                else {
+                    loop {
+                        children.push(self.parse_expression()?);
 
-                    if self.optional_consume_symbol(",").is_none() {
-                        break;
+                        if self.optional_consume_symbol(",").is_none() {
+                            break;
+                        }
                     }
-                }
 
-                let symbol1 = self.enforce_consume_symbol(")")?;
+                    self.enforce_consume_symbol(")")?
+                };
 
                 inner = ParseTreeNode::Expression { expression_type: ExpressionType::FunctionCall, symbols: vec![symbol, symbol1], children }
             }
@@ -74,124 +191,115 @@ impl<'file, 'this, I: LocationTracker<'file>> ParserContext<'file, 'this, I> {
         Some(inner)
     }
 
-    pub fn parse_multiplicative_expressions(&mut self) -> Option<ParseTreeNode<'file>> {
-        let left = self.parse_postfix_expression()?;
-
-        if let Some(symbol) = self.optional_consume_symbol("*") {
-            let right = self.parse_multiplicative_expressions()?;
+    /// Parse a postfix expression along with any prefix operators applied to it. A `^`
+    /// binds tighter than a prefix operator before it, so `-2 ^ 2` is `-(2 ^ 2)`, and
+    /// is right associative, with its exponent being another unary expression.
+    pub fn parse_unary_expression(&mut self) -> Option<ParseTreeNode<'file>> {
+        if let Some(symbol) = self.optional_consume_symbol("-") {
+            let operand = self.parse_unary_expression()?;
 
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::Multiply, symbols: vec![symbol], children: vec![left, right] })
+            Some(ParseTreeNode::Expression { expression_type: ExpressionType::Negate, symbols: vec![symbol], children: vec![operand] })
         }
-        else if let Some(symbol) = self.optional_consume_symbol("/") {
-            let right = self.parse_multiplicative_expressions()?;
+        else if let Some(symbol) = self.optional_consume_identifier_value("not") {
+            let operand = self.parse_unary_expression()?;
 
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::Divide, symbols: vec![symbol], children: vec![left, right] })
+            Some(ParseTreeNode::Expression { expression_type: ExpressionType::LogicalNot, symbols: vec![symbol], children: vec![operand] })
         }
         else {
-            Some(left)
+            let base = self.parse_postfix_expression()?;
+
+            if let Some(symbol) = self.optional_consume_symbol("^") {
+                let exponent = self.parse_unary_expression()?;
+
+                Some(ParseTreeNode::Expression { expression_type: ExpressionType::Power, symbols: vec![symbol], children: vec![base, exponent] })
+            }
+            else {
+                Some(base)
+            }
         }
     }
 
-    pub fn parse_additive_expressions(&mut self) -> Option<ParseTreeNode<'file>> {
-        let left = self.parse_multiplicative_expressions()?;
+    fn consume_operator(&mut self, level: usize) -> Option<(Token<'file>, ExpressionType)> {
+        for i in 0..self.precedence.levels[level].0.len() {
+            let (operator, expression_type) = self.precedence.levels[level].0[i];
 
-        if let Some(symbol) = self.optional_consume_symbol("+") {
-            let right = self.parse_additive_expressions()?;
+            let token = if operator.chars().all(char::is_alphabetic) {
+                self.optional_consume_identifier_value(operator)
+            }
+            else {
+                self.optional_consume_symbol(operator)
+            };
 
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::Add, symbols: vec![symbol], children: vec![left, right] })
+            if let Some(token) = token {
+                return Some((token, expression_type));
+            }
         }
-        else if let Some(symbol) = self.optional_consume_symbol("-") {
-            let right = self.parse_additive_expressions()?;
 
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::Subtract, symbols: vec![symbol], children: vec![left, right] })
-        }
-        else {
-            Some(left)
-        }
+        None
     }
 
-    pub fn parse_comparison_expressions(&mut self) -> Option<ParseTreeNode<'file>> {
-        let left = self.parse_additive_expressions()?;
+    /// Parse an expression made of operators from `level` and any tighter binding level
+    pub fn parse_precedence_level(&mut self, level: usize) -> Option<ParseTreeNode<'file>> {
+        if level >= self.precedence.levels.len() {
+            return self.parse_unary_expression();
+        }
 
-        if let Some(symbol) = self.optional_consume_symbol("<") {
-            let right = self.parse_comparison_expressions()?;
+        let mut left = self.parse_precedence_level(level + 1)?;
 
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::LessThan, symbols: vec![symbol], children: vec![left, right] })
-        }
-        else if let Some(symbol) = self.optional_consume_symbol(">") {
-            let right = self.parse_comparison_expressions()?;
+        while let Some((symbol, expression_type)) = self.consume_operator(level) {
+            if self.precedence.levels[level].1 == Associativity::Right {
+                if (expression_type == ExpressionType::Assignment || expression_type.compound_operation().is_some()) && !left.is_assignable() {
+                    let (start, end) = (left.first_token().clone(), left.last_token().clone());
+                    self.add_error(GenericError::error(start, "cannot assign to this expression".to_string()).end_token(end)
+                                        .note("only variables, array elements and members can be assigned to".to_string()));
+                }
 
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::GreaterThan, symbols: vec![symbol], children: vec![left, right] })
-        }
-        else if let Some(symbol) = self.optional_consume_symbol("<=") {
-            let right = self.parse_comparison_expressions()?;
+                let right = self.parse_precedence_level(level)?;
 
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::LessThanEqual, symbols: vec![symbol], children: vec![left, right] })
-        }
-        else if let Some(symbol) = self.optional_consume_symbol(">=") {
-            let right = self.parse_comparison_expressions()?;
+                return Some(ParseTreeNode::Expression { expression_type, symbols: vec![symbol], children: vec![left, right] });
+            }
 
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::GreaterThanEqual, symbols: vec![symbol], children: vec![left, right] })
+            let right = self.parse_precedence_level(level + 1)?;
+            left = ParseTreeNode::Expression { expression_type, symbols: vec![symbol], children: vec![left, right] };
         }
-        else {
-            Some(left)
-        }
-    }
 
-    pub fn parse_equality_expressions(&mut self) -> Option<ParseTreeNode<'file>> {
-        let left = self.parse_comparison_expressions()?;
+        Some(left)
+    }
 
-        if let Some(symbol) = self.optional_consume_symbol("==") {
-            let right = self.parse_equality_expressions()?;
+    fn parse_level_of(&mut self, expression_type: ExpressionType) -> Option<ParseTreeNode<'file>> {
+        let level = self.precedence.level_of(expression_type).unwrap_or(self.precedence.levels.len());
+        self.parse_precedence_level(level)
+    }
 
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::Equality, symbols: vec![symbol], children: vec![left, right] })
-        }
-        else if let Some(symbol) = self.optional_consume_symbol("!=") {
-            let right = self.parse_equality_expressions()?;
+    pub fn parse_multiplicative_expressions(&mut self) -> Option<ParseTreeNode<'file>> {
+        self.parse_level_of(ExpressionType::Multiply)
+    }
 
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::Inequality, symbols: vec![symbol], children: vec![left, right] })
-        }
-        else {
-            Some(left)
-        }
+    pub fn parse_additive_expressions(&mut self) -> Option<ParseTreeNode<'file>> {
+        self.parse_level_of(ExpressionType::Add)
     }
 
-    pub fn parse_logical_and_expression(&mut self) -> Option<ParseTreeNode<'file>> {
-        let left = self.parse_equality_expressions()?;
+    pub fn parse_comparison_expressions(&mut self) -> Option<ParseTreeNode<'file>> {
+        self.parse_level_of(ExpressionType::LessThan)
+    }
 
-        if let Some(symbol) = self.optional_consume_identifier_value("and") {
-            let right = self.parse_logical_and_expression()?;
+    pub fn parse_equality_expressions(&mut self) -> Option<ParseTreeNode<'file>> {
+        self.parse_level_of(ExpressionType::Equality)
+    }
 
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::LogicalAnd, symbols: vec![symbol], children: vec![left, right] })
-        }
-        else {
-            Some(left)
-        }
+    pub fn parse_logical_and_expression(&mut self) -> Option<ParseTreeNode<'file>> {
+        self.parse_level_of(ExpressionType::LogicalAnd)
     }
 
     pub fn parse_logical_or_expression(&mut self) -> Option<ParseTreeNode<'file>> {
-        let left = self.parse_logical_and_expression()?;
-
-        if let Some(symbol) = self.optional_consume_identifier_value("or") {
-            let right = self.parse_logical_or_expression()?;
+        self.parse_level_of(ExpressionType::LogicalOr)
+    }
 
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::LogicalOr, symbols: vec![symbol], children: vec![left, right] })
-        }
-        else {
-            Some(left)
-        }
+    pub fn parse_logical_xor_expression(&mut self) -> Option<ParseTreeNode<'file>> {
+        self.parse_level_of(ExpressionType::LogicalXor)
     }
 
     pub fn parse_assignment_expressions(&mut self) -> Option<ParseTreeNode<'file>> {
-        let left = self.parse_logical_or_expression()?;
-
-        if let Some(symbol) = self.optional_consume_symbol("=") {
-            let right = self.parse_assignment_expressions()?;
-
-            Some(ParseTreeNode::Expression { expression_type: ExpressionType::Assignment, symbols: vec![symbol], children: vec![left, right] })
-        }
-        else {
-            Some(left)
-        }
+        self.parse_precedence_level(0)
     }
-}
\ No newline at end of file
+}
@@ -4,5 +4,10 @@ pub use context::*;
 pub mod expression;
 pub use expression::*;
 
+pub mod pretty;
+
 pub mod parsetree;
-pub use parsetree::*;
\ No newline at end of file
+pub use parsetree::*;
+
+pub mod visitor;
+pub use visitor::*;
\ No newline at end of file
@@ -1,4 +1,4 @@
-use crate::tokenizer::Token;
+use crate::tokenizer::{Location, Token};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExpressionType {
@@ -7,29 +7,61 @@ pub enum ExpressionType {
     Subtract,
     Multiply,
     Divide,
+    FloorDivide,
     MemberAccess,
     Indexing,
     LogicalOr,
     LogicalAnd,
+    LogicalXor,
     LessThan,
     GreaterThan,
     LessThanEqual,
     GreaterThanEqual,
     Equality,
     Inequality,
-    FunctionCall
+    Concatenation,
+    FunctionCall,
+    Ternary,
+    Negate,
+    LogicalNot,
+    Modulo,
+    Power,
+    AddAssign,
+    SubAssign,
+    MulAssign,
+    DivAssign
+}
+
+impl ExpressionType {
+    /// The operation applied by a compound assignment such as `+=`
+    pub fn compound_operation(self) -> Option<ExpressionType> {
+        match self {
+            ExpressionType::AddAssign => Some(ExpressionType::Add),
+            ExpressionType::SubAssign => Some(ExpressionType::Subtract),
+            ExpressionType::MulAssign => Some(ExpressionType::Multiply),
+            ExpressionType::DivAssign => Some(ExpressionType::Divide),
+            _ => None
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum ParseTreeNode<'file> {
-    Function{name: Token<'file>, arguments: Vec<Token<'file>>, block: Box<ParseTreeNode<'file>>},
-    Block{statements: Vec<ParseTreeNode<'file>>},
+    Function{name: Token<'file>, arguments: Vec<Token<'file>>, type_hints: Vec<Option<String>>, block: Box<ParseTreeNode<'file>>},
+    Block{token: Token<'file>, statements: Vec<ParseTreeNode<'file>>},
     ReturnStatement{token: Token<'file>, expression: Option<Box<ParseTreeNode<'file>>>},
+    BreakStatement{token: Token<'file>},
+    ContinueStatement{token: Token<'file>},
     IdentifierValue{token: Token<'file>},
     NumericValue{token: Token<'file>, value: f64},
+    IntegerValue{token: Token<'file>, value: i64},
+    StringValue{token: Token<'file>, value: String},
     IfStatement{ifs: Vec<(Token<'file>, ParseTreeNode<'file>, ParseTreeNode<'file>)>, else_block: Option<Box<ParseTreeNode<'file>>> },
-    ForLoop{token: Token<'file>, loop_variable: Token<'file>, bound0: Box<ParseTreeNode<'file>>, bound1: Box<ParseTreeNode<'file>>, reverse: bool, block: Box<ParseTreeNode<'file>> },
+    ForLoop{token: Token<'file>, loop_variable: Token<'file>, bound0: Box<ParseTreeNode<'file>>, bound1: Box<ParseTreeNode<'file>>, step: Option<Box<ParseTreeNode<'file>>>, reverse: bool, block: Box<ParseTreeNode<'file>> },
+    ForEachLoop{token: Token<'file>, item_variable: Token<'file>, array_expression: Box<ParseTreeNode<'file>>, block: Box<ParseTreeNode<'file>> },
     WhileLoop{token: Token<'file>, condition: Box<ParseTreeNode<'file>>, block: Box<ParseTreeNode<'file>>},
+    RepeatLoop{token: Token<'file>, block: Box<ParseTreeNode<'file>>, condition: Box<ParseTreeNode<'file>>},
+    SwitchStatement{token: Token<'file>, value: Box<ParseTreeNode<'file>>, cases: Vec<(ParseTreeNode<'file>, ParseTreeNode<'file>)>, default: Option<Box<ParseTreeNode<'file>>>},
     Expression{expression_type: ExpressionType, symbols: Vec<Token<'file>>, children: Vec<ParseTreeNode<'file>>}
 }
 
@@ -37,14 +69,164 @@ impl<'file> ParseTreeNode<'file> {
     pub fn get_token(&self) -> &Token<'file> {
         match self {
             ParseTreeNode::Function { name, .. } => name,
-            ParseTreeNode::Block { statements } => statements[0].get_token(),
+            ParseTreeNode::Block { token, statements } => statements.first().map(|s| s.get_token()).unwrap_or(token),
             ParseTreeNode::ReturnStatement { token, .. } => token,
+            ParseTreeNode::BreakStatement { token } => token,
+            ParseTreeNode::ContinueStatement { token } => token,
             ParseTreeNode::IdentifierValue { token } => token,
             ParseTreeNode::NumericValue { token, .. } => token,
+            ParseTreeNode::IntegerValue { token, .. } => token,
+            ParseTreeNode::StringValue { token, .. } => token,
             ParseTreeNode::IfStatement { ifs, .. } => &ifs[0].0,
             ParseTreeNode::ForLoop { token, .. } => token,
+            ParseTreeNode::ForEachLoop { token, .. } => token,
             ParseTreeNode::WhileLoop { token, .. } => token,
+            ParseTreeNode::RepeatLoop { token, .. } => token,
+            ParseTreeNode::SwitchStatement { token, .. } => token,
             ParseTreeNode::Expression { symbols, .. } => &symbols[0],
         }
     }
-}
\ No newline at end of file
+
+    /// Tokens held by this node itself, not counting those of its children
+    fn own_tokens(&self) -> Vec<&Token<'file>> {
+        match self {
+            ParseTreeNode::Function { name, arguments, .. } => std::iter::once(name).chain(arguments).collect(),
+            ParseTreeNode::Block { token, statements } => if statements.is_empty() { vec![token] } else { vec![] },
+            ParseTreeNode::IfStatement { ifs, .. } => ifs.iter().map(|(token, _, _)| token).collect(),
+            ParseTreeNode::ForLoop { token, loop_variable, .. } => vec![token, loop_variable],
+            ParseTreeNode::ForEachLoop { token, item_variable, .. } => vec![token, item_variable],
+            ParseTreeNode::Expression { symbols, .. } => symbols.iter().collect(),
+            _ => vec![self.get_token()]
+        }
+    }
+
+    /// The token which appears first in the source text of this node, unlike
+    /// `get_token` which gives the operator of an expression
+    pub fn first_token(&self) -> &Token<'file> {
+        self.children().into_iter().map(|c| c.first_token()).chain(self.own_tokens())
+            .min_by_key(|t| t.location.index).unwrap_or_else(|| self.get_token())
+    }
+
+    /// The token which appears last in the source text of this node
+    pub fn last_token(&self) -> &Token<'file> {
+        self.children().into_iter().map(|c| c.last_token()).chain(self.own_tokens())
+            .max_by_key(|t| t.location.index).unwrap_or_else(|| self.get_token())
+    }
+
+    /// Locations of the first and last tokens of this node, spanning its source text
+    pub fn position_range(&self) -> (Location<'file>, Location<'file>) {
+        (self.first_token().location.clone(), self.last_token().location.clone())
+    }
+
+    /// Whether a value can be assigned to this node, as a variable, an array
+    /// element or a member
+    pub fn is_assignable(&self) -> bool {
+        matches!(self, ParseTreeNode::IdentifierValue { .. }
+            | ParseTreeNode::Expression { expression_type: ExpressionType::Indexing | ExpressionType::MemberAccess, .. })
+    }
+
+    /// Direct children of this node, in source order
+    pub fn children(&self) -> Vec<&ParseTreeNode<'file>> {
+        match self {
+            ParseTreeNode::Function { block, .. } => vec![&**block],
+            ParseTreeNode::Block { statements, .. } => statements.iter().collect(),
+            ParseTreeNode::ReturnStatement { expression, .. } => expression.iter().map(|e| &**e).collect(),
+            ParseTreeNode::BreakStatement { .. } |
+            ParseTreeNode::ContinueStatement { .. } |
+            ParseTreeNode::IdentifierValue { .. } |
+            ParseTreeNode::NumericValue { .. } |
+            ParseTreeNode::IntegerValue { .. } |
+            ParseTreeNode::StringValue { .. } => vec![],
+            ParseTreeNode::IfStatement { ifs, else_block } => {
+                ifs.iter().flat_map(|(_, condition, block)| [condition, block])
+                    .chain(else_block.iter().map(|b| &**b)).collect()
+            },
+            ParseTreeNode::ForLoop { bound0, bound1, step, block, .. } => {
+                [&**bound0, &**bound1].into_iter().chain(step.iter().map(|s| &**s)).chain(std::iter::once(&**block)).collect()
+            },
+            ParseTreeNode::ForEachLoop { array_expression, block, .. } => vec![&**array_expression, &**block],
+            ParseTreeNode::WhileLoop { condition, block, .. } => vec![&**condition, &**block],
+            ParseTreeNode::RepeatLoop { block, condition, .. } => vec![&**block, &**condition],
+            ParseTreeNode::SwitchStatement { value, cases, default, .. } => {
+                std::iter::once(&**value).chain(cases.iter().flat_map(|(case, block)| [case, block]))
+                    .chain(default.iter().map(|b| &**b)).collect()
+            },
+            ParseTreeNode::Expression { children, .. } => children.iter().collect(),
+        }
+    }
+
+    pub fn child_count(&self) -> usize {
+        self.children().len()
+    }
+
+    pub fn child_at(&self, i: usize) -> Option<&ParseTreeNode<'file>> {
+        self.children().get(i).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser::ParserContext, tokenizer::TokenStream};
+
+    use super::*;
+
+    /// Follow `path` down from `node`, taking the child at each index in turn
+    fn descend<'a, 'file>(node: &'a ParseTreeNode<'file>, path: &[usize]) -> &'a ParseTreeNode<'file> {
+        path.iter().fold(node, |node, i| node.child_at(*i).unwrap())
+    }
+
+    #[test]
+    fn child_count_of_each_node() {
+        let source = "\
+F(a)
+    if a
+        return
+    elseif a
+        x = -1.5
+    else
+        x = \"s\"
+    for i = 1 to 10 by 2
+        x = F(a, 2)
+    for y in a
+        x = y
+    while a
+        break
+    repeat
+        continue
+    until a
+    case a of
+        1: x = 1
+        else: x = 2
+    return a + 1
+";
+        let mut tokens = TokenStream::from_source(source, "test.ps");
+        let (parse_tree, _) = ParserContext::new(&mut tokens).parse_document().unwrap();
+
+        let function = &parse_tree[0];
+        assert_eq!(function.child_count(), 1);
+
+        let block = descend(function, &[0]);
+        assert_eq!(block.child_count(), 7);
+
+        // if, for, for in, while, repeat, case and return
+        let counts = block.children().iter().map(|s| s.child_count()).collect::<Vec<_>>();
+        assert_eq!(counts, [5, 4, 2, 2, 2, 4, 1]);
+
+        // return, x = -1.5, its negation and 1.5, then the string literal
+        assert_eq!(descend(block, &[0, 1, 0]).child_count(), 0);
+        assert_eq!(descend(block, &[0, 3, 0]).child_count(), 2);
+        assert_eq!(descend(block, &[0, 3, 0, 1]).child_count(), 1);
+        assert_eq!(descend(block, &[0, 3, 0, 1, 0]).child_count(), 0);
+        assert_eq!(descend(block, &[0, 4, 0, 1]).child_count(), 0);
+
+        // A call counts the function name along with its arguments
+        assert_eq!(descend(block, &[1, 3, 0, 1]).child_count(), 3);
+
+        // break and continue
+        assert_eq!(descend(block, &[3, 1, 0]).child_count(), 0);
+        assert_eq!(descend(block, &[4, 0, 0]).child_count(), 0);
+
+        assert_eq!(descend(block, &[6, 0]).child_count(), 2);
+        assert!(descend(block, &[6]).child_at(1).is_none());
+    }
+}
@@ -0,0 +1,278 @@
+use std::{fmt::Write, collections::VecDeque};
+
+use super::{ParseTreeNode, ExpressionType, PrecedenceClimber, Associativity};
+
+impl<'file> ParseTreeNode<'file> {
+    /// Render the node back to source text, indenting each block by `indent_size` spaces
+    pub fn pretty_print(&self, indent_size: usize) -> String {
+        let mut printer = PrettyPrinter::new(indent_size, Vec::new());
+        printer.print_statement(self, 0);
+        printer.output
+    }
+}
+
+/// Render every function in a document back to source text, separated by
+/// blank lines, keeping the `//` comments given with their (zero indexed) lines.
+/// Comments are placed before the statement following them, or after the
+/// statement on their line.
+pub fn pretty_print_document<'a>(nodes: &[ParseTreeNode<'_>], indent_size: usize, comments: impl IntoIterator<Item = (usize, &'a str)>) -> String {
+    let mut comments = comments.into_iter().map(|(line, text)| (line, text.to_string())).collect::<Vec<_>>();
+    comments.sort_by_key(|(line, _)| *line);
+
+    let mut printer = PrettyPrinter::new(indent_size, comments);
+
+    for (i, node) in nodes.iter().enumerate() {
+        if i != 0 {
+            printer.output.push('\n');
+        }
+
+        printer.print_statement(node, 0);
+    }
+
+    printer.comments_before(usize::MAX, 0, None);
+    printer.output
+}
+
+struct PrettyPrinter {
+    output: String,
+    indent_size: usize,
+    climber: PrecedenceClimber,
+    /// Comments not yet printed, in order of their lines
+    comments: VecDeque<(usize, String)>,
+    /// Line of the statement being printed, until its first line is written
+    statement_line: Option<usize>
+}
+
+impl PrettyPrinter {
+    fn new(indent_size: usize, comments: Vec<(usize, String)>) -> Self {
+        Self { output: String::new(), indent_size, climber: PrecedenceClimber::default(), comments: comments.into(), statement_line: None }
+    }
+
+    /// Write a line of the statement being printed, followed by the comment
+    /// from the end of its source line if this is its first line
+    fn line(&mut self, depth: usize, text: &str) {
+        let statement_line = self.statement_line.take();
+        let comment = match self.comments.front() {
+            Some((line, _)) if Some(*line) == statement_line => self.comments.pop_front().map(|(_, comment)| comment),
+            _ => None
+        };
+
+        match comment {
+            Some(comment) => self.write_line(depth, &format!("{} //{}", text, comment)),
+            None => self.write_line(depth, text)
+        }
+    }
+
+    fn write_line(&mut self, depth: usize, text: &str) {
+        let _ = writeln!(self.output, "{:width$}{}", "", text, width = depth * self.indent_size);
+    }
+
+    /// Write the comments from lines before `line` on lines of their own,
+    /// except the one on `type_hint_line`, which is printed from the parsed types
+    fn comments_before(&mut self, line: usize, depth: usize, type_hint_line: Option<usize>) {
+        while let Some((comment_line, comment)) = self.comments.front().filter(|(l, _)| *l < line).cloned() {
+            self.comments.pop_front();
+
+            if Some(comment_line) != type_hint_line {
+                self.write_line(depth, &format!("//{}", comment));
+            }
+        }
+    }
+
+    /// Line of the first comment still to be printed, if it comes before `line`
+    fn first_comment_before(&self, line: usize) -> Option<usize> {
+        self.comments.front().map(|(l, _)| *l).filter(|l| *l < line)
+    }
+
+    fn print_block(&mut self, node: &ParseTreeNode, depth: usize) {
+        if let ParseTreeNode::Block { statements, .. } = node {
+            let mut last: Option<(usize, usize)> = None;
+
+            for statement in statements {
+                // Comments printed before the statement count as part of it
+                let line = statement.first_token().location.line;
+                let line = self.first_comment_before(line).unwrap_or(line);
+
+                // Keep a single blank line wherever the source had a gap between statements
+                if let Some((last_line, printed_lines)) = last {
+                    if line > last_line + printed_lines {
+                        self.output.push('\n');
+                    }
+                }
+
+                let before = self.output.len();
+                self.print_statement(statement, depth);
+                last = Some((line, self.output[before..].matches('\n').count()));
+            }
+        }
+        else {
+            self.print_statement(node, depth);
+        }
+    }
+
+    fn print_statement(&mut self, node: &ParseTreeNode, depth: usize) {
+        let line = node.first_token().location.line;
+        let type_hint_line = match node {
+            ParseTreeNode::Function { name, type_hints, .. } if type_hints.iter().any(|h| h.is_some()) => name.location.line.checked_sub(1),
+            _ => None
+        };
+
+        self.comments_before(line, depth, type_hint_line);
+        self.statement_line = Some(line);
+
+        match node {
+            ParseTreeNode::Function { name, arguments, type_hints, block } => {
+                let hints = arguments.iter().zip(type_hints)
+                    .filter_map(|(a, h)| h.as_ref().map(|h| format!("{}: {}", a.extract_text(), h)))
+                    .collect::<Vec<_>>();
+
+                if !hints.is_empty() {
+                    self.line(depth, &format!("// @type {}", hints.join(", ")));
+                }
+
+                let arguments = arguments.iter().map(|a| a.extract_text().to_string()).collect::<Vec<_>>();
+                self.line(depth, &format!("{}({})", name.extract_text(), arguments.join(", ")));
+                self.print_block(block, depth + 1);
+            }
+            ParseTreeNode::Block { .. } => self.print_block(node, depth),
+            ParseTreeNode::ReturnStatement { expression: Some(expression), .. } => {
+                let text = format!("return {}", self.expression(expression));
+                self.line(depth, &text);
+            }
+            ParseTreeNode::ReturnStatement { expression: None, .. } => self.line(depth, "return"),
+            ParseTreeNode::BreakStatement { .. } => self.line(depth, "break"),
+            ParseTreeNode::ContinueStatement { .. } => self.line(depth, "continue"),
+            ParseTreeNode::IfStatement { ifs, else_block } => {
+                for (i, (_, condition, block)) in ifs.iter().enumerate() {
+                    let text = format!("{} {}", if i == 0 { "if" } else { "elseif" }, self.expression(condition));
+                    self.line(depth, &text);
+                    self.print_block(block, depth + 1);
+                }
+
+                if let Some(else_block) = else_block {
+                    self.line(depth, "else");
+                    self.print_block(else_block, depth + 1);
+                }
+            }
+            ParseTreeNode::ForLoop { loop_variable, bound0, bound1, step, reverse, block, .. } => {
+                let mut text = format!("for {} = {} {}to {}", loop_variable.extract_text(), self.expression(bound0),
+                                        if *reverse { "down " } else { "" }, self.expression(bound1));
+                if let Some(step) = step {
+                    text += &format!(" by {}", self.expression(step));
+                }
+                self.line(depth, &text);
+                self.print_block(block, depth + 1);
+            }
+            ParseTreeNode::ForEachLoop { item_variable, array_expression, block, .. } => {
+                let text = format!("for {} in {}", item_variable.extract_text(), self.expression(array_expression));
+                self.line(depth, &text);
+                self.print_block(block, depth + 1);
+            }
+            ParseTreeNode::WhileLoop { condition, block, .. } => {
+                let text = format!("while {}", self.expression(condition));
+                self.line(depth, &text);
+                self.print_block(block, depth + 1);
+            }
+            ParseTreeNode::SwitchStatement { value, cases, default, .. } => {
+                let text = format!("case {} of", self.expression(value));
+                self.line(depth, &text);
+
+                for (case, block) in cases {
+                    let text = format!("{}:", self.expression(case));
+                    self.line(depth + 1, &text);
+                    self.print_block(block, depth + 2);
+                }
+
+                if let Some(default) = default {
+                    self.line(depth + 1, "else:");
+                    self.print_block(default, depth + 2);
+                }
+            }
+            ParseTreeNode::RepeatLoop { block, condition, .. } => {
+                self.line(depth, "repeat");
+                self.print_block(block, depth + 1);
+                let text = format!("until {}", self.expression(condition));
+                self.line(depth, &text);
+            }
+            _ => {
+                let text = self.expression(node);
+                self.line(depth, &text);
+            }
+        }
+    }
+
+    /// How tightly an expression binds, with higher values binding tighter. An
+    /// inline if binds as loosely as assignment, since its last branch extends
+    /// as far to the right as it can.
+    fn binding(&self, node: &ParseTreeNode) -> usize {
+        let prefix = self.climber.levels.len();
+        let power = prefix + 1;
+        let postfix = power + 1;
+
+        match node {
+            ParseTreeNode::Expression { expression_type: ExpressionType::Ternary, .. } => 0,
+            ParseTreeNode::Expression { expression_type: ExpressionType::Negate | ExpressionType::LogicalNot, .. } => prefix,
+            ParseTreeNode::Expression { expression_type: ExpressionType::Power, .. } => power,
+            ParseTreeNode::Expression { expression_type: ExpressionType::Indexing | ExpressionType::MemberAccess | ExpressionType::FunctionCall, .. } => postfix,
+            ParseTreeNode::Expression { expression_type, .. } => self.climber.level_of(*expression_type).unwrap_or(postfix),
+            _ => postfix + 1
+        }
+    }
+
+    fn parenthesized(&self, node: &ParseTreeNode, parenthesize: bool) -> String {
+        if parenthesize {
+            format!("({})", self.expression(node))
+        }
+        else {
+            self.expression(node)
+        }
+    }
+
+    fn expression(&self, node: &ParseTreeNode) -> String {
+        match node {
+            ParseTreeNode::Expression { expression_type: ExpressionType::FunctionCall, children, .. } => {
+                let arguments = children[1..].iter().map(|c| self.expression(c)).collect::<Vec<_>>();
+                format!("{}({})", self.parenthesized(&children[0], self.binding(&children[0]) < self.binding(node)), arguments.join(", "))
+            }
+            ParseTreeNode::Expression { expression_type: ExpressionType::Indexing, children, .. } => {
+                format!("{}[{}]", self.parenthesized(&children[0], self.binding(&children[0]) < self.binding(node)), self.expression(&children[1]))
+            }
+            ParseTreeNode::Expression { expression_type: ExpressionType::MemberAccess, children, .. } => {
+                format!("{}.{}", self.parenthesized(&children[0], self.binding(&children[0]) < self.binding(node)), self.expression(&children[1]))
+            }
+            ParseTreeNode::Expression { expression_type: ExpressionType::Negate, symbols, children } => {
+                format!("{}{}", symbols[0].extract_text(), self.parenthesized(&children[0], self.binding(&children[0]) <= self.binding(node)))
+            }
+            ParseTreeNode::Expression { expression_type: ExpressionType::LogicalNot, symbols, children } => {
+                format!("{} {}", symbols[0].extract_text(), self.parenthesized(&children[0], self.binding(&children[0]) <= self.binding(node)))
+            }
+            // The base of a power is a postfix expression, while the exponent may be any unary expression
+            ParseTreeNode::Expression { expression_type: ExpressionType::Power, symbols, children } => {
+                let prefix = self.climber.levels.len();
+
+                format!("{} {} {}", self.parenthesized(&children[0], self.binding(&children[0]) <= self.binding(node)), symbols[0].extract_text(),
+                                    self.parenthesized(&children[1], self.binding(&children[1]) < prefix))
+            }
+            ParseTreeNode::Expression { expression_type: ExpressionType::Ternary, children, .. } => {
+                let parts = children.iter().map(|c| self.parenthesized(c, self.binding(c) == 0)).collect::<Vec<_>>();
+                format!("if {} then {} else {}", parts[0], parts[1], parts[2])
+            }
+            ParseTreeNode::Expression { symbols, children, .. } => {
+                let level = self.binding(node);
+                let associativity = self.climber.levels.get(level).map(|l| l.1).unwrap_or(Associativity::Left);
+
+                let (left, right) = (&children[0], &children[1]);
+                let left_parens = self.binding(left) < level || (self.binding(left) == level && associativity == Associativity::Right)
+                                    || matches!(left, ParseTreeNode::Expression { expression_type: ExpressionType::Ternary, .. });
+                let right_parens = self.binding(right) < level || (self.binding(right) == level && associativity == Associativity::Left);
+
+                format!("{} {} {}", self.parenthesized(left, left_parens), symbols[0].extract_text(), self.parenthesized(right, right_parens))
+            }
+            ParseTreeNode::IdentifierValue { token } |
+            ParseTreeNode::NumericValue { token, .. } |
+            ParseTreeNode::IntegerValue { token, .. } |
+            ParseTreeNode::StringValue { token, .. } => token.extract_text().to_string(),
+            _ => String::new()
+        }
+    }
+}
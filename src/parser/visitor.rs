@@ -0,0 +1,263 @@
+use std::collections::BTreeSet;
+
+use super::{ParseTreeNode, ExpressionType};
+
+/// Called by `ParseTreeNode::walk` for every node, parents before their children.
+/// The methods for each kind of statement and value fall back to `visit_statement`
+/// and `visit_value`, so a visitor only needs to override what it cares about.
+/// Nodes are borrowed for as long as the tree, so a visitor may keep them.
+pub trait NodeVisitor<'file> {
+    fn visit_function(&mut self, _node: &'file ParseTreeNode<'file>) {}
+    fn visit_block(&mut self, _node: &'file ParseTreeNode<'file>) {}
+    fn visit_statement(&mut self, _node: &'file ParseTreeNode<'file>) {}
+    fn visit_expression(&mut self, _node: &'file ParseTreeNode<'file>) {}
+    fn visit_value(&mut self, _node: &'file ParseTreeNode<'file>) {}
+
+    fn visit_return(&mut self, node: &'file ParseTreeNode<'file>) {
+        self.visit_statement(node)
+    }
+
+    fn visit_break(&mut self, node: &'file ParseTreeNode<'file>) {
+        self.visit_statement(node)
+    }
+
+    fn visit_continue(&mut self, node: &'file ParseTreeNode<'file>) {
+        self.visit_statement(node)
+    }
+
+    fn visit_if(&mut self, node: &'file ParseTreeNode<'file>) {
+        self.visit_statement(node)
+    }
+
+    fn visit_for_loop(&mut self, node: &'file ParseTreeNode<'file>) {
+        self.visit_statement(node)
+    }
+
+    fn visit_for_each_loop(&mut self, node: &'file ParseTreeNode<'file>) {
+        self.visit_statement(node)
+    }
+
+    fn visit_while_loop(&mut self, node: &'file ParseTreeNode<'file>) {
+        self.visit_statement(node)
+    }
+
+    fn visit_repeat_loop(&mut self, node: &'file ParseTreeNode<'file>) {
+        self.visit_statement(node)
+    }
+
+    fn visit_switch(&mut self, node: &'file ParseTreeNode<'file>) {
+        self.visit_statement(node)
+    }
+
+    fn visit_identifier(&mut self, node: &'file ParseTreeNode<'file>) {
+        self.visit_value(node)
+    }
+
+    fn visit_number(&mut self, node: &'file ParseTreeNode<'file>) {
+        self.visit_value(node)
+    }
+
+    fn visit_string(&mut self, node: &'file ParseTreeNode<'file>) {
+        self.visit_value(node)
+    }
+}
+
+/// Visitor which counts how many of each kind of node were visited
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DefaultNodeVisitor {
+    pub functions: usize,
+    pub blocks: usize,
+    pub statements: usize,
+    pub expressions: usize,
+    pub values: usize
+}
+
+impl<'file> NodeVisitor<'file> for DefaultNodeVisitor {
+    fn visit_function(&mut self, _node: &'file ParseTreeNode<'file>) {
+        self.functions += 1;
+    }
+
+    fn visit_block(&mut self, _node: &'file ParseTreeNode<'file>) {
+        self.blocks += 1;
+    }
+
+    fn visit_statement(&mut self, _node: &'file ParseTreeNode<'file>) {
+        self.statements += 1;
+    }
+
+    fn visit_expression(&mut self, _node: &'file ParseTreeNode<'file>) {
+        self.expressions += 1;
+    }
+
+    fn visit_value(&mut self, _node: &'file ParseTreeNode<'file>) {
+        self.values += 1;
+    }
+}
+
+/// Visitor which collects the names of every variable used, including function
+/// arguments and loop variables, but not the names of called functions or
+/// accessed attributes
+#[derive(Debug, Clone, Default)]
+pub struct VariableCollector<'file> {
+    pub variables: BTreeSet<&'file str>,
+    /// Function and attribute names seen in their expression but not yet
+    /// visited themselves, innermost last
+    pending_names: Vec<&'file ParseTreeNode<'file>>
+}
+
+impl<'file> NodeVisitor<'file> for VariableCollector<'file> {
+    fn visit_function(&mut self, node: &'file ParseTreeNode<'file>) {
+        if let ParseTreeNode::Function { arguments, .. } = node {
+            self.variables.extend(arguments.iter().map(|a| a.extract_text().as_ref()));
+        }
+    }
+
+    fn visit_for_loop(&mut self, node: &'file ParseTreeNode<'file>) {
+        if let ParseTreeNode::ForLoop { loop_variable, .. } = node {
+            self.variables.insert(loop_variable.extract_text());
+        }
+    }
+
+    fn visit_for_each_loop(&mut self, node: &'file ParseTreeNode<'file>) {
+        if let ParseTreeNode::ForEachLoop { item_variable, .. } = node {
+            self.variables.insert(item_variable.extract_text());
+        }
+    }
+
+    fn visit_expression(&mut self, node: &'file ParseTreeNode<'file>) {
+        match node {
+            ParseTreeNode::Expression { expression_type: ExpressionType::FunctionCall, children, .. } => self.pending_names.push(&children[0]),
+            ParseTreeNode::Expression { expression_type: ExpressionType::MemberAccess, children, .. } => self.pending_names.push(&children[1]),
+            _ => {}
+        }
+    }
+
+    fn visit_identifier(&mut self, node: &'file ParseTreeNode<'file>) {
+        // Names are visited after everything their expression pushed before them
+        if self.pending_names.last().is_some_and(|name| std::ptr::eq(*name, node)) {
+            self.pending_names.pop();
+        }
+        else if let ParseTreeNode::IdentifierValue { token } = node {
+            self.variables.insert(token.extract_text());
+        }
+    }
+}
+
+impl<'file> ParseTreeNode<'file> {
+    pub fn walk(&'file self, visitor: &mut dyn NodeVisitor<'file>) {
+        match self {
+            ParseTreeNode::Function { .. } => visitor.visit_function(self),
+            ParseTreeNode::Block { .. } => visitor.visit_block(self),
+            ParseTreeNode::ReturnStatement { .. } => visitor.visit_return(self),
+            ParseTreeNode::BreakStatement { .. } => visitor.visit_break(self),
+            ParseTreeNode::ContinueStatement { .. } => visitor.visit_continue(self),
+            ParseTreeNode::IfStatement { .. } => visitor.visit_if(self),
+            ParseTreeNode::ForLoop { .. } => visitor.visit_for_loop(self),
+            ParseTreeNode::ForEachLoop { .. } => visitor.visit_for_each_loop(self),
+            ParseTreeNode::WhileLoop { .. } => visitor.visit_while_loop(self),
+            ParseTreeNode::RepeatLoop { .. } => visitor.visit_repeat_loop(self),
+            ParseTreeNode::SwitchStatement { .. } => visitor.visit_switch(self),
+            ParseTreeNode::IdentifierValue { .. } => visitor.visit_identifier(self),
+            ParseTreeNode::NumericValue { .. } | ParseTreeNode::IntegerValue { .. } => visitor.visit_number(self),
+            ParseTreeNode::StringValue { .. } => visitor.visit_string(self),
+            ParseTreeNode::Expression { .. } => visitor.visit_expression(self),
+        }
+
+        for child in self.children() {
+            child.walk(visitor);
+        }
+    }
+
+    /// Call `visitor` on this node and every node below it which satisfies `predicate`, in source order
+    fn for_each_matching(&self, predicate: fn(&ParseTreeNode) -> bool, visitor: &mut impl FnMut(&ParseTreeNode<'file>)) {
+        if predicate(self) {
+            visitor(self);
+        }
+
+        for child in self.children() {
+            child.for_each_matching(predicate, visitor);
+        }
+    }
+
+    /// Call `visitor` with every `return` statement in or below this node
+    pub fn for_each_return(&self, visitor: &mut impl FnMut(&ParseTreeNode<'file>)) {
+        self.for_each_matching(|n| matches!(n, ParseTreeNode::ReturnStatement { .. }), visitor)
+    }
+
+    /// Call `visitor` with every identifier in or below this node, including
+    /// the names of called functions and accessed attributes
+    pub fn for_each_identifier(&self, visitor: &mut impl FnMut(&ParseTreeNode<'file>)) {
+        self.for_each_matching(|n| matches!(n, ParseTreeNode::IdentifierValue { .. }), visitor)
+    }
+
+    /// Call `visitor` with every function defined in or below this node, including this node itself
+    pub fn for_each_function(&self, visitor: &mut impl FnMut(&ParseTreeNode<'file>)) {
+        self.for_each_matching(|n| matches!(n, ParseTreeNode::Function { .. }), visitor)
+    }
+
+    /// Call `visitor` with every function call expression in or below this node
+    pub fn for_each_function_call(&self, visitor: &mut impl FnMut(&ParseTreeNode<'file>)) {
+        self.for_each_matching(|n| matches!(n, ParseTreeNode::Expression { expression_type: ExpressionType::FunctionCall, .. }), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser::ParserContext, tokenizer::TokenStream};
+
+    use super::*;
+
+    fn parse(source: &str) -> Vec<ParseTreeNode<'_>> {
+        let mut tokens = TokenStream::from_source(source, "test.ps");
+        ParserContext::new(&mut tokens).parse_document().unwrap().0
+    }
+
+    #[test]
+    fn variable_collector_skips_function_and_attribute_names() {
+        let source = "\
+Sum(A, start)
+    total = start
+    for i = 1 to A.length
+        total = total + Helper(A[i])
+    for x in A
+        last = x
+    return total
+";
+        let parse_tree = parse(source);
+        let mut collector = VariableCollector::default();
+        for node in &parse_tree {
+            node.walk(&mut collector);
+        }
+
+        assert_eq!(collector.variables.into_iter().collect::<Vec<_>>(), ["A", "i", "last", "start", "total", "x"]);
+    }
+
+    #[test]
+    fn variable_collector_skips_nested_names() {
+        let parse_tree = parse("G(B)\n    return Helper(B.length, y).size + B.length\n");
+        let mut collector = VariableCollector::default();
+        for node in &parse_tree {
+            node.walk(&mut collector);
+        }
+
+        assert_eq!(collector.variables.into_iter().collect::<Vec<_>>(), ["B", "y"]);
+    }
+
+    #[test]
+    fn specific_methods_fall_back_to_their_category() {
+        let source = "\
+F(a)
+    while a
+        break
+    return \"s\"
+";
+        let parse_tree = parse(source);
+        let mut counter = DefaultNodeVisitor::default();
+        for node in &parse_tree {
+            node.walk(&mut counter);
+        }
+
+        // while, break and return are statements, the condition and the string are values
+        assert_eq!(counter, DefaultNodeVisitor { functions: 1, blocks: 2, statements: 3, expressions: 0, values: 2 });
+    }
+}
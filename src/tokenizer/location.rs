@@ -1,29 +1,50 @@
-use std::{str::CharIndices, borrow::Cow, collections::VecDeque};
+use std::{str::CharIndices, borrow::Cow, collections::VecDeque, sync::Arc};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct Location<'filename> {
-    pub filename: &'filename str,
+    /// Shared by every location in the file, so copying a location never copies the name
+    pub filename: Arc<str>,
     pub line: usize,
     pub column: usize,
     pub index: usize,
-    pub file_text: Option<&'filename str>
+    /// Text of the whole file, used to show the offending line in errors
+    pub file_text: Option<FileText<'filename>>
+}
+
+/// Text of a source file, borrowed when tokenizing a `&str` and shared between
+/// every location when the tokenizer owns the text
+#[derive(Debug, Clone)]
+pub enum FileText<'file> {
+    Borrowed(&'file str),
+    Shared(Arc<str>)
+}
+
+impl<'file> std::ops::Deref for FileText<'file> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            FileText::Borrowed(text) => text,
+            FileText::Shared(text) => text
+        }
+    }
 }
 
 pub struct LocationTrack<'file> {
     pub raw: &'file str,
     characters: CharIndices<'file>,
     last_reported_index: usize,
-    name: &'file str,
+    name: Arc<str>,
     line: usize,
     column: usize,
     cached_next: Option<(usize, char)>,
 }
 
 pub struct LocationTrackOwned {
-    pub raw: String,
+    pub raw: Arc<str>,
     characters: VecDeque<(usize, char)>,
     last_reported_index: usize,
-    name: &'static str,
+    name: Arc<str>,
     line: usize,
     column: usize,
     cached_next: Option<(usize, char)>,
@@ -42,6 +63,35 @@ pub trait LocationTracker<'file>: std::iter::Iterator<Item = (usize, Location<'f
     fn next_location(&self) -> Location<'file>;
 }
 
+// The file text is only carried along for error rendering, so it takes no
+// part in comparing or hashing positions
+impl<'filename> PartialEq for Location<'filename> {
+    fn eq(&self, other: &Self) -> bool {
+        self.filename == other.filename &&
+        self.line == other.line &&
+        self.column == other.column &&
+        self.index == other.index
+    }
+}
+
+impl<'filename> Eq for Location<'filename> {}
+
+impl<'filename> std::hash::Hash for Location<'filename> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.filename.hash(state);
+        self.line.hash(state);
+        self.column.hash(state);
+        self.index.hash(state);
+    }
+}
+
+impl Location<'static> {
+    /// Location which does not come from any source file
+    pub fn synthetic() -> Location<'static> {
+        Location { filename: "<synthetic>".into(), line: 0, column: 0, index: 0, file_text: None }
+    }
+}
+
 impl<'filename> std::fmt::Display for Location<'filename> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "line {}, column {} in file {}", self.line + 1, self.column + 1, self.filename)
@@ -56,7 +106,7 @@ impl<'file> LocationTrack<'file> {
             characters: s.char_indices(),
             last_reported_index: 0,
             raw: s,
-            name,
+            name: name.into(),
             line: 0,
             column: 0
         }
@@ -125,7 +175,7 @@ impl<'file> LocationTracker<'file> for LocationTrack<'file> {
     }
 
     fn next_location(&self) -> Location<'file> {
-        Location { filename: self.name, line: self.line, column: self.column, index: self.last_reported_index + 1, file_text: Some(self.raw) }
+        Location { filename: self.name.clone(), line: self.line, column: self.column, index: self.last_reported_index + 1, file_text: Some(FileText::Borrowed(self.raw)) }
     }
 }
 
@@ -136,7 +186,7 @@ impl<'file> std::iter::Iterator for LocationTrack<'file> {
         self.peek();
 
         self.cached_next.take().map( |(index, character)| {
-            let location = Location { filename: self.name, line: self.line, column: self.column, index, file_text: Some(self.raw) };
+            let location = Location { filename: self.name.clone(), line: self.line, column: self.column, index, file_text: Some(FileText::Borrowed(self.raw)) };
 
             self.consume((index, character));
 
@@ -148,13 +198,22 @@ impl<'file> std::iter::Iterator for LocationTrack<'file> {
 
 impl LocationTrackOwned {
     pub fn new<Data: Into<String>>(data: Data, name: &'static str) -> Self {
+        Self::with_name(data, name)
+    }
+
+    /// Same as `new`, for a file name which is only known at runtime
+    pub fn new_owned(data: String, name: String) -> Self {
+        Self::with_name(data, name)
+    }
+
+    pub(crate) fn with_name<Data: Into<String>, Name: Into<Arc<str>>>(data: Data, name: Name) -> Self {
         let s = data.into();
         Self {
             cached_next: None,
-            raw: s.clone(),
+            raw: s.as_str().into(),
             characters: s.char_indices().collect(),
             last_reported_index: 0,
-            name,
+            name: name.into(),
             line: 0,
             column: 0
         }
@@ -222,7 +281,7 @@ impl LocationTracker<'static> for LocationTrackOwned {
     }
 
     fn next_location(&self) -> Location<'static> {
-        Location { filename: self.name, line: self.line, column: self.column, index: self.last_reported_index + 1, file_text: None }
+        Location { filename: self.name.clone(), line: self.line, column: self.column, index: self.last_reported_index + 1, file_text: Some(FileText::Shared(self.raw.clone())) }
     }
 }
 
@@ -233,11 +292,45 @@ impl std::iter::Iterator for LocationTrackOwned {
         self.peek();
 
         self.cached_next.take().map( |(index, character)| {
-            let location = Location { filename: self.name, line: self.line, column: self.column, index, file_text: None };
+            let location = Location { filename: self.name.clone(), line: self.line, column: self.column, index, file_text: Some(FileText::Shared(self.raw.clone())) };
 
             self.consume((index, character));
 
             (index, location, character)
         })
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::tokenizer::{FileText, TokenStream};
+
+    #[test]
+    fn locations_ignore_file_text() {
+        let source = "Test()\n    x = 1\n";
+
+        let borrowed = TokenStream::from_source(source, "test.ps").map(|t| t.location).collect::<Vec<_>>();
+        let owned = TokenStream::from_source_owned(source, "test.ps").map(|t| t.location).collect::<Vec<_>>();
+
+        assert!(borrowed.iter().all(|l| matches!(l.file_text, Some(FileText::Borrowed(_)))));
+        assert!(owned.iter().all(|l| matches!(l.file_text, Some(FileText::Shared(_)))));
+        assert_eq!(borrowed, owned);
+
+        let set = borrowed.iter().cloned().collect::<HashSet<_>>();
+        assert!(owned.iter().all(|l| set.contains(l)));
+    }
+
+    #[test]
+    fn locations_share_the_filename() {
+        let source = "Test()\n    x = 1\n";
+
+        for locations in [
+            TokenStream::from_source(source, "test.ps").map(|t| t.location).collect::<Vec<_>>(),
+            TokenStream::from_source_owned(source, "test.ps").map(|t| t.location).collect::<Vec<_>>()
+        ] {
+            assert_eq!(&*locations[0].filename, "test.ps");
+            assert!(locations.iter().all(|l| std::sync::Arc::ptr_eq(&l.filename, &locations[0].filename)));
+        }
+    }
+}
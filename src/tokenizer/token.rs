@@ -1,10 +1,13 @@
 use super::Location;
+use crate::util::json_string;
+
 use std::borrow::Cow;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TokenData<'filedata> {
     Identifier(Cow<'filedata, str>),
     NumericLiteral(Cow<'filedata, str>),
+    StringLiteral(Cow<'filedata, str>),
     Symbol(Cow<'filedata, str>),
     Indentation(Cow<'filedata, str>),
     EndOfFile
@@ -20,6 +23,7 @@ impl<'filedata> std::fmt::Display for TokenData<'filedata> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self {
             TokenData::NumericLiteral(command) => write!(f, "number {}", command),
+            TokenData::StringLiteral(literal) => write!(f, "string {}", literal),
             TokenData::Symbol(symbol) => write!(f, "symbol {}", symbol),
             TokenData::Identifier(identifier) => write!(f, "identifier {}", identifier),
             TokenData::Indentation(indentation) => write!(f, "indentation {}", indentation),
@@ -29,15 +33,28 @@ impl<'filedata> std::fmt::Display for TokenData<'filedata> {
 }
 
 impl<'file> TokenData<'file> {
-    pub fn extract_text(&self) -> &Cow<str> {
+    pub fn extract_text(&self) -> &Cow<'_, str> {
         match &self {
             TokenData::NumericLiteral(literal) => literal,
+            TokenData::StringLiteral(literal) => literal,
             TokenData::Symbol(symbol) => symbol,
             TokenData::Identifier(identifier) => identifier,
             TokenData::Indentation(indentation) => indentation,
             TokenData::EndOfFile=> &Cow::Borrowed(" "),
         }
     }
+
+    /// Short name of the kind of token, as used in machine readable output
+    pub fn kind_name(&self) -> &'static str {
+        match &self {
+            TokenData::NumericLiteral(_) => "number",
+            TokenData::StringLiteral(_) => "string",
+            TokenData::Symbol(_) => "symbol",
+            TokenData::Identifier(_) => "identifier",
+            TokenData::Indentation(_) => "indentation",
+            TokenData::EndOfFile => "eof",
+        }
+    }
 }
 
 impl<'file> Token<'file> {
@@ -47,9 +64,74 @@ impl<'file> Token<'file> {
         }
     }
 
-    pub fn extract_text(&self) -> &Cow<str> {
+    pub fn extract_text(&self) -> &Cow<'_, str> {
         self.data.extract_text()
     }
+
+    /// Copy of this token placed at `new_location`
+    pub fn clone_with_location(&self, new_location: Location<'file>) -> Token<'file> {
+        Token::new(new_location, self.data.clone())
+    }
+
+    /// Copy of this token at the same location, holding `new_data`
+    pub fn clone_with_data(&self, new_data: TokenData<'file>) -> Token<'file> {
+        Token::new(self.location.clone(), new_data)
+    }
+
+    pub fn same_line_as(&self, other: &Token<'_>) -> bool {
+        self.location.line == other.location.line
+    }
+
+    pub fn is_on_line(&self, line: usize) -> bool {
+        self.location.line == line
+    }
+
+    /// JSON object describing this token, as printed by `tokens --format json`
+    /// and `tokenize --json`. Indentation also gives its length as `indent_len`.
+    pub fn to_json(&self) -> String {
+        let text = if self.data == TokenData::EndOfFile { "" } else { self.extract_text() };
+        let indent = if let TokenData::Indentation(s) = &self.data { format!(", \"indent_len\": {}", s.len()) } else { String::new() };
+
+        format!("{{\"type\": {}, \"text\": {}, \"line\": {}, \"column\": {}, \"filename\": {}{}}}",
+            json_string(self.data.kind_name()), json_string(text), self.location.line + 1, self.location.column + 1, json_string(&self.location.filename), indent)
+    }
+}
+
+impl Token<'static> {
+    /// Identifier token which does not come from any source file
+    pub fn synthetic(text: &'static str) -> Token<'static> {
+        Token::new(Location::synthetic(), TokenData::Identifier(Cow::Borrowed(text)))
+    }
+}
+
+/// Replace the escape sequences `\n`, `\t`, `\r`, `\\` and `\"` in the text of a
+/// string literal, or give the byte offset of the first unknown escape and why
+pub fn unescape_string(s: &str) -> Result<Cow<'_, str>, (usize, String)> {
+    if !s.contains('\\') {
+        return Ok(Cow::Borrowed(s));
+    }
+
+    let mut result = String::with_capacity(s.len());
+    let mut characters = s.char_indices();
+
+    while let Some((i, c)) = characters.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match characters.next() {
+            Some((_, 'n')) => result.push('\n'),
+            Some((_, 't')) => result.push('\t'),
+            Some((_, 'r')) => result.push('\r'),
+            Some((_, '\\')) => result.push('\\'),
+            Some((_, '"')) => result.push('"'),
+            Some((_, other)) => return Err((i, format!("unknown escape sequence '\\{}'", other))),
+            None => return Err((i, "missing character after '\\'".to_string()))
+        }
+    }
+
+    Ok(Cow::Owned(result))
 }
 
 impl<'file> std::fmt::Display for Token<'file> {
@@ -62,4 +144,29 @@ impl<'file> std::fmt::Debug for Token<'file> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Token('{}')", self.data)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::{parser::ParserContext, tokenizer::TokenStream};
+
+    use super::*;
+
+    #[test]
+    fn escape_sequences_are_replaced() {
+        assert_eq!(unescape_string(r"line\nnext").unwrap(), "line\nnext");
+        assert_eq!(unescape_string(r#"\t\r\\\""#).unwrap(), "\t\r\\\"");
+        assert!(matches!(unescape_string("plain"), Ok(Cow::Borrowed("plain"))));
+    }
+
+    #[test]
+    fn unknown_escape_sequence_is_an_error() {
+        assert_eq!(unescape_string(r"ab\q").unwrap_err(), (2, r"unknown escape sequence '\q'".to_string()));
+        assert_eq!(unescape_string("ab\\").unwrap_err(), (2, r"missing character after '\'".to_string()));
+
+        let mut tokens = TokenStream::from_source("Test()\n    Print(\"ab\\q\")\n", "test.ps");
+        let errors = ParserContext::new(&mut tokens).parse_document().unwrap_err();
+        let message = errors[0].to_string();
+        assert!(message.contains(r"unknown escape sequence '\q'"), "{}", message);
+        assert!(message.contains("test.ps:2:14"), "{}", message);
+    }
+}
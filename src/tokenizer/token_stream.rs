@@ -1,21 +1,44 @@
 use super::LocationTrack;
 use super::LocationTrackOwned;
 use super::LocationTracker;
+use super::Location;
 use super::Token;
 use super::TokenData;
 
+use crate::error::GenericError;
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
 pub struct TokenStream<'file, I: LocationTracker<'file>> {
     pub location_stream: I,
-    cached_next_token: Option<Token<'file>>,
-    sent_eof: bool
+    /// Tokens read ahead of the parser, the front being the next token
+    lookahead: VecDeque<Token<'file>>,
+    sent_eof: bool,
+    /// Text of every comment read so far, without the leading `//`, with its line
+    comments: Vec<(usize, Cow<'file, str>)>,
+    /// Whether the last token read could end an operand, so a `//` after it may be floor division
+    after_operand: bool,
+    /// Number of spaces each tab counts as in indentation
+    tab_width: usize,
+    /// The character, tab or space, used by the first indented line
+    indentation_character: Option<char>,
+    /// Warnings and errors found while reading tokens, collected by the parser
+    errors: Vec<GenericError<'file>>
 }
 
 impl TokenStream<'static, LocationTrackOwned> {
-    pub fn from_source_owned<Data: Into<String>>(data: Data, name: &'static str) -> TokenStream<'static, LocationTrackOwned> {
+    pub fn from_source_owned<Data: Into<String>, Name: Into<Arc<str>>>(data: Data, name: Name) -> TokenStream<'static, LocationTrackOwned> {
         Self {
-            location_stream: LocationTrackOwned::new(data, name),
-            cached_next_token: None,
-            sent_eof: false
+            location_stream: LocationTrackOwned::with_name(data, name),
+            lookahead: VecDeque::new(),
+            sent_eof: false,
+            comments: Vec::new(),
+            after_operand: false,
+            tab_width: 4,
+            indentation_character: None,
+            errors: Vec::new()
         }
     }
 }
@@ -30,20 +53,96 @@ impl<'file, I: LocationTracker<'file>> TokenStream<'file, I> {
     pub fn new(location_stream: I) -> Self {
         Self {
             location_stream,
-            cached_next_token: None,
-            sent_eof: false
+            lookahead: VecDeque::new(),
+            sent_eof: false,
+            comments: Vec::new(),
+            after_operand: false,
+            tab_width: 4,
+            indentation_character: None,
+            errors: Vec::new()
         }
     }
 
+    /// Set the number of spaces each tab in indentation counts as
+    pub fn set_tab_width(&mut self, n: usize) {
+        self.tab_width = n;
+    }
+
+    /// Take the warnings and errors found so far, such as indentation mixing
+    /// tabs and spaces or an unterminated block comment
+    pub fn take_errors(&mut self) -> Vec<GenericError<'file>> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// The comment on the given line, if one has been read
+    pub fn comment_on_line(&self, line: usize) -> Option<&str> {
+        self.comments.iter().rev().find(|(l, _)| *l == line).map(|(_, text)| text.as_ref())
+    }
+
+    /// Every `//` comment read so far with its line, in the order they appear
+    pub fn comments(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.comments.iter().map(|(line, text)| (*line, text.as_ref()))
+    }
+
     fn consume_while_identifier(&mut self) {
         self.location_stream.consume_if(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '_'));
         self.location_stream.consume_while(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '_'));
     }
 
-    fn consume_while_number(&mut self) {
-        self.location_stream.consume_while(|c| matches!(c, '0'..='9'));
+    fn consume_while_number(&mut self, first: char) {
+        // Hexadecimal and binary literals, any letters or digits after the prefix are
+        // kept in the token so a bad digit is reported by the parser
+        if first == '0' && self.location_stream.consume_if(|c| matches!(c, 'x' | 'X' | 'b' | 'B')) {
+            self.location_stream.consume_while(|c: char| c.is_ascii_alphanumeric());
+            return;
+        }
+
+        self.location_stream.consume_while(|c: char| c.is_ascii_digit());
         self.location_stream.consume_if(|c| c == '.');
-        self.location_stream.consume_while(|c| matches!(c, '0'..='9'));
+        self.location_stream.consume_while(|c: char| c.is_ascii_digit());
+
+        // An exponent, as in `1.5e10` or `2e-3`
+        if self.location_stream.consume_if(|c| c == 'e' || c == 'E') {
+            self.location_stream.consume_if(|c| c == '+' || c == '-');
+            self.location_stream.consume_while(|c: char| c.is_ascii_digit());
+        }
+    }
+
+    /// Skip the rest of a `/* ... */` comment, which may contain nested block comments
+    fn consume_block_comment(&mut self, location: Location<'file>) {
+        let mut depth = 1;
+        let mut previous = None;
+
+        while depth > 0 {
+            let Some((_, _, c)) = self.location_stream.next() else {
+                let token = Token::new(location, TokenData::Symbol(Cow::Borrowed("/*")));
+                self.errors.push(GenericError::error(token, "unterminated block comment".to_string())
+                                    .arrow("comment starts here".to_string()));
+                return;
+            };
+
+            previous = match (previous, c) {
+                (Some('/'), '*') => { depth += 1; None }
+                (Some('*'), '/') => { depth -= 1; None }
+                _ => Some(c)
+            };
+        }
+    }
+
+    fn consume_string_literal(&mut self) {
+        loop {
+            self.location_stream.consume_while(|c| c != '"' && c != '\n' && c != '\\');
+
+            // A backslash escapes the character after it, which may be a quote
+            if self.location_stream.consume_if(|c| c == '\\') {
+                self.location_stream.consume_if(|c| c != '\n');
+            }
+            else {
+                break;
+            }
+        }
+
+        self.location_stream.consume_if(|c| c == '"');
     }
 
     fn consume_compound_token(&mut self, c: char) {
@@ -60,85 +159,177 @@ impl<'file, I: LocationTracker<'file>> TokenStream<'file, I> {
             '!' => {
                 self.location_stream.consume_if(|c| c == '=');
             }
+            '+' | '-' | '*' => {
+                self.location_stream.consume_if(|c| c == '=');
+            }
             _ => {eprintln!("{}", c); todo!() }
         }
     }
 
-    pub fn peek(&mut self) -> Option<&Token<'file>> {
-        if self.cached_next_token.is_none() {
-            self.cached_next_token = 
-            if let Some((index, location, c)) = self.location_stream.next() {
-                match c {
-                    'a'..='z' | 'A'..='Z' | '_' => {
-                        self.consume_while_identifier();
-                        Some(Token::new(location, TokenData::Identifier(self.location_stream.to_last_reported(index))))
-                    },
-                    '0'..='9' => {
-                        self.consume_while_number();
-                        Some(Token::new(location, TokenData::NumericLiteral(self.location_stream.to_last_reported(index))))
+    /// Indentation with each tab replaced by `tab_width` spaces, warning if tabs and
+    /// spaces are mixed within the line or differ from earlier indentation
+    fn indentation_text(&mut self, location: &Location<'file>, text: Cow<'file, str>) -> Cow<'file, str> {
+        let uses_tabs = text.contains('\t');
+        let uses_spaces = text.contains(' ');
+
+        if !uses_tabs && !uses_spaces {
+            return text;
+        }
+
+        let character = if uses_tabs { '\t' } else { ' ' };
+        let expected = *self.indentation_character.get_or_insert(character);
+
+        if uses_tabs && uses_spaces || character != expected {
+            let token = Token::new(location.clone(), TokenData::Indentation(text.clone()));
+            self.errors.push(GenericError::warning(token, "inconsistent indentation: mixing tabs and spaces".to_string()));
+        }
+
+        if uses_tabs {
+            Cow::Owned(text.replace('\t', &" ".repeat(self.tab_width)))
+        }
+        else {
+            text
+        }
+    }
+
+    /// Read the next token from the source, skipping whitespace and comments
+    fn read_token(&mut self) -> Option<Token<'file>> {
+        if let Some((index, location, c)) = self.location_stream.next() {
+            match c {
+                'a'..='z' | 'A'..='Z' | '_' => {
+                    self.consume_while_identifier();
+                    Some(Token::new(location, TokenData::Identifier(self.location_stream.to_last_reported(index))))
+                },
+                '0'..='9' => {
+                    self.consume_while_number(c);
+                    Some(Token::new(location, TokenData::NumericLiteral(self.location_stream.to_last_reported(index))))
+                }
+                // The closing quote is left for the parser to check
+                '"' => {
+                    self.consume_string_literal();
+                    Some(Token::new(location, TokenData::StringLiteral(self.location_stream.to_last_reported(index))))
+                }
+                // Comments, or floor division when `//` follows an operand and comes before another
+                '/' => {
+                    if self.location_stream.consume_if(|c| c == '*') {
+                        self.consume_block_comment(location);
+                        self.read_token()
                     }
-                    // Comments
-                    '/' => {
-                        if self.location_stream.consume_if(|c| c == '/') {
-                            self.location_stream.consume_while(|c| c != '\n');
-                            self.next()
+                    else if self.location_stream.consume_if(|c| c == '/') {
+                        let rest = match self.location_stream.peek() {
+                            Some((i, _)) => self.location_stream.raw()[i..].split('\n').next().unwrap_or(""),
+                            None => ""
+                        };
+
+                        if self.after_operand && starts_with_operand(rest) {
+                            Some(Token::new(location, TokenData::Symbol(self.location_stream.to_last_reported(index))))
                         }
                         else {
-                            Some(Token::new(location, TokenData::Symbol(self.location_stream.to_last_reported(index))))
+                            if self.after_operand {
+                                let token = Token::new(location.clone(), TokenData::Symbol(Cow::Borrowed("//")));
+                                self.errors.push(GenericError::warning(token, "`//` after a value is read as a comment rather than floor division".to_string())
+                                    .note("use `/* ... */` for a comment after a value".to_string()));
+                            }
+
+                            self.location_stream.consume_while(|c| c != '\n');
+                            let text = self.location_stream.to_last_reported(index);
+                            self.comments.push((location.line, match text {
+                                Cow::Borrowed(s) => Cow::Borrowed(&s[2..]),
+                                Cow::Owned(s) => Cow::Owned(s[2..].to_string()),
+                            }));
+                            self.read_token()
                         }
                     }
-                    // Compound Symbols
-                    '!' | '<' | '>' | '=' => {
-                        self.consume_compound_token(c);
+                    else {
+                        self.location_stream.consume_if(|c| c == '=');
                         Some(Token::new(location, TokenData::Symbol(self.location_stream.to_last_reported(index))))
                     }
-                    '(' | ')' | '[' | ']' | '.' | ',' | '+' | '-' | '*' => Some(Token::new(location, TokenData::Symbol(self.location_stream.to_last_reported(index)))),
-                    ' ' | '\r' => self.next(),
-                    '\n' => {
-                        let mut location = location;
-                        let mut start_index = index + 1;
-                        loop {
-                            if let Some((_, ' ')) = self.location_stream.peek() {
-                                location = self.location_stream.next().unwrap().1;
-                            }
-                            self.location_stream.consume_while(|c| c == ' ' || c == '\r');
-                            if let Some((index, '\n')) = self.location_stream.peek() {
-                                start_index = index + 1;
-                                self.location_stream.next();
-                            }
-                            else {
-                                break;
-                            }
+                }
+                // Compound Symbols
+                '!' | '<' | '>' | '=' | '+' | '-' | '*' => {
+                    self.consume_compound_token(c);
+                    Some(Token::new(location, TokenData::Symbol(self.location_stream.to_last_reported(index))))
+                }
+                '(' | ')' | '[' | ']' | '.' | ',' | ':' | '%' | '^' | '&' => Some(Token::new(location, TokenData::Symbol(self.location_stream.to_last_reported(index)))),
+                ' ' | '\t' | '\r' => self.read_token(),
+                '\n' => {
+                    let mut location = location;
+                    let mut start_index = index + 1;
+                    loop {
+                        if let Some((_, ' ' | '\t')) = self.location_stream.peek() {
+                            location = self.location_stream.next().unwrap().1;
                         }
-
-                        let s = self.location_stream.to_last_reported(start_index);
-
-                        if s.len() == 0 {
-                            self.next()
+                        self.location_stream.consume_while(|c| c == ' ' || c == '\t' || c == '\r');
+                        if let Some((index, '\n')) = self.location_stream.peek() {
+                            start_index = index + 1;
+                            self.location_stream.next();
                         }
                         else {
-                            Some(Token::new(location, TokenData::Indentation(self.location_stream.to_last_reported(start_index))))
+                            break;
                         }
                     }
-                    _ => {
-                        println!("Character: {}", c);
-                        todo!()
+
+                    let s = self.location_stream.to_last_reported(start_index);
+
+                    // A new line never continues the value before it
+                    self.after_operand = false;
+
+                    if s.is_empty() {
+                        self.read_token()
+                    }
+                    else {
+                        let text = self.indentation_text(&location, s);
+                        Some(Token::new(location, TokenData::Indentation(text)))
                     }
                 }
+                _ => {
+                    println!("Character: {}", c);
+                    todo!()
+                }
             }
-            else if !self.sent_eof {
-                self.sent_eof = true;
+        }
+        else if !self.sent_eof {
+            self.sent_eof = true;
 
-                let location = self.location_stream.next_location();
+            let location = self.location_stream.next_location();
 
-                Some(Token::new(location, TokenData::EndOfFile))
-            }
-            else {
-                None
+            Some(Token::new(location, TokenData::EndOfFile))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Read tokens until at least `n + 1` are buffered
+    fn fill_lookahead(&mut self, n: usize) {
+        // A line holding only a comment gives an indentation token directly
+        // followed by the next line's, so only the last of these is kept
+        while self.lookahead.len() <= n
+            || matches!(self.lookahead.back().map(|t| &t.data), Some(TokenData::Indentation(_))) {
+            let token = match self.read_token() {
+                Some(token) => token,
+                None => break
             };
+
+            self.after_operand = matches!(&token.data,
+                TokenData::Identifier(_) | TokenData::NumericLiteral(_) | TokenData::StringLiteral(_))
+                || matches!(&token.data, TokenData::Symbol(s) if s == ")" || s == "]");
+
+            match (self.lookahead.back_mut(), &token.data) {
+                (Some(last @ Token { data: TokenData::Indentation(_), .. }), TokenData::Indentation(_)) => *last = token,
+                _ => self.lookahead.push_back(token)
+            }
         }
+    }
+
+    pub fn peek(&mut self) -> Option<&Token<'file>> {
+        self.peek_nth(0)
+    }
 
-        self.cached_next_token.as_ref()
+    /// Look `n` tokens past the next one without consuming any, `peek_nth(0)` being `peek()`
+    pub fn peek_nth(&mut self, n: usize) -> Option<&Token<'file>> {
+        self.fill_lookahead(n);
+        self.lookahead.get(n)
     }
 }
 
@@ -146,7 +337,153 @@ impl<'file, I: LocationTracker<'file>> std::iter::Iterator for TokenStream<'file
     type Item = Token<'file>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.peek();
-        self.cached_next_token.take()
+        self.fill_lookahead(0);
+        self.lookahead.pop_front()
+    }
+}
+/// Whether the rest of a line after `//` starts with the right operand of a
+/// floor division. A name followed by another word, as in `// outer loop`, or
+/// by punctuation such as `:` is prose, unless that word is `and`, `to` or the like.
+fn starts_with_operand(rest: &str) -> bool {
+    let is_name = |c: char| c.is_alphanumeric() || c == '_';
+    let rest = rest.trim_start_matches([' ', '\t']);
+
+    match rest.chars().next() {
+        Some('0'..='9' | '(' | '-' | '"') => true,
+        Some(c) if is_name(c) => {
+            let after = rest.trim_start_matches(is_name).trim_start_matches([' ', '\t', '\r']);
+
+            match after.chars().next() {
+                None => true,
+                Some(c) if is_name(c) => {
+                    let next_word = after.split(|c: char| !is_name(c)).next().unwrap_or("");
+                    matches!(next_word, "and" | "or" | "xor" | "down" | "to" | "by" | "then" | "else")
+                }
+                Some(c) => "+-*/%^()[],.<>=!&".contains(c)
+            }
+        }
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Kind and text of every token in `source` before the end of the file
+    fn tokens(source: &str) -> Vec<(&'static str, String)> {
+        TokenStream::from_source(source, "test.ps")
+            .filter(|t| t.data != TokenData::EndOfFile)
+            .map(|t| (t.data.kind_name(), t.extract_text().to_string()))
+            .collect()
+    }
+
+    fn number(text: &str) -> (&'static str, String) {
+        ("number", text.to_string())
+    }
+
+    fn symbol(text: &str) -> (&'static str, String) {
+        ("symbol", text.to_string())
+    }
+
+    #[test]
+    fn hexadecimal_and_binary_literals() {
+        assert_eq!(tokens("0xFF"), [number("0xFF")]);
+        assert_eq!(tokens("0b1100"), [number("0b1100")]);
+        assert_eq!(tokens("0xFF+1"), [number("0xFF"), symbol("+"), number("1")]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn scientific_notation() {
+        for literal in ["1e3", "2.5e-1", "1.5e10", "3E+2"] {
+            assert_eq!(tokens(literal), [number(literal)]);
+        }
+
+        assert_eq!(tokens("1e3-1"), [number("1e3"), symbol("-"), number("1")]);
+    }
+
+    /// Messages of the warnings and errors found while reading every token of `source`
+    fn errors(source: &str) -> Vec<String> {
+        let mut stream = TokenStream::from_source(source, "test.ps");
+        stream.by_ref().for_each(drop);
+
+        stream.take_errors().iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn indentation_with_tabs_or_spaces() {
+        assert!(errors("Test()\n\tx = 1\n\tif x\n\t\treturn x\n").is_empty());
+        assert!(errors("Test()\n    x = 1\n    if x\n        return x\n").is_empty());
+
+        // Tabs count as four spaces by default
+        assert_eq!(tokens("F()\n\tx")[3], ("indentation", "    ".to_string()));
+
+        let mixed_in_line = errors("Test()\n \tx = 1\n");
+        let mixed_between_lines = errors("Test()\n\tx = 1\n    y = 1\n");
+        for warnings in [mixed_in_line, mixed_between_lines] {
+            assert_eq!(warnings.len(), 1);
+            assert!(warnings[0].contains("inconsistent indentation: mixing tabs and spaces"), "{}", warnings[0]);
+        }
+    }
+
+    fn identifier(text: &str) -> (&'static str, String) {
+        ("identifier", text.to_string())
+    }
+
+    #[test]
+    fn block_comments() {
+        let five_lines = "x /* one\ntwo\nthree\nfour\nfive */ y";
+        assert_eq!(tokens(five_lines), [identifier("x"), identifier("y")]);
+        assert!(errors(five_lines).is_empty());
+
+        // Lines after the comment keep their numbers
+        let y = TokenStream::from_source(five_lines, "test.ps").nth(1).unwrap();
+        assert_eq!(y.location.line, 4);
+
+        let before_signature = "/* Sums the elements\n   of A */\nSum(A)\n    return 0\n";
+        assert_eq!(&tokens(before_signature)[..2], [identifier("Sum"), symbol("(")]);
+
+        assert_eq!(tokens("a /* outer /* inner */ still outer */ b"), [identifier("a"), identifier("b")]);
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let errors = errors("Test()\n    /* never\n    closed\n");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("unterminated block comment"), "{}", errors[0]);
+        assert!(errors[0].contains("test.ps:2:5"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn escaped_quotes_stay_inside_strings() {
+        assert_eq!(tokens(r#"x = "say \"hi\"\n" + y"#), [
+            identifier("x"), symbol("="), ("string", r#""say \"hi\"\n""#.to_string()), symbol("+"), identifier("y")
+        ]);
+    }
+
+    #[test]
+    fn floor_division_after_a_value() {
+        assert_eq!(tokens("x // k"), [identifier("x"), symbol("//"), identifier("k")]);
+        assert_eq!(tokens("x // -1"), [identifier("x"), symbol("//"), symbol("-"), number("1")]);
+        assert_eq!(tokens("(a + b) // 2")[5..], [symbol("//"), number("2")]);
+        assert_eq!(tokens("A[i] // n and y")[4..], [symbol("//"), identifier("n"), identifier("and"), identifier("y")]);
+        assert!(errors("x // k").is_empty());
+    }
+
+    #[test]
+    fn comments_after_a_value_warn() {
+        for source in ["x = n // place it", "x = n // TODO: check", "x = n //"] {
+            assert_eq!(tokens(source), [identifier("x"), symbol("="), identifier("n")], "{}", source);
+
+            let warnings = errors(source);
+            assert_eq!(warnings.len(), 1, "{}", source);
+            assert!(warnings[0].contains("`//` after a value is read as a comment"), "{}", warnings[0]);
+        }
+
+        // Anywhere else `//` always starts a comment
+        for source in ["// k", "x = // k", "F(x)\n// k"] {
+            assert!(!tokens(source).contains(&symbol("//")), "{}", source);
+            assert!(errors(source).is_empty(), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::borrow::Cow;
+
+use crate::{interpreter::Value, tokenizer::{Token, TokenData, Location}, VMValue, error::GenericError};
+
+use super::Runtime;
+
+pub const DEBUGGER_HELP: &str = "\
+commands:
+  <enter>             step to the next line
+  continue            run until the next breakpoint
+  set NAME VALUE      assign VALUE to the variable NAME
+  set NAME[I] VALUE   assign VALUE to element I of the array NAME
+  print NAME          display the value of the variable NAME
+  print NAME[I]       display element I of the array NAME
+  help                display this message
+
+VALUE may be a number, True or False";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugTarget {
+    pub name: String,
+    pub index: Option<f64>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugCommand {
+    Step,
+    /// Run until the next breakpoint
+    Continue,
+    Set(DebugTarget, Value),
+    Print(DebugTarget),
+    Help
+}
+
+impl DebugTarget {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (name, index) = if let Some((name, rest)) = text.split_once('[') {
+            let index = rest.strip_suffix(']').ok_or_else(|| format!("expected ']' at the end of '{}'", text))?;
+            let index = index.trim().parse::<f64>().map_err(|_| format!("index '{}' is not a number", index))?;
+
+            (name.trim(), Some(index))
+        }
+        else {
+            (text.trim(), None)
+        };
+
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("'{}' is not a variable name", name));
+        }
+
+        Ok(Self { name: name.to_string(), index })
+    }
+
+    fn to_vm_value(&self) -> VMValue<'static> {
+        let location = Location { filename: "<debugger>".into(), line: 0, column: 0, index: 0, file_text: None };
+        let token = Token::new(location, TokenData::Identifier(Cow::Owned(self.name.clone())));
+
+        if let Some(index) = self.index {
+            VMValue::Indexing(Box::new(token.into()), Box::new(Value::Number(index).into()))
+        }
+        else {
+            token.into()
+        }
+    }
+}
+
+impl std::fmt::Display for DebugTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(index) = self.index {
+            write!(f, "{}[{}]", self.name, index)
+        }
+        else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    match text {
+        "True" => Ok(Value::Boolean(true)),
+        "False" => Ok(Value::Boolean(false)),
+        _ => text.parse::<i64>().map(Value::Integer).or_else(|_| text.parse::<f64>().map(Value::Number)).map_err(|_| format!("'{}' is not a number, True or False", text))
+    }
+}
+
+impl DebugCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+
+        if line.is_empty() {
+            return Ok(DebugCommand::Step);
+        }
+
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+
+        match command {
+            "help" => Ok(DebugCommand::Help),
+            "continue" => Ok(DebugCommand::Continue),
+            "print" => Ok(DebugCommand::Print(DebugTarget::parse(rest)?)),
+            "set" => {
+                let (target, value) = rest.rsplit_once(char::is_whitespace).ok_or_else(|| "expected 'set NAME VALUE'".to_string())?;
+                Ok(DebugCommand::Set(DebugTarget::parse(target)?, parse_value(value.trim())?))
+            },
+            _ => Err(format!("unknown command '{}', type 'help' for a list of commands", command))
+        }
+    }
+}
+
+impl<'file> Runtime<'file> {
+    /// Run a debugger command against the current frame, returning the text to
+    /// display. Stepping is left to the caller.
+    pub fn run_debug_command(&mut self, command: &DebugCommand) -> Result<String, GenericError<'file>> {
+        match command {
+            DebugCommand::Step | DebugCommand::Continue => Ok(String::new()),
+            DebugCommand::Help => Ok(DEBUGGER_HELP.to_string()),
+            DebugCommand::Print(target) => {
+                let frame = self.current_frame_mut().ok_or_else(|| GenericError::tokenless_error("runtime not executing program".to_string()))?;
+                let value = frame.load_value(target.to_vm_value(), false)?;
+
+                Ok(format!("{} = {}", target, value))
+            },
+            DebugCommand::Set(target, value) => {
+                let frame = self.current_frame_mut().ok_or_else(|| GenericError::tokenless_error("runtime not executing program".to_string()))?;
+                let old = frame.load_value(target.to_vm_value(), false).unwrap_or(Value::None);
+                frame.store_value_into(target.to_vm_value(), value.clone(), &mut |_, _, _| {})?;
+
+                Ok(format!("{}: {} -> {}", target, old, value))
+            }
+        }
+    }
+}
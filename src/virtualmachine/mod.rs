@@ -1,3 +1,9 @@
+pub mod debugger;
+pub use debugger::*;
+
+pub mod profile;
+pub use profile::*;
+
 pub mod render;
 pub use render::*;
 
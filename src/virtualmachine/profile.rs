@@ -0,0 +1,65 @@
+use std::{collections::HashMap, time::Duration};
+
+use crate::util::json_string;
+
+#[derive(Debug, Clone, Default)]
+pub struct ProfileReport {
+    pub function_instructions: HashMap<String, u64>,
+    pub function_time: HashMap<String, Duration>,
+    pub line_instructions: HashMap<usize, u64>
+}
+
+impl ProfileReport {
+    pub fn record(&mut self, function: &str, line: usize, time: Duration) {
+        *self.function_instructions.entry(function.to_string()).or_insert(0) += 1;
+        *self.function_time.entry(function.to_string()).or_default() += time;
+        *self.line_instructions.entry(line).or_insert(0) += 1;
+    }
+
+    /// Source lines with the most instructions executed, as `(line, count)` pairs
+    pub fn hottest_lines(&self, count: usize) -> Vec<(usize, u64)> {
+        let mut lines = self.line_instructions.iter().map(|(l, c)| (*l, *c)).collect::<Vec<_>>();
+        lines.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        lines.truncate(count);
+
+        lines
+    }
+
+    fn sorted_functions(&self) -> Vec<(&String, u64, Duration)> {
+        let mut functions = self.function_instructions.iter()
+            .map(|(name, count)| (name, *count, self.function_time.get(name).copied().unwrap_or_default()))
+            .collect::<Vec<_>>();
+        functions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        functions
+    }
+
+    pub fn to_json(&self) -> String {
+        let functions = self.sorted_functions().iter()
+            .map(|(name, count, time)| format!("{{\"name\": {}, \"instructions\": {}, \"time_us\": {}}}", json_string(name), count, time.as_micros()))
+            .collect::<Vec<_>>();
+
+        let lines = self.hottest_lines(10).iter()
+            .map(|(line, count)| format!("{{\"line\": {}, \"instructions\": {}}}", line + 1, count))
+            .collect::<Vec<_>>();
+
+        format!("{{\"functions\": [{}], \"hottest_lines\": [{}]}}", functions.join(", "), lines.join(", "))
+    }
+}
+
+impl std::fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<20} {:>14} {:>14}", "Function", "Instructions", "Time (us)")?;
+        for (name, count, time) in self.sorted_functions() {
+            writeln!(f, "{:<20} {:>14} {:>14}", name, count, time.as_micros())?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<20} {:>14}", "Line", "Instructions")?;
+        for (line, count) in self.hottest_lines(10) {
+            writeln!(f, "{:<20} {:>14}", line + 1, count)?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,26 +1,69 @@
-use crate::interpreter::Value;
+use std::{collections::VecDeque, fmt::Write};
 
-use super::{ExecutionFrame, UpdateData};
+use crate::{interpreter::Value, util::html_escape, color::{Ansi, CLEAR, RED, YELLOW, CYAN}};
 
-const CLEAR: &str = "\x1b[0m";
-const RED: &str = "\x1b[31m";
-const YELLOW: &str = "\x1b[33m";
-const CYAN: &str = "\x1b[36m";
+use super::{ExecutionFrame, UpdateData, Runtime};
+
+/// Wrapper for displaying a runtime's call history outside of the visualizer
+pub struct CallHistory<'a>(pub &'a VecDeque<(String, usize)>);
 
 fn move_cursor(f: &mut std::fmt::Formatter<'_>, x: usize, y: usize) -> std::fmt::Result {
     write!(f, "\x1b[{};{}H", y, x)
 }
 
-fn render_heap(f: &mut std::fmt::Formatter<'_>, left: usize, top: usize, data: &[String], color: &[&str]) -> std::fmt::Result {
-    let max_width = data.iter().map(|s| s.len()).max().unwrap_or(1).max(3);
-    let texts = data.iter().map(|s| format!("{:^width$}", s, width=max_width)).collect::<Vec<_>>();
-    let tree_height = (((1 + texts.len()) as f64).log2().ceil() as u32).max(1) as usize;
+/// Narrowest terminal the visualizer lays itself out for, anything smaller is
+/// drawn at this width so the code listing keeps some of each line
+const MIN_RENDER_WIDTH: usize = 40;
+
+/// The first `width` visible characters of `text`, keeping every color code
+fn truncate_visible(text: &str, width: usize) -> String {
+    let mut result = String::new();
+    let mut visible = 0;
+    let mut characters = text.chars();
+
+    while let Some(c) = characters.next() {
+        if c == '\x1b' {
+            result.push(c);
+            for c in characters.by_ref() {
+                result.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        }
+        else if visible < width {
+            result.push(c);
+            visible += 1;
+        }
+    }
+
+    result
+}
+
+/// Deepest heap level drawn, anything below is summarised with `...`
+const MAX_HEAP_LEVELS: usize = 6;
+
+fn render_heap(f: &mut std::fmt::Formatter<'_>, left: usize, top: usize, data: &[String], color: &[Ansi]) -> std::fmt::Result {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    // An odd width keeps every parent exactly centered over its children
+    let mut max_width = data.iter().map(|s| s.len()).max().unwrap_or(1).max(3);
+    if max_width % 2 == 0 {
+        max_width += 1;
+    }
+
+    let full_height = (((1 + data.len()) as f64).log2().ceil() as usize).max(1);
+    let tree_height = full_height.min(MAX_HEAP_LEVELS);
+    let visible = data.len().min((1 << tree_height) - 1);
+    let texts = data[..visible].iter().map(|s| format!("{:^width$}", s, width=max_width)).collect::<Vec<_>>();
 
     for row in 0..tree_height {
         let layer_start_indexing = 2usize.pow(row as u32) - 1;
         let count = 2usize.pow(row as u32);
         let height = tree_height - row;
-        let upper_bound = (layer_start_indexing + count).min(data.len());
+        let upper_bound = (layer_start_indexing + count).min(texts.len());
         let spacing = (1 + max_width) * 2usize.pow(height as u32 - 1);
 
         let offset = ((2.0f64.powf(height as f64 - 1.0) - 1.0) * (max_width as f64 + 1.0) / 2.0).ceil() as usize;
@@ -58,6 +101,11 @@ fn render_heap(f: &mut std::fmt::Formatter<'_>, left: usize, top: usize, data: &
         }
     }
 
+    if full_height > tree_height {
+        move_cursor(f, left, top + 2 * tree_height)?;
+        write!(f, "...")?;
+    }
+
     /*
     for (i, text) in texts.iter().enumerate() {
         let layer = ((i + 1) as f64).log2() as usize;
@@ -77,21 +125,35 @@ fn render_heap(f: &mut std::fmt::Formatter<'_>, left: usize, top: usize, data: &
 
 impl<'file> std::fmt::Display for ExecutionFrame<'file> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.render(f, usize::MAX, usize::MAX)
+    }
+}
+
+impl<'file> ExecutionFrame<'file> {
+    /// Draw the frame for a terminal of the given size, the code listing takes
+    /// at most half of the width and the variables fill the rest
+    pub fn render(&self, f: &mut std::fmt::Formatter<'_>, width: usize, height: usize) -> std::fmt::Result {
         write!(f, "\x1b[2J{}", CLEAR)?;
 
+        let width = width.max(MIN_RENDER_WIDTH);
+
         let mut left_most = 1;
 
         // Prepare to display the code listing if it exists
-        if let Some(code) = self.function.raw_file {
+        if let Some(code) = &self.function.raw_file {
             let highlight_line = self.last_line;
-            let secondary_lines = &self.last_lines;
-            let longest_line = code.lines().map(|s| s.len()).max().unwrap_or(10);
+            let secondary_lines = &self.call_site_lines;
+            let longest_line = code.lines().map(|s| s.len()).max().unwrap_or(10).min((width / 2).saturating_sub(9));
             move_cursor(f, left_most, 1)?;
             write!(f, "{}:", self.function.name.location.filename)?;
+
+            // Scroll the listing to keep the current line visible
+            let rows = height.saturating_sub(3).max(1);
+            let first_row = highlight_line.unwrap_or(0).saturating_sub(rows / 2).min(code.lines().count().saturating_sub(rows));
             
-            for (i, line) in code.lines().enumerate() {
-                
-                move_cursor(f, left_most, i + 2)?;
+            for (i, line) in code.lines().enumerate().skip(first_row).take(rows) {
+                let row = i - first_row;
+                move_cursor(f, left_most, row + 2)?;
                 write!(f, "{:<4}| ", i + 1)?;
                 if Some(i) == highlight_line {
                     write!(f, "{}", RED)?;
@@ -99,8 +161,8 @@ impl<'file> std::fmt::Display for ExecutionFrame<'file> {
                 else if secondary_lines.contains(&i) {
                     write!(f, "{}", CYAN)?;
                 }
-                write!(f, "{}", line)?;
-                move_cursor(f, left_most + 7 + longest_line, i + 2)?;
+                write!(f, "{}", line.chars().take(longest_line).collect::<String>())?;
+                move_cursor(f, left_most + 7 + longest_line, row + 2)?;
                 write!(f, "{}|", CLEAR)?;
             }
 
@@ -115,7 +177,10 @@ impl<'file> std::fmt::Display for ExecutionFrame<'file> {
 
         for (i, variable_name) in keys.iter().enumerate() {
             move_cursor(f, left_most, i + 2)?;
-            write!(f, "{}: ", variable_name)?;
+
+            // Each variable is drawn on one line, cut off at the edge of the terminal
+            let mut text = format!("{}: ", variable_name);
+            let mut heap = None;
 
             /*
             if self.last_updated.contains(variable_name) {
@@ -140,13 +205,14 @@ impl<'file> std::fmt::Display for ExecutionFrame<'file> {
             };
 
             match v {
-                crate::interpreter::Value::Number(number) => write!(f, "{}{}{}", color, number, CLEAR),
+                crate::interpreter::Value::Number(number) => write!(text, "{}{}{}", color, number, CLEAR),
+                crate::interpreter::Value::Integer(number) => write!(text, "{}{}{}", color, number, CLEAR),
                 crate::interpreter::Value::Array(v) => {
                     let mut colors = Vec::new();
-                    write!(f, "{}[", color)?;
+                    write!(text, "{}[", color)?;
                     for (i, v) in v.borrow().0.iter().enumerate() {
                         if i != 0 {
-                            write!(f, ", ")?;
+                            write!(text, ", ")?;
                         }
                         let this_color = if self.last_updated.contains(&UpdateData::indexed(variable_name.to_string(), i + 1)) {
                             YELLOW
@@ -158,21 +224,30 @@ impl<'file> std::fmt::Display for ExecutionFrame<'file> {
                             color
                         };
                         colors.push(this_color);
-                        write!(f, "{}{}{}", this_color, v, color)?;
+                        write!(text, "{}{}{}", this_color, v, color)?;
                     }
-                    write!(f, "]{}", CLEAR)?;
+                    write!(text, "]{}", CLEAR)?;
 
-                    let heap = v.borrow().0.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                    let elements = v.borrow().0.iter().map(|v| v.to_string()).collect::<Vec<_>>();
 
-                    if let Value::Number(n) = v.borrow().1.clone() {
-                        render_heap(f, left_most, count + 5, &heap[..n as usize], &colors[..n as usize])?;
+                    if let Some(n) = v.borrow().1.get("heapsize").and_then(|n| n.as_number()) {
+                        let n = (n.max(0.0) as usize).min(elements.len());
+                        heap = Some((elements[..n].to_vec(), colors[..n].to_vec()));
                     }
                     
                     Ok(())
                 },
-                crate::interpreter::Value::Boolean(b) => write!(f, "{}{}{}", color, b, CLEAR),
-                crate::interpreter::Value::None => write!(f, "{}None{}", color, CLEAR),
+                crate::interpreter::Value::Map(_) => write!(text, "{}{}{}", color, v, CLEAR),
+                crate::interpreter::Value::String(s) => write!(text, "{}{:?}{}", color, s, CLEAR),
+                crate::interpreter::Value::Boolean(b) => write!(text, "{}{}{}", color, b, CLEAR),
+                crate::interpreter::Value::None => write!(text, "{}None{}", color, CLEAR),
             }?;
+
+            write!(f, "{}{}", truncate_visible(&text, width.saturating_sub(left_most)), CLEAR)?;
+
+            if let Some((elements, colors)) = heap {
+                render_heap(f, left_most, count + 5, &elements, &colors)?;
+            }
         }
 
         move_cursor(f, 0, 10000)?;
@@ -180,4 +255,168 @@ impl<'file> std::fmt::Display for ExecutionFrame<'file> {
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+const HTML_STYLE: &str = "\
+body { font-family: monospace; display: flex; gap: 4em; }
+.current { color: red; }
+.call-site { color: darkcyan; }
+.updated { background: yellow; }
+.read { background: lightblue; }";
+
+impl<'file> ExecutionFrame<'file> {
+    fn sorted_variable_names(&self) -> Vec<&String> {
+        let mut keys = self.variables.keys().filter(|s| !s.contains('$')).collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    /// Render the frame as plain text, marking the current line with `>` and
+    /// the lines of calls on the stack with `*`
+    pub fn to_plain(&self) -> String {
+        let mut result = String::new();
+
+        if let Some(code) = &self.function.raw_file {
+            result += &format!("{}:\n", self.function.name.location.filename);
+            for (i, line) in code.lines().enumerate() {
+                let marker = if Some(i) == self.last_line { ">" } else if self.call_site_lines.contains(&i) { "*" } else { " " };
+                result += &format!("{} {:<4}| {}\n", marker, i + 1, line);
+            }
+            result += "\n";
+        }
+
+        for name in self.sorted_variable_names() {
+            result += &format!("{}: {}\n", name, self.variables[name]);
+        }
+
+        result
+    }
+
+    /// Render the frame as a standalone HTML page
+    pub fn to_html(&self) -> String {
+        let mut code_listing = String::new();
+
+        if let Some(code) = &self.function.raw_file {
+            for (i, line) in code.lines().enumerate() {
+                let class = if Some(i) == self.last_line { " class=\"current\"" } else if self.call_site_lines.contains(&i) { " class=\"call-site\"" } else { "" };
+                code_listing += &format!("<span{}>{:<4}| {}</span>\n", class, i + 1, html_escape(line));
+            }
+        }
+
+        let class_for = |data: &UpdateData| {
+            if self.last_updated.contains(data) {
+                " class=\"updated\""
+            }
+            else if self.last_read.contains(data) {
+                " class=\"read\""
+            }
+            else {
+                ""
+            }
+        };
+
+        let mut variables = String::new();
+        for name in self.sorted_variable_names() {
+            let value = match &self.variables[name] {
+                Value::Array(array) => {
+                    let elements = array.borrow().0.iter().enumerate()
+                        .map(|(i, v)| format!("<span{}>{}</span>", class_for(&UpdateData::indexed(name.to_string(), i + 1)), html_escape(&v.to_string())))
+                        .collect::<Vec<_>>();
+                    format!("[{}]", elements.join(", "))
+                },
+                v => html_escape(&v.to_string())
+            };
+
+            variables += &format!("<tr><td{}>{}</td><td>{}</td></tr>\n", class_for(&UpdateData::variable(name.to_string())), html_escape(name), value);
+        }
+
+        format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<pre>\n{}</pre>\n<table>\n{}</table>\n</body>\n</html>\n",
+            html_escape(self.function.name.extract_text()), HTML_STYLE, code_listing, variables)
+    }
+}
+
+impl<'file> std::fmt::Display for Runtime<'file> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(last) = self.current_frame() {
+            let (width, height) = self.terminal_size();
+            last.render(f, width, height)?;
+
+            let history = self.call_history();
+            if !history.is_empty() {
+                // Draw the history just above the prompt on the last line
+                move_cursor(f, 0, 10000)?;
+                write!(f, "\x1b[{}A{}Call History:{}", history.len() + 1, CYAN, CLEAR)?;
+                for (name, instruction) in history {
+                    write!(f, "\x1b[1E  {} (instruction {})", name, instruction)?;
+                }
+
+                move_cursor(f, 0, 10000)?;
+                write!(f, ">")?;
+            }
+
+            Ok(())
+        }
+        else {
+            write!(f, "Runtime not executing program")
+        }
+    }
+}
+
+impl std::fmt::Display for CallHistory<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}Call History:{}", CYAN, CLEAR)?;
+        for (name, instruction) in self.0 {
+            writeln!(f, "  {} (instruction {})", name, instruction)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Heap(Vec<String>);
+
+    impl std::fmt::Display for Heap {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            render_heap(f, 1, 1, &self.0, &vec![CLEAR; self.0.len()])
+        }
+    }
+
+    fn heap(size: usize) -> Heap {
+        Heap((1..=size).map(|i| i.to_string()).collect())
+    }
+
+    #[test]
+    fn renders_heaps_of_each_size() {
+        assert_eq!(heap(0).to_string(), "");
+
+        for size in [1, 3, 7, 15] {
+            let text = heap(size).to_string();
+            for i in 1..=size {
+                assert!(text.contains(&format!(" {} ", i)) || text.contains(&format!("{} ", i)), "{} missing from heap of {}", i, size);
+            }
+            assert!(!text.contains("..."));
+        }
+    }
+
+    #[test]
+    fn truncates_deep_heaps() {
+        let text = heap(100).to_string();
+
+        assert!(text.contains("63"));
+        assert!(!text.contains("64"));
+        assert!(text.contains("..."));
+    }
+
+    #[test]
+    fn truncation_keeps_color_codes() {
+        let text = format!("A: {}[1, 2, 3]{}", YELLOW, CLEAR);
+
+        assert_eq!(truncate_visible(&text, 6), format!("A: {}[1,{}", YELLOW, CLEAR));
+        assert_eq!(truncate_visible(&text, 100), text);
+        assert_eq!(truncate_visible(&text, 0), format!("{}{}", YELLOW, CLEAR));
+    }
+}
@@ -1,10 +1,45 @@
-use std::collections::HashMap;
+use std::{collections::{HashMap, HashSet, VecDeque}, time::Instant};
 
-use crate::{VMFunction, VMInstructionType, interpreter::{Value, builtin::*}, error::GenericError, VMValue, tokenizer::Token, VMInstruction, VMVariable};
+use crate::{VMFunction, VMInstructionType, interpreter::{Value, RuntimeError, InputSource, OutputSink, NativeFunctions, DEFAULT_MAX_RECURSION_DEPTH, builtin_input, builtin::*}, error::GenericError, util::closest_match, VMValue, tokenizer::Token, VMInstruction, VMVariable};
+
+use super::ProfileReport;
+
+/// Callback given a variable's name, old value and new value when it changes
+type Watchpoint = Box<dyn Fn(&str, &Value, &Value)>;
 
 pub struct Runtime<'file> {
     functions: HashMap<String, VMFunction<'file>>,
     stack: Vec<ExecutionFrame<'file>>,
+    stats: RuntimeStats,
+    profile: Option<ProfileReport>,
+    call_history: VecDeque<(String, usize)>,
+    call_history_limit: usize,
+    terminal_width: usize,
+    terminal_height: usize,
+    zero_indexed: bool,
+    input: InputSource,
+    output: OutputSink,
+    native_functions: NativeFunctions,
+    /// Largest number of frames allowed on the stack
+    max_depth: usize,
+    /// Number of instructions to execute before stopping with an error
+    max_steps: Option<u64>,
+    /// Value returned by the outermost function once execution has finished
+    return_value: Option<Value>,
+    /// `(function name, instruction index)` pairs where `run_until_breakpoint` stops
+    breakpoints: Vec<(String, usize)>,
+    /// Callbacks run when the variable they are keyed by changes value
+    watchpoints: HashMap<String, Watchpoint>,
+    /// Whether a watched variable has changed since `run_until_breakpoint` started
+    watchpoint_hit: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeStats {
+    pub total_steps: u64,
+    pub max_stack_depth: usize,
+    pub function_call_counts: HashMap<String, u64>,
+    pub distinct_lines_executed: HashSet<usize>
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -37,11 +72,30 @@ pub struct ExecutionFrame<'file> {
     pub last_read: Vec<UpdateData>,
     pub return_value: Option<Value>,
     pub passed_return: Option<Value>,
-    pub last_lines: Vec<usize>
+    /// Source lines of the calls leading to this frame, outermost first. The
+    /// last entry is the line of the call which pushed this frame.
+    pub call_site_lines: Vec<usize>,
+    /// Index arrays from 0 rather than 1. Indices recorded in `last_updated`
+    /// and `last_read` are always 1-based, whatever this is set to.
+    pub zero_indexed: bool,
+    /// Lines read by `Input`, shared with every other frame
+    pub input: InputSource,
+    /// Where `Print` writes to, shared with every other frame
+    pub output: OutputSink,
+    /// Functions registered by the embedding code, shared with every other frame
+    pub native_functions: NativeFunctions
+}
+
+/// Read a terminal dimension from the environment, if it is not set the
+/// visualizer is left unbounded in that direction
+fn terminal_dimension(variable: &str) -> usize {
+    std::env::var(variable).ok().and_then(|v| v.parse().ok()).unwrap_or(usize::MAX)
 }
 
 impl<'file> Runtime<'file> {
-    pub fn load(functions: Vec<VMFunction<'file>>) -> Self {
+    /// Load compiled functions, failing with the first problem found if any of
+    /// them is malformed, as bytecode read from a file may be
+    pub fn load(functions: Vec<VMFunction<'file>>) -> Result<Self, GenericError<'file>> {
         let mut hashmap = HashMap::new();
 
         for func in functions {
@@ -49,35 +103,125 @@ impl<'file> Runtime<'file> {
             hashmap.insert(name, func);
         }
 
-        Self {
+        let result = Self {
             functions: hashmap,
-            stack: Vec::new()
+            stack: Vec::new(),
+            stats: RuntimeStats::default(),
+            profile: None,
+            call_history: VecDeque::new(),
+            call_history_limit: 20,
+            terminal_width: terminal_dimension("COLUMNS"),
+            terminal_height: terminal_dimension("LINES"),
+            zero_indexed: false,
+            input: InputSource::default(),
+            output: OutputSink::default(),
+            native_functions: NativeFunctions::default(),
+            max_depth: DEFAULT_MAX_RECURSION_DEPTH,
+            max_steps: None,
+            return_value: None,
+            breakpoints: Vec::new(),
+            watchpoints: HashMap::new(),
+            watchpoint_hit: false
+        };
+
+        // Argument counts are left to the runtime here, since a mismatch is a mistake
+        // in the program rather than in the compiler
+        for function in result.functions.values() {
+            if let Err(mut errors) = function.verify_with(|_| None) {
+                return Err(errors.remove(0));
+            }
         }
+
+        Ok(result)
+    }
+
+    /// Verify every loaded function, checking calls between them against their argument counts
+    pub fn verify(&self) -> Result<(), Vec<GenericError<'file>>> {
+        let mut errors = Vec::new();
+
+        for function in self.functions.values() {
+            if let Err(mut e) = function.verify_with(|name| self.functions.get(name).map(|f| f.arguments.len())) {
+                errors.append(&mut e);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(errors)
+        }
+    }
+
+    pub fn loaded_function_names(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(|k| k.as_str())
+    }
+
+    pub fn get_function(&self, name: &str) -> Option<&VMFunction<'file>> {
+        self.functions.get(name)
+    }
+
+    fn record_stack_frame(&mut self, name: &str) {
+        *self.stats.function_call_counts.entry(name.to_string()).or_insert(0) += 1;
+        self.stats.max_stack_depth = self.stats.max_stack_depth.max(self.stack.len());
     }
 
     pub fn add_stack_frame(&mut self, function_name: VMVariable<'file>, arguments: Vec<Value>) -> Result<(), GenericError<'file>> {
         let name = function_name.extract_text().to_string();
+
+        if self.stack.len() >= self.max_depth {
+            return Err(GenericError::tokenable_error(function_name.get_token(), format!("maximum recursion depth {} exceeded", self.max_depth)));
+        }
+
         if let Some(f) = self.functions.get(&name) {
             let mut v = vec![];
+            let mut call_index = 0;
 
             if let Some(last) = self.stack.last() {
-                v = last.last_lines.clone();
+                v = last.call_site_lines.clone();
+                call_index = last.line;
                 if let Some(inst) = last.next_instruction(){
                     v.push(inst.associated_line);
                 }
             }
 
-            self.stack.push(ExecutionFrame::new(f.clone(), arguments, v));
+            self.call_history.push_front((name.clone(), call_index));
+            self.call_history.truncate(self.call_history_limit);
+
+            let mut frame = ExecutionFrame::new(f.clone(), arguments, v);
+            frame.zero_indexed = self.zero_indexed;
+            frame.input = self.input.clone();
+            frame.output = self.output.clone();
+            frame.native_functions = self.native_functions.clone();
+            self.stack.push(frame);
+            self.record_stack_frame(&name);
             Ok(())
         }
         else {
-            Err(GenericError::tokenable_error(function_name.get_token(), format!("function '{}' not defined", function_name)))
+            let suggestion = closest_match(function_name.extract_text(), self.functions.keys().map(|k| k.as_str()).chain(BUILTIN_NAMES.iter().copied()));
+            Err(GenericError::tokenable_error(function_name.get_token(), format!("function '{}' not defined", function_name)).suggest(suggestion))
         }
     }
 
     pub fn start_execution(&mut self, function_name: &str) -> Result<(), GenericError<'file>> {
+        self.start_execution_with_arguments(function_name, vec![])
+    }
+
+    pub fn start_execution_with_arguments(&mut self, function_name: &str, arguments: Vec<Value>) -> Result<(), GenericError<'file>> {
+        self.return_value = None;
+
         if let Some(f) = self.functions.get(function_name) {
-            self.stack.push(ExecutionFrame::new(f.clone(), vec![], vec![]));
+            if f.arguments.len() != arguments.len() {
+                return Err(RuntimeError::ArgumentCountError { expected: f.arguments.len(), got: arguments.len() }.finish(f.name.clone()));
+            }
+
+            let mut frame = ExecutionFrame::new(f.clone(), arguments, vec![]);
+            frame.zero_indexed = self.zero_indexed;
+            frame.input = self.input.clone();
+            frame.output = self.output.clone();
+            frame.native_functions = self.native_functions.clone();
+            self.stack.push(frame);
+            self.record_stack_frame(function_name);
             Ok(())
         }
         else {
@@ -93,27 +237,206 @@ impl<'file> Runtime<'file> {
                 if let Some(new_last) = self.stack.last_mut() {
                     new_last.passed_return = Some(value);
                 }
+                else {
+                    self.return_value = Some(value);
+                }
                 self.single_step(show_instructions)?;
                 Ok(true)
             }
-            else if let Some((name, args)) = last.single_step(show_instructions)? {
-                self.add_stack_frame(name, args)?;
-                Ok(true)
-            }
             else {
-                let at_end = last.next_instruction().map(|i| i.associated_line);
-                Ok(at_start != at_end)
-            }   
+                if let Some(max_steps) = self.max_steps {
+                    if self.stats.total_steps >= max_steps {
+                        return Err(GenericError::tokenless_error(format!("execution exceeded the limit of {} steps", max_steps)));
+                    }
+                }
+
+                if let Some(line) = at_start {
+                    self.stats.distinct_lines_executed.insert(line);
+                }
+                self.stats.total_steps += 1;
+
+                let start_time = self.profile.as_ref().map(|_| Instant::now());
+                let watchpoints = &self.watchpoints;
+                let mut watchpoint_hit = false;
+                let step = last.single_step(show_instructions, &mut |name, old, new| {
+                    if let Some(callback) = watchpoints.get(name) {
+                        callback(name, old, new);
+                        watchpoint_hit = true;
+                    }
+                });
+                self.watchpoint_hit |= watchpoint_hit;
+
+                if let (Some(profile), Some(start_time), Some(line)) = (&mut self.profile, start_time, at_start) {
+                    profile.record(last.function.name.extract_text(), line, start_time.elapsed());
+                }
+
+                if let Some((name, args)) = step? {
+                    self.add_stack_frame(name, args)?;
+                    Ok(true)
+                }
+                else {
+                    let at_end = last.next_instruction().map(|i| i.associated_line);
+                    Ok(at_start != at_end)
+                }
+            }
         }
         else {
             Ok(true)
         }
     }
 
+    /// Source lines of every call currently on the stack, outermost first
+    pub fn call_sites(&self) -> Vec<usize> {
+        let depth = self.stack.len().saturating_sub(1);
+
+        self.stack[..depth].iter()
+            .filter_map(|frame| frame.next_instruction().map(|i| i.associated_line))
+            .collect()
+    }
+
+    pub fn enable_profiling(&mut self) {
+        if self.profile.is_none() {
+            self.profile = Some(ProfileReport::default());
+        }
+    }
+
+    pub fn profile_report(&self) -> Option<&ProfileReport> {
+        self.profile.as_ref()
+    }
+
+    pub fn output_stats(&self) -> RuntimeStats {
+        self.stats.clone()
+    }
+
+    /// Most recent function calls, newest first, as `(function name, index of
+    /// the call instruction in the caller)` pairs
+    pub fn call_history(&self) -> &VecDeque<(String, usize)> {
+        &self.call_history
+    }
+
+    pub fn set_call_history_limit(&mut self, limit: usize) {
+        self.call_history_limit = limit;
+        self.call_history.truncate(limit);
+    }
+
+    /// Index arrays from 0 rather than 1 in every frame pushed from now on
+    pub fn set_zero_indexed(&mut self, zero_indexed: bool) {
+        self.zero_indexed = zero_indexed;
+    }
+
+    /// Whether frames pushed from now on index arrays from 0 rather than 1
+    pub fn zero_indexed(&self) -> bool {
+        self.zero_indexed
+    }
+
+    /// Stop execution with an error once more than `n` frames would be on the stack
+    pub fn set_max_recursion_depth(&mut self, n: usize) {
+        self.max_depth = n;
+    }
+
+    /// Stop execution with an error once `n` instructions have been executed
+    pub fn set_max_steps(&mut self, n: u64) {
+        self.max_steps = Some(n);
+    }
+
+    /// Number of instructions executed so far
+    pub fn steps_executed(&self) -> u64 {
+        self.stats.total_steps
+    }
+
+    /// Make `function` callable from pseudocode as `name`. Builtins take priority
+    /// over native functions, which take priority over functions in the program.
+    pub fn register_native_function(&mut self, name: &str, function: impl Fn(Vec<Value>) -> Result<Value, RuntimeError<'static>> + 'static) {
+        self.native_functions.register(name, function);
+    }
+
+    /// Read lines for `Input` from `input` rather than standard input
+    pub fn set_input(&mut self, input: InputSource) {
+        self.input = input;
+    }
+
+    /// Write the output of `Print` to `output` rather than standard output
+    pub fn set_output(&mut self, output: OutputSink) {
+        self.output = output;
+    }
+
+    /// Override the terminal size used by the visualizer
+    pub fn set_terminal_size(&mut self, width: usize, height: usize) {
+        self.terminal_width = width;
+        self.terminal_height = height;
+    }
+
+    pub fn terminal_size(&self) -> (usize, usize) {
+        (self.terminal_width, self.terminal_height)
+    }
+
+    pub fn current_frame(&self) -> Option<&ExecutionFrame<'file>> {
+        self.stack.last()
+    }
+
+    pub fn current_frame_mut(&mut self) -> Option<&mut ExecutionFrame<'file>> {
+        self.stack.last_mut()
+    }
+
+    /// The value returned by the function passed to `start_execution`, once it has finished
+    pub fn return_value(&self) -> Option<&Value> {
+        self.return_value.as_ref()
+    }
+
     pub fn is_done(&self) -> bool {
         self.stack.is_empty()
     }
 
+    /// Step until the function passed to `start_execution` returns, giving its return value
+    pub fn run_to_completion(&mut self) -> Result<Option<Value>, GenericError<'file>> {
+        while !self.is_done() {
+            self.single_step(false)?;
+        }
+
+        Ok(self.return_value.clone())
+    }
+
+    /// Stop `run_until_breakpoint` before instruction `instruction` of `function` is executed
+    pub fn add_breakpoint(&mut self, function: &str, instruction: usize) {
+        self.breakpoints.push((function.to_string(), instruction));
+    }
+
+    /// Call `f` with the variable's name, old value and new value whenever a
+    /// variable called `name` is assigned a different value, in any function.
+    /// Changes to the elements of an array held by the variable are not seen.
+    pub fn add_watchpoint(&mut self, name: &str, f: impl Fn(&str, &Value, &Value) + 'static) {
+        self.watchpoints.insert(name.to_string(), Box::new(f));
+    }
+
+    /// Whether the next instruction to execute has a breakpoint on it
+    pub fn at_breakpoint(&self) -> bool {
+        self.current_frame().is_some_and(|frame| frame.return_value.is_none()
+            && self.breakpoints.iter().any(|(function, instruction)| function == frame.function.name.extract_text() && *instruction == frame.line))
+    }
+
+    /// Step until a breakpoint is reached or a watched variable changes, giving
+    /// `true`, or the program finishes, giving `false`. At least one instruction
+    /// is always executed, so this can be called again to carry on from a breakpoint.
+    pub fn run_until_breakpoint(&mut self) -> Result<bool, GenericError<'file>> {
+        self.watchpoint_hit = false;
+
+        while !self.is_done() {
+            self.single_step(false)?;
+
+            if self.at_breakpoint() || std::mem::take(&mut self.watchpoint_hit) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Run the function `name` with the given arguments until it returns
+    pub fn run_function(&mut self, name: &str, arguments: Vec<Value>) -> Result<Option<Value>, GenericError<'file>> {
+        self.start_execution_with_arguments(name, arguments)?;
+        self.run_to_completion()
+    }
+
     pub fn clear(&mut self) {
         if let Some(last) = self.stack.last_mut() {
             last.clear();
@@ -122,7 +445,7 @@ impl<'file> Runtime<'file> {
 }
 
 impl<'file> ExecutionFrame<'file> {
-    pub fn new(function: VMFunction<'file>, arguments: Vec<Value>, last_lines: Vec<usize>) -> Self {
+    pub fn new(function: VMFunction<'file>, arguments: Vec<Value>, call_site_lines: Vec<usize>) -> Self {
         let arg_names = function.arguments.clone();
         let line = function.name.location.line;
         let mut result = Self {
@@ -134,21 +457,36 @@ impl<'file> ExecutionFrame<'file> {
             last_read: Vec::new(),
             return_value: None,
             passed_return: None,
-            last_lines
+            call_site_lines,
+            zero_indexed: false,
+            input: InputSource::default(),
+            output: OutputSink::default(),
+            native_functions: NativeFunctions::default()
         };
 
-        for (name, arg) in arg_names.into_iter().zip(arguments.into_iter()) {
+        for (name, arg) in arg_names.into_iter().zip(arguments) {
             result.variables.insert(name.extract_text().to_string(), arg);
         }
 
         result
     }
 
+    /// Source line of a call site, where a depth of 0 is the call which pushed
+    /// this frame, 1 is the call which pushed the caller, and so on
+    pub fn call_site_line(&self, depth: usize) -> Option<usize> {
+        self.call_site_lines.iter().rev().nth(depth).copied()
+    }
+
     pub fn clear(&mut self) {
         self.last_updated.clear();
         self.last_read.clear();
     }
 
+    /// The 1-based index recorded for an access to index `n`
+    fn tracked_index(&self, n: f64) -> usize {
+        if self.zero_indexed { n as usize + 1 } else { n as usize }
+    }
+
     pub fn load_value(&mut self, value: VMValue<'file>, report: bool) -> Result<Value, GenericError<'file>> {
         match value {
             VMValue::MemberAccess(m, a) => {
@@ -164,20 +502,20 @@ impl<'file> ExecutionFrame<'file> {
                 let m = self.load_value(*m, false)?;
                 let i = self.load_value(*i, report)?;
 
-                if let Value::Number(n) = i {
+                if let Some(n) = i.as_number() {
                     if report {
-                        self.read_variable_index(t.extract_text(), n as usize)?;
+                        self.read_variable_index(t.extract_text(), self.tracked_index(n))?;
                     }
                 }
 
-                builtin_indexing(vec![m, i]).map_err(|e| e.finish(t))
+                builtin_indexing(vec![m, i], self.zero_indexed).map_err(|e| e.finish(t))
             },
             VMValue::Value(v, _) => Ok(v),
             VMValue::Variable(v) => self.read_variable(v.extract_text(), v.get_token(), report),
         }
     }
 
-    pub fn store_value_into(&mut self, value: VMValue<'file>, to_store: Value) -> Result<(), GenericError<'file>> {
+    pub fn store_value_into(&mut self, value: VMValue<'file>, to_store: Value, on_assign: &mut dyn FnMut(&str, &Value, &Value)) -> Result<(), GenericError<'file>> {
         match value {
             VMValue::MemberAccess(m, i) => {
                 self.touch_variable(m.get_token().unwrap().extract_text())?;
@@ -191,22 +529,29 @@ impl<'file> ExecutionFrame<'file> {
                 let t = m.get_token().unwrap();
                 let m = self.load_value(*m, false)?;
                 let i = self.load_value(*i, true)?;
-                if let Value::Number(n) = i {
-                    self.touch_variable_index(t.extract_text(), n as usize)?;
+                if let Some(n) = i.as_number() {
+                    self.touch_variable_index(t.extract_text(), self.tracked_index(n))?;
                 }
 
-                builtin_mutable_indexing(vec![m, i], to_store).map_err(|e| e.finish(t))
+                builtin_mutable_indexing(vec![m, i], to_store, self.zero_indexed).map_err(|e| e.finish(t))
             },
             VMValue::Value(v, t) => Err(GenericError::tokenable_error(t, format!("unable to assign to immutable value '{}'", v))),
             VMValue::Variable(v) => {
-                self.assign_to_variable(v.extract_text(), to_store)
+                self.assign_to_variable(v.extract_text(), to_store, on_assign)
             },
         }
     }
 
-    pub fn assign_to_variable(&mut self, var_name: &str, value: Value) -> Result<(), GenericError<'file>> {
-        self.variables.insert(var_name.to_string(), value);
+    /// Assign `value` to a variable, calling `on_assign` if this changes its
+    /// value. A variable which was not yet defined counts as holding `None`.
+    pub fn assign_to_variable(&mut self, var_name: &str, value: Value, on_assign: &mut dyn FnMut(&str, &Value, &Value)) -> Result<(), GenericError<'file>> {
+        let old = self.variables.insert(var_name.to_string(), value.clone());
         self.last_updated.push(UpdateData::variable(var_name.to_string()));
+
+        if old.as_ref() != Some(&value) {
+            on_assign(var_name, old.as_ref().unwrap_or(&Value::None), &value);
+        }
+
         Ok(())
     }
 
@@ -233,7 +578,9 @@ impl<'file> ExecutionFrame<'file> {
             Ok(v.clone())
         }
         else {
-            Err(GenericError::tokenable_error(token, format!("variable '{}' is not defined", var_name)))
+            // Temporaries made by the compiler contain a '$' and are never worth suggesting
+            let suggestion = closest_match(var_name, self.variables.keys().map(|k| k.as_str()).filter(|k| !k.contains('$')));
+            Err(GenericError::tokenable_error(token, format!("variable '{}' is not defined", var_name)).suggest(suggestion))
         }
     }
 
@@ -241,33 +588,152 @@ impl<'file> ExecutionFrame<'file> {
         self.function.instructions.get(self.line)
     }
 
-    pub fn builtin_function_call(&mut self, function_name: VMVariable<'file>, arguments: Vec<Value>) -> Result<Option<Value>, GenericError<'file>> {
+    /// Call the builtin function `function_name`, giving `None` if there is no builtin with that name
+    pub fn try_builtin_function_call(&mut self, function_name: VMVariable<'file>, arguments: Vec<Value>) -> Option<Result<Value, GenericError<'file>>> {
         let name = function_name.extract_text();
 
-        if name == "Print" {
-            Ok(Some(builtin_print(arguments).map_err(|e| e.finish_maybe(function_name.get_token()))?))
+        let result = if name == "Print" {
+            builtin_print(arguments, &self.output)
         }
         else if name == "Array" {
-            Ok(Some(builtin_array(arguments).map_err(|e| e.finish_maybe(function_name.get_token()))?))
+            builtin_array(arguments)
         }
         else if name == "ArrayCreate" {
-            Ok(Some(builtin_array_create(arguments).map_err(|e| e.finish_maybe(function_name.get_token()))?))
+            builtin_array_create(arguments)
         }
         else if name == "AssertEqual" {
-            Ok(Some(builtin_assert_eq(function_name.get_token(), arguments).map_err(|e| e.finish_maybe(function_name.get_token()))?))
+            builtin_assert_eq(function_name.get_token(), arguments)
         }
         else if name == "floor" {
-            Ok(Some(builtin_floor(arguments).map_err(|e| e.finish_maybe(function_name.get_token()))?))
+            builtin_floor(arguments)
         }
         else if name == "ceil" {
-            Ok(Some(builtin_ceil(arguments).map_err(|e| e.finish_maybe(function_name.get_token()))?))
+            builtin_ceil(arguments)
         }
-        else {
-            Ok(None)
+        else if name == "FloorDiv" {
+            builtin_floor_divide(arguments)
+        }
+        else if name == "DivMod" {
+            builtin_divmod(arguments)
+        }
+        else if name == "Substring" {
+            builtin_substring(arguments)
+        }
+        else if name == "Split" {
+            builtin_split(arguments)
+        }
+        else if name == "Join" {
+            builtin_join(arguments)
+        }
+        else if name == "ModExp" {
+            builtin_mod_exp(arguments)
+        }
+        else if name == "Gcd" {
+            builtin_gcd(arguments)
+        }
+        else if name == "ExtendedGcd" {
+            builtin_extended_gcd(arguments)
+        }
+        else if name == "BitAnd" {
+            builtin_bitwise_and(arguments)
+        }
+        else if name == "BitOr" {
+            builtin_bitwise_or(arguments)
+        }
+        else if name == "BitXor" {
+            builtin_bitwise_xor(arguments)
+        }
+        else if name == "BitNot" {
+            builtin_bitwise_not(arguments)
+        }
+        else if name == "SHL" {
+            builtin_shift_left(arguments)
+        }
+        else if name == "SHR" {
+            builtin_shift_right(arguments)
+        }
+        else if name == "Map" {
+            builtin_map(arguments)
+        }
+        else if name == "Keys" {
+            builtin_keys(arguments)
+        }
+        else if name == "Values" {
+            builtin_values(arguments)
+        }
+        else if name == "Sort" {
+            builtin_sort(arguments)
+        }
+        else if name == "Append" {
+            builtin_append(arguments)
+        }
+        else if name == "Pop" {
+            builtin_pop(arguments)
+        }
+        else if name == "min" {
+            builtin_min(arguments)
+        }
+        else if name == "max" {
+            builtin_max(arguments)
         }
+        else if name == "abs" {
+            builtin_abs(arguments)
+        }
+        else if name == "sqrt" {
+            builtin_sqrt(arguments)
+        }
+        else if name == "round" {
+            builtin_round(arguments)
+        }
+        else if name == "truncate" {
+            builtin_truncate(arguments)
+        }
+        else if name == "str" {
+            builtin_str(arguments)
+        }
+        else if name == "num" {
+            builtin_num(arguments)
+        }
+        else if name == "type" {
+            builtin_type(arguments)
+        }
+        else if name == "Contains" {
+            builtin_contains(arguments)
+        }
+        else if name == "HasKey" {
+            builtin_has_key(arguments)
+        }
+        else if name == "Copy" {
+            builtin_copy(arguments)
+        }
+        else if name == "int" {
+            builtin_int(arguments)
+        }
+        else if name == "Input" {
+            builtin_input(arguments, &self.input, &self.output)
+        }
+        else if name == "Insert" {
+            builtin_insert(arguments, self.zero_indexed)
+        }
+        else if name == "Remove" {
+            builtin_remove(arguments, self.zero_indexed)
+        }
+        else if name == "Swap" {
+            builtin_swap(arguments, self.zero_indexed)
+        }
+        else if name == "Range" {
+            builtin_range(arguments, self.zero_indexed)
+        }
+        else {
+            self.native_functions.call(name, arguments)?
+        };
+
+        Some(result.map_err(|e| e.finish_maybe(function_name.get_token())))
     }
 
-    pub fn single_step(&mut self, show_instructions: bool) -> Result<Option<(VMVariable<'file>, Vec<Value>)>, GenericError<'file>> {
+    /// Execute the next instruction, calling `on_assign` with the name, old value
+    /// and new value of each variable it assigns to
+    pub fn single_step(&mut self, show_instructions: bool, on_assign: &mut dyn FnMut(&str, &Value, &Value)) -> Result<Option<(VMVariable<'file>, Vec<Value>)>, GenericError<'file>> {
         let instruction = self.function.instructions[self.line].clone();
         if show_instructions {
             println!("{}", instruction);
@@ -276,27 +742,24 @@ impl<'file> ExecutionFrame<'file> {
         match instruction.instruction_type {
             VMInstructionType::Assign(a, b) => {
                 let v = self.load_value(b, true)?;
-                self.store_value_into(a, v)?;
+                self.store_value_into(a, v, on_assign)?;
+                self.line += 1;
+            }
+            VMInstructionType::UnaryOperation(op, dest, a) => {
+                let a = self.load_value(a, true)?;
+
+                let to_store = op.apply(a).map_err(|e| e.finish_no_token())?;
+
+                self.store_value_into(dest.into(), to_store, on_assign)?;
                 self.line += 1;
             }
             VMInstructionType::BinaryOperation(op, dest, a, b) => {
                 let a = self.load_value(a, true)?;
                 let b = self.load_value(b, true)?;
 
-                let to_store = match op {
-                    crate::VMBinaryOperation::Add => builtin_add(vec![a, b]),
-                    crate::VMBinaryOperation::Subtract => builtin_sub(vec![a, b]),
-                    crate::VMBinaryOperation::Multiply => builtin_mul(vec![a, b]),
-                    crate::VMBinaryOperation::Divide => builtin_div(vec![a, b]),
-                    crate::VMBinaryOperation::LessThan => builtin_less_than(vec![a, b]),
-                    crate::VMBinaryOperation::GreaterThan => builtin_greater_than(vec![a, b]),
-                    crate::VMBinaryOperation::LessThanEqual => builtin_less_than_equal(vec![a, b]),
-                    crate::VMBinaryOperation::GreaterThanEqual => builtin_greater_than_equal(vec![a, b]),
-                    crate::VMBinaryOperation::Equality => builtin_equality(vec![a, b]),
-                    crate::VMBinaryOperation::Inequality => builtin_inequality(vec![a, b]),
-                } .map_err(|e| e.finish_no_token())?;
-
-                self.store_value_into(dest.into(),to_store)?;
+                let to_store = op.apply(a, b).map_err(|e| e.finish_no_token())?;
+
+                self.store_value_into(dest.into(),to_store, on_assign)?;
                 self.line += 1;
             }
             VMInstructionType::Return(value) => {
@@ -304,22 +767,36 @@ impl<'file> ExecutionFrame<'file> {
             }
             VMInstructionType::FunctionCall(function, dest, arguments) => {
                 if let Some(returned_value) = self.passed_return.take() {
-                    self.store_value_into(dest.into(), returned_value)?;
+                    self.store_value_into(dest.into(), returned_value, on_assign)?;
                     self.line += 1;
                 }
                 else {
                     let mut argument_values = Vec::new();
+                    let first_argument = match arguments.first() {
+                        Some(VMValue::Variable(v)) => Some(v.extract_text().to_string()),
+                        _ => None
+                    };
 
                     for arg in arguments {
                         argument_values.push(self.load_value(arg, true)?);
                     }
 
-                    if let Some(v) = self.builtin_function_call(function.clone(), argument_values.clone())? {
-                        self.store_value_into(dest.into(), v)?;
-                        self.line += 1;
-                    }
-                    else {
-                        return Ok(Some((function, argument_values)));
+                    match self.try_builtin_function_call(function.clone(), argument_values.clone()) {
+                        Some(v) => {
+                            let v = v?;
+
+                            // Highlight both exchanged cells, as if they had been assigned to
+                            if function.extract_text() == "Swap" {
+                                if let (Some(array), Some(i), Some(j)) = (&first_argument, argument_values[1].as_number(), argument_values[2].as_number()) {
+                                    self.touch_variable_index(array, self.tracked_index(i))?;
+                                    self.touch_variable_index(array, self.tracked_index(j))?;
+                                }
+                            }
+
+                            self.store_value_into(dest.into(), v, on_assign)?;
+                            self.line += 1;
+                        }
+                        None => return Ok(Some((function, argument_values))),
                     }
                 }
             }
@@ -341,6 +818,31 @@ impl<'file> ExecutionFrame<'file> {
             VMInstructionType::Goto(branch) => {
                 self.line = branch;
             }
+            VMInstructionType::LoopStep(dest, step) => {
+                let token = step.get_token();
+                let step = self.load_value(step, true)?;
+                loop_step(&step).map_err(|e| e.finish_maybe(token))?;
+
+                self.store_value_into(dest.into(), step, on_assign)?;
+                self.line += 1;
+            }
+            VMInstructionType::IterationLength(dest, array) => {
+                let token = array.get_token();
+                let array = self.load_value(array, true)?;
+                let length = iteration_length(&array).map_err(|e| e.finish_maybe(token))?;
+
+                self.store_value_into(dest.into(), Value::Integer(length as i64), on_assign)?;
+                self.line += 1;
+            }
+            VMInstructionType::IterationItem(dest, array, index) => {
+                let token = array.get_token();
+                let array = self.load_value(array, true)?;
+                let index = self.load_value(index, true)?;
+                let item = get_integer(&index, "iterate at").and_then(|i| iteration_item(&array, i as usize)).map_err(|e| e.finish_maybe(token))?;
+
+                self.store_value_into(dest.into(), item, on_assign)?;
+                self.line += 1;
+            }
         }
 
         self.last_line = Some(instruction.associated_line);
@@ -349,13 +851,144 @@ impl<'file> ExecutionFrame<'file> {
     }
 }
 
-impl<'file> std::fmt::Display for Runtime<'file> {
+impl std::fmt::Display for RuntimeStats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if let Some(last) = self.stack.last() {
-            write!(f, "{}", last)
+        writeln!(f, "Total Steps:           {}", self.total_steps)?;
+        writeln!(f, "Max Stack Depth:       {}", self.max_stack_depth)?;
+        writeln!(f, "Distinct Lines:        {}", self.distinct_lines_executed.len())?;
+        writeln!(f, "Function Calls:")?;
+
+        let mut calls = self.function_call_counts.iter().collect::<Vec<_>>();
+        calls.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        for (name, count) in calls {
+            writeln!(f, "  {:<20} {}", name, count)?;
         }
-        else {
-            write!(f, "Runtime not executing program")
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{rc::Rc, cell::RefCell};
+
+    use crate::{compile_document, parser::ParserContext, tokenizer::TokenStream};
+
+    use super::*;
+
+    const ASSIGNMENTS: &str = "Test()\n    x = 1\n    x = 1\n    y = 5\n    x = 2\n    return x\n";
+
+    fn runtime(source: &'static str) -> Runtime<'static> {
+        let mut tokens = TokenStream::from_source(source, "test.ps");
+        let (parse_tree, _) = ParserContext::new(&mut tokens).parse_document().unwrap();
+
+        Runtime::load(compile_document(parse_tree).unwrap()).unwrap()
+    }
+
+    /// Watch `name`, giving every `(old, new)` pair the watchpoint was called with
+    fn watch(runtime: &mut Runtime<'_>, name: &str) -> Rc<RefCell<Vec<(Value, Value)>>> {
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let seen = changes.clone();
+        runtime.add_watchpoint(name, move |_, old, new| seen.borrow_mut().push((old.clone(), new.clone())));
+
+        changes
+    }
+
+    #[test]
+    fn loading_a_malformed_function_fails() {
+        let mut function = VMFunction::new(Token::synthetic("Test"), vec![]);
+        function.add_instruction_type(0, VMInstructionType::Goto(5));
+
+        match Runtime::load(vec![function]) {
+            Err(e) => assert!(e.to_string().contains("instruction 0 jumps to 5, past the end of the function"), "{}", e),
+            Ok(_) => panic!("a jump past the end of the function was loaded")
+        }
+    }
+
+    #[test]
+    fn watchpoint_is_given_old_and_new_values() {
+        let mut runtime = runtime(ASSIGNMENTS);
+        let changes = watch(&mut runtime, "x");
+
+        assert_eq!(runtime.run_function("Test", vec![]).unwrap(), Some(Value::Integer(2)));
+
+        // The second `x = 1` leaves the value alone, so it is not reported
+        assert_eq!(*changes.borrow(), [(Value::None, Value::Integer(1)), (Value::Integer(1), Value::Integer(2))]);
+    }
+
+    #[test]
+    fn watchpoint_ignores_other_variables() {
+        let mut runtime = runtime(ASSIGNMENTS);
+        let changes = watch(&mut runtime, "z");
+
+        runtime.run_function("Test", vec![]).unwrap();
+        assert!(changes.borrow().is_empty());
+    }
+
+    #[test]
+    fn run_until_breakpoint_stops_after_a_watched_change() {
+        let mut runtime = runtime(ASSIGNMENTS);
+        let changes = watch(&mut runtime, "x");
+        runtime.start_execution("Test").unwrap();
+
+        let x = |runtime: &Runtime<'_>| runtime.current_frame().unwrap().variables.get("x").cloned();
+
+        assert!(runtime.run_until_breakpoint().unwrap());
+        assert_eq!(x(&runtime), Some(Value::Integer(1)));
+        assert_eq!(changes.borrow().len(), 1);
+
+        // Runs through the equal re-assignment and `y = 5` without stopping
+        assert!(runtime.run_until_breakpoint().unwrap());
+        assert_eq!(x(&runtime), Some(Value::Integer(2)));
+        assert_eq!(runtime.current_frame().unwrap().variables.get("y"), Some(&Value::Integer(5)));
+        assert_eq!(changes.borrow().len(), 2);
+
+        assert!(!runtime.run_until_breakpoint().unwrap());
+        assert_eq!(runtime.return_value(), Some(&Value::Integer(2)));
+    }
+
+    #[test]
+    fn misspelled_names_suggest_the_closest() {
+        let mut runtime = runtime("Test()\n    total = 1\n    Prnit(total)\n\nSum()\n    total = 1\n    return totl\n");
+
+        for (function, suggestion) in [("Test", "did you mean 'Print'?"), ("Sum", "did you mean 'total'?")] {
+            let error = runtime.run_function(function, vec![]).unwrap_err();
+            assert!(error.to_string().contains(suggestion), "{}", error);
+        }
+    }
+
+    #[test]
+    fn swap_highlights_both_cells() {
+        let mut runtime = runtime("Test()\n    A = Array(3, 1, 2)\n    Swap(A, 1, 3)\n    return A\n");
+        runtime.start_execution("Test").unwrap();
+
+        let swapped = |runtime: &Runtime<'_>| runtime.current_frame().unwrap().variables.get("A").is_some_and(|a| a.to_string() == "[2, 1, 3]");
+        while !swapped(&runtime) {
+            runtime.single_step(false).unwrap();
+        }
+
+        let updated = &runtime.current_frame().unwrap().last_updated;
+        assert!(updated.contains(&UpdateData::indexed("A".to_string(), 1)), "{:?}", updated);
+        assert!(updated.contains(&UpdateData::indexed("A".to_string(), 3)), "{:?}", updated);
+    }
+
+    #[test]
+    fn call_site_lines_of_recursive_calls() {
+        let mut runtime = runtime("Test()\n    return Down(2)\n\nDown(n)\n    if n == 0\n        return 0\n    x = Down(n - 1)\n    return x\n");
+        runtime.start_execution("Test").unwrap();
+
+        while runtime.stack.len() < 4 {
+            runtime.single_step(false).unwrap();
         }
+
+        // Zero based lines: `Test` calls on line 1, each `Down` recurses on line 6
+        assert_eq!(runtime.call_sites(), [1, 6, 6]);
+
+        let frame = runtime.current_frame().unwrap();
+        assert_eq!(frame.call_site_lines, [1, 6, 6]);
+        assert_eq!(frame.call_site_line(0), Some(6));
+        assert_eq!(frame.call_site_line(2), Some(1));
+        assert_eq!(frame.call_site_line(3), None);
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,19 @@
+mod common;
+
+use common::run_both;
+
+#[test]
+fn empty_if_body_before_else() {
+    let source = "\
+Test()
+    if True
+    else
+        Print(\"not taken\")
+    if False
+    else
+        Print(\"taken\")
+    Print(\"after\")
+";
+
+    assert_eq!(run_both(source), "taken\nafter\n");
+}
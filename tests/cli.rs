@@ -0,0 +1,67 @@
+use std::process::Command;
+
+/// Runs the pseudocode binary from the root of the repository, giving what it printed
+fn pseudocode(arguments: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_pseudocode"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .arg("--no-color")
+        .args(arguments)
+        .output()
+        .expect("unable to run pseudocode");
+
+    assert!(output.status.success(), "pseudocode {:?} failed: {}", arguments, String::from_utf8_lossy(&output.stderr));
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn tokens_as_json() {
+    let expected = r#"[
+  {"type": "identifier", "text": "Test", "line": 1, "column": 1, "filename": "tests/data/tokens.ps"},
+  {"type": "symbol", "text": "(", "line": 1, "column": 5, "filename": "tests/data/tokens.ps"},
+  {"type": "symbol", "text": ")", "line": 1, "column": 6, "filename": "tests/data/tokens.ps"},
+  {"type": "indentation", "text": "    ", "line": 2, "column": 1, "filename": "tests/data/tokens.ps", "indent_len": 4},
+  {"type": "identifier", "text": "Print", "line": 2, "column": 5, "filename": "tests/data/tokens.ps"},
+  {"type": "symbol", "text": "(", "line": 2, "column": 10, "filename": "tests/data/tokens.ps"},
+  {"type": "string", "text": "\"hi\"", "line": 2, "column": 11, "filename": "tests/data/tokens.ps"},
+  {"type": "symbol", "text": ",", "line": 2, "column": 15, "filename": "tests/data/tokens.ps"},
+  {"type": "number", "text": "1.5", "line": 2, "column": 17, "filename": "tests/data/tokens.ps"},
+  {"type": "symbol", "text": ")", "line": 2, "column": 20, "filename": "tests/data/tokens.ps"},
+  {"type": "eof", "text": "", "line": 3, "column": 1, "filename": "tests/data/tokens.ps"}
+]
+"#;
+
+    assert_eq!(pseudocode(&["tokens", "--format", "json", "tests/data/tokens.ps"]), expected);
+}
+
+#[test]
+fn tokenize_json_matches_tokens() {
+    assert_eq!(pseudocode(&["tokenize", "--json", "tests/data/tokens.ps"]), pseudocode(&["tokens", "--format", "json", "tests/data/tokens.ps"]));
+}
+
+#[test]
+fn reformat_keeps_comments() {
+    let expected = "\
+// Sorting helpers
+// @type A: Array
+Sort(A)
+    // walk the array
+    for i = 2 to A.length // outer loop
+        key = A[i]
+
+        // shift larger elements
+        j = i - 1
+        while j > 0 and A[j] > key
+            A[j + 1] = A[j]
+            j = j - 1
+        A[j + 1] = key // place it
+    return A
+
+// entry point
+Test()
+    Print(Sort(Array(3, 1, 2)))
+// trailing
+";
+
+    assert_eq!(pseudocode(&["reformat", "tests/data/reformat.ps"]), expected);
+}
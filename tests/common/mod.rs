@@ -0,0 +1,65 @@
+#![allow(dead_code)]
+
+use std::{rc::Rc, cell::RefCell, io::Write};
+
+use pseudocode::{PseudocodeEngine, compile_document, interpreter::{OutputSink, Value},
+                 parser::ParserContext, tokenizer::TokenStream, virtualmachine::Runtime};
+
+/// Collects everything written to it, clones share the same buffer so the
+/// text can be read back after the program has run
+#[derive(Clone, Default)]
+pub struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    pub fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+/// Runs `Test()` in the tree walking interpreter, giving what it returned and printed
+pub fn interpret(source: &str) -> (Value, String) {
+    let output = Captured::default();
+    let value = PseudocodeEngine::new()
+        .with_source(source, "test.ps")
+        .set_stdout(output.clone())
+        .execute("Test", vec![])
+        .unwrap_or_else(|e| panic!("{}", e.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")));
+
+    (value, output.text())
+}
+
+/// Compiles `Test()` and runs it in the virtual machine, giving what it returned and printed
+pub fn run_vm(source: &str) -> (Option<Value>, String) {
+    let mut tokens = TokenStream::from_source(source, "test.ps");
+    let (parse_tree, _) = ParserContext::new(&mut tokens).parse_document()
+        .unwrap_or_else(|e| panic!("{}", e.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")));
+
+    let output = Captured::default();
+    let mut runtime = Runtime::load(compile_document(parse_tree).unwrap_or_else(|e| panic!("{}", e))).unwrap_or_else(|e| panic!("{}", e));
+    runtime.set_output(OutputSink::new(output.clone()));
+
+    let value = runtime.run_function("Test", vec![]).unwrap_or_else(|e| panic!("{}", e));
+
+    (value, output.text())
+}
+
+/// Runs `Test()` in both the interpreter and the virtual machine, checking
+/// they print the same text, and gives that text
+pub fn run_both(source: &str) -> String {
+    let (_, interpreted) = interpret(source);
+    let (_, compiled) = run_vm(source);
+
+    assert_eq!(interpreted, compiled, "the interpreter and virtual machine printed different output");
+
+    interpreted
+}
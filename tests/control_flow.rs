@@ -0,0 +1,68 @@
+mod common;
+
+use common::{interpret, run_vm};
+
+use pseudocode::interpreter::Value;
+
+#[test]
+fn return_from_if_inside_for() {
+    let source = "\
+Find(A, x)
+    for i = 1 to A.length
+        if A[i] == x
+            return i
+        Print(i)
+    return -1
+
+Test()
+    A = Array(4, 8, 15, 16)
+    Print(Find(A, 8))
+    return Find(A, 23)
+";
+
+    let (value, printed) = interpret(source);
+    assert_eq!(value, Value::Integer(-1));
+    assert_eq!(printed, "1\n2\n1\n2\n3\n4\n");
+
+    let (value, printed) = run_vm(source);
+    assert_eq!(value, Some(Value::Integer(-1)));
+    assert_eq!(printed, "1\n2\n1\n2\n3\n4\n");
+}
+
+#[test]
+fn repeat_runs_its_body_at_least_once() {
+    let source = "\
+Test()
+    i = 10
+    repeat
+        Print(i)
+        i = i + 1
+    until True
+    return i
+";
+
+    let (value, printed) = interpret(source);
+    assert_eq!(value, Value::Integer(11));
+    assert_eq!(printed, "10\n");
+
+    let (value, printed) = run_vm(source);
+    assert_eq!(value, Some(Value::Integer(11)));
+    assert_eq!(printed, "10\n");
+}
+
+#[test]
+fn repeat_until_condition_holds() {
+    let source = "\
+Test()
+    i = 1
+    total = 0
+    repeat
+        total = total + i
+        i = i + 1
+    until i > 4
+    return total
+";
+
+    assert_eq!(interpret(source).0, Value::Integer(10));
+    assert_eq!(run_vm(source).0, Some(Value::Integer(10)));
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::Captured;
+use pseudocode::{PseudocodeEngine, interpreter::{RuntimeError, Value}};
+
+const ADD: &str = "Add(a, b)\n    return a + b\n";
+
+fn messages(errors: Vec<pseudocode::error::GenericError>) -> String {
+    errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")
+}
+
+#[test]
+fn execute_passes_arguments_and_returns_the_result() {
+    let engine = PseudocodeEngine::new().with_source(ADD, "add.ps");
+
+    assert_eq!(engine.execute("Add", vec![Value::Integer(2), Value::Integer(3)]).unwrap(), Value::Integer(5));
+    assert_eq!(engine.execute("Add", vec![Value::Integer(4), Value::Integer(4)]).unwrap(), Value::Integer(8));
+}
+
+#[test]
+fn print_goes_to_the_given_writer() {
+    let output = Captured::default();
+    PseudocodeEngine::new()
+        .with_source("Test()\n    Print(\"hello\")\n    Print(1 + 1)\n", "print.ps")
+        .set_stdout(output.clone())
+        .execute("Test", vec![])
+        .unwrap();
+
+    assert_eq!(output.text(), "hello\n2\n");
+}
+
+#[test]
+fn max_steps_stops_an_infinite_loop() {
+    let errors = PseudocodeEngine::new()
+        .with_source("Test()\n    while True\n        x = 1\n", "loop.ps")
+        .with_max_steps(50)
+        .execute("Test", vec![])
+        .unwrap_err();
+
+    assert!(messages(errors).contains("exceeded the limit of 50 steps"));
+}
+
+#[test]
+fn deep_recursion_is_an_error() {
+    let source = "Depth(n)\n    if n == 0\n        return 0\n    return Depth(n - 1) + 1\n";
+    let engine = PseudocodeEngine::new().with_source(source, "depth.ps");
+
+    // The default limit is reached without overflowing the small stack of a test thread
+    let errors = engine.execute("Depth", vec![Value::Integer(5000)]).unwrap_err();
+    assert!(messages(errors).contains("maximum recursion depth 1000 exceeded"));
+
+    let engine = engine.with_max_recursion_depth(20);
+    assert_eq!(engine.execute("Depth", vec![Value::Integer(19)]).unwrap(), Value::Integer(19));
+
+    let errors = engine.execute("Depth", vec![Value::Integer(20)]).unwrap_err();
+    assert!(messages(errors).contains("maximum recursion depth 20 exceeded"));
+}
+
+#[test]
+fn native_functions_are_callable_from_the_program() {
+    let value = PseudocodeEngine::new()
+        .with_source("Test()\n    return Double(Double(5))\n", "native.ps")
+        .with_native_function("Double", |arguments| match arguments.as_slice() {
+            [Value::Integer(i)] => Ok(Value::Integer(i * 2)),
+            _ => Err(RuntimeError::MessageError("Double takes one integer".to_string()))
+        })
+        .execute("Test", vec![])
+        .unwrap();
+
+    assert_eq!(value, Value::Integer(20));
+}
+
+#[test]
+fn clones_do_not_share_later_native_functions() {
+    let engine = PseudocodeEngine::new()
+        .with_source("Test()\n    return Seven()\n", "clone.ps");
+    let with_seven = engine.clone()
+        .with_native_function("Seven", |_| Ok(Value::Integer(7)));
+
+    assert_eq!(with_seven.execute("Test", vec![]).unwrap(), Value::Integer(7));
+    assert!(engine.execute("Test", vec![]).is_err());
+}
+
+#[test]
+fn missing_source_and_functions_are_errors() {
+    let errors = PseudocodeEngine::new().execute("Test", vec![]).unwrap_err();
+    assert!(messages(errors).contains("no source was given to the engine"));
+
+    let errors = PseudocodeEngine::new()
+        .with_source(ADD, "add.ps")
+        .execute("Missing", vec![])
+        .unwrap_err();
+    assert!(messages(errors).contains("function 'Missing' not defined"));
+}
+
+#[test]
+fn parse_errors_are_returned() {
+    let engine = PseudocodeEngine::new().with_source("Test()\n    x = (1 +\n", "broken.ps");
+
+    assert!(!engine.execute("Test", vec![]).unwrap_err().is_empty());
+    assert!(!engine.check().unwrap_err().is_empty());
+}
+
+#[test]
+fn check_gives_parser_warnings() {
+    let engine = PseudocodeEngine::new()
+        .with_source("// @type c: Integer\nAdd(a, b)\n    return a + b\n", "hint.ps");
+
+    let warnings = engine.check().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].to_string().contains("type hint given for unknown argument 'c'"));
+    assert!(PseudocodeEngine::new().with_source(ADD, "add.ps").check().unwrap().is_empty());
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use common::run_both;
+
+#[test]
+fn xor_in_both_backends() {
+    let source = "\
+Test()
+    Print(True xor True)
+    Print(True xor False)
+    Print(False xor True)
+    Print(False xor False)
+";
+
+    assert_eq!(run_both(source), "false\ntrue\ntrue\nfalse\n");
+}
+
+#[test]
+fn string_builtins_in_both_backends() {
+    let source = "\
+Test()
+    parts = Split(\"a,b,c\", \",\")
+    Print(parts.length)
+    Print(Join(parts, \"-\"))
+    Print(Substring(\"hello\", 2, 3))
+";
+
+    assert_eq!(run_both(source), "3\na-b-c\nell\n");
+}
+
+#[test]
+fn number_theory_builtins_in_both_backends() {
+    let source = "\
+Test()
+    Print(ModExp(3, 12, 17))
+    Print(ModExp(2, 10, 1000))
+    Print(Gcd(30, 21))
+    Print(ExtendedGcd(30, 21))
+";
+
+    assert_eq!(run_both(source), "4\n24\n3\n[3, -2, 3]\n");
+}
+
+#[test]
+fn bitwise_builtins_in_both_backends() {
+    let source = "\
+Test()
+    Print(BitAnd(12, 10))
+    Print(BitOr(12, 10))
+    Print(BitXor(12, 10))
+    Print(BitNot(12))
+    Print(SHL(1, 3))
+    Print(SHR(12, 2))
+";
+
+    assert_eq!(run_both(source), "8\n14\n6\n-13\n8\n3\n");
+}
+
+#[test]
+fn ternary_picks_one_branch() {
+    let source = "\
+Sign(x)
+    y = if x > 0 then 1 else -1
+    return y
+
+Test()
+    Print(Sign(5))
+    Print(Sign(-5))
+    Print(if True then \"yes\" else Missing())
+";
+
+    assert_eq!(run_both(source), "1\n-1\nyes\n");
+}
+
+#[test]
+fn compound_assignment_evaluates_target_once() {
+    let source = "\
+Idx(i)
+    Print(\"idx\")
+    return i
+
+Test()
+    A = Array(5, 6)
+    A[Idx(1)] += 1
+    A[Idx(2)] *= A[1]
+    Print(A)
+";
+
+    assert_eq!(run_both(source), "idx\nidx\n[6, 36]\n");
+}
+
+#[test]
+fn min_and_max_of_many_values() {
+    let source = "\
+Test()
+    Print(max(3, 1, 4, 1, 5, 9))
+    Print(min(3, 1, 4, 1, 5, 9))
+    Print(max(2, 2.5))
+";
+
+    assert_eq!(run_both(source), "9\n1\n2.5\n");
+}
+
+#[test]
+fn math_builtins_in_both_backends() {
+    let source = "\
+Test()
+    Print(abs(-5))
+    Print(sqrt(4))
+    Print(round(2.5))
+    Print(truncate(-2.7))
+";
+
+    assert_eq!(run_both(source), "5\n2\n3\n-2\n");
+}
+
+#[test]
+fn logical_operators_short_circuit_on_variables() {
+    let source = "\
+Test()
+    found = False
+    if found and missing
+        Print(\"and read the right side\")
+    found = True
+    if found or missing
+        Print(\"or skipped the right side\")
+    if found and \"not a boolean\" == \"not a boolean\"
+        Print(\"and read the right side\")
+    Print(True and False, True or False)
+";
+    assert_eq!(run_both(source), "or skipped the right side\nand read the right side\nfalse, true\n");
+}
+
+#[test]
+fn range_matches_the_equivalent_array() {
+    let source = "\
+Test()
+    Print(Range(3) == Array(1, 2, 3))
+    Print(Range(2, 4))
+";
+    assert_eq!(run_both(source), "true\n[2, 3, 4]\n");
+}